@@ -0,0 +1,86 @@
+//! Filter queried names, e.g., to block ads and trackers like Pi-hole.
+
+use std::fmt;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::sync::Arc;
+
+use log::debug;
+
+use crate::{Error, Message, RecordClass, RecordData, RecordType, ResourceRecord};
+
+/// What a blocked name is answered with, set with [`ResolverBuilder::blocklist`](crate::ResolverBuilder::blocklist).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BlockAction {
+    /// Fail with [`Error::NxDomain`] as if the name doesn't exist.
+    NxDomain,
+    /// Answer A with `0.0.0.0` and AAAA with `::`, so connections go nowhere.
+    /// Other types are answered without any record.
+    Sinkhole,
+}
+
+type Predicate = dyn Fn(&str, &RecordType) -> bool + Send + Sync;
+
+/// Names to answer locally without going to the network.
+#[derive(Clone)]
+pub(crate) struct Blocklist {
+    predicate: Arc<Predicate>,
+    action: BlockAction,
+}
+
+impl Blocklist {
+    pub(crate) fn new(predicate: Arc<Predicate>, action: BlockAction) -> Self {
+        Self { predicate, action }
+    }
+
+    /// The synthesized result if the name is blocked, or `None` to resolve it as usual.
+    pub(crate) fn check(
+        &self,
+        domain: &str,
+        record_type: &RecordType,
+    ) -> Option<Result<Message, Error>> {
+        // Names are case-insensitive, so a blocked name can't slip through in capitals.
+        if !(self.predicate)(&domain.to_ascii_lowercase(), record_type) {
+            return None;
+        }
+
+        debug!("{domain} is blocked, answered with {:?}", self.action);
+        let result = match self.action {
+            BlockAction::NxDomain => Err(Error::NxDomain {
+                authorities: Vec::new(),
+            }),
+            BlockAction::Sinkhole => Ok(Self::sinkhole(domain, record_type)),
+        };
+
+        Some(result)
+    }
+
+    fn sinkhole(domain: &str, record_type: &RecordType) -> Message {
+        let r_data = match record_type {
+            RecordType::A => Some(RecordData::A(Ipv4Addr::UNSPECIFIED)),
+            RecordType::AAAA => Some(RecordData::AAAA(Ipv6Addr::UNSPECIFIED)),
+            _ => None,
+        };
+        let answers = r_data
+            .map(|r_data| ResourceRecord {
+                name: domain.into(),
+                r_type: Some(*record_type),
+                r_class: RecordClass::IN,
+                // Not to be cached, in case the name is unblocked later.
+                ttl: 0,
+                rd_length: r_data.to_bytes().len() as u16,
+                r_data,
+            })
+            .into_iter()
+            .collect();
+
+        Message::new_query(domain, record_type).into_response(answers)
+    }
+}
+
+impl fmt::Debug for Blocklist {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Blocklist")
+            .field("action", &self.action)
+            .finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,437 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::{Condvar, Mutex};
+use std::thread::{self, ThreadId};
+use std::time::{Duration, Instant};
+
+use crate::{ClientSubnet, RecordClass, RecordType, ResourceRecord};
+
+/// Domain, type and class of a question, and the options it's asked with.
+type Key = (String, u16, u16, QueryOptions);
+
+/// Options of a query that change what name servers answer with, e.g., DNSSEC records for the DO bit,
+/// or addresses close to the client subnet, or how the answer is parsed. Queries with different options don't share
+/// answers.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub(crate) struct QueryOptions {
+    pub(crate) dnssec_ok: bool,
+    pub(crate) checking_disabled: bool,
+    pub(crate) client_subnet: Option<ClientSubnet>,
+    pub(crate) keep_unknown_records: bool,
+}
+
+/// How many answers, and as many names known not to exist, a cache keeps by default.
+const DEFAULT_CAPACITY: usize = 10_000;
+
+/// Answers resolved so far, which can be shared by resolvers across threads.
+///
+/// Resolvers cloned from one another share the same cache. To share it between resolvers built separately,
+/// pass it to [`ResolverBuilder::cache`](crate::ResolverBuilder::cache). Answers are kept apart by the DO and CD
+/// bits and the client subnet they are asked with, and whether records of unknown types are kept, so resolvers
+/// configured differently don't get each other's.
+///
+/// When several threads look up the same question at the same time, only one of them resolves it,
+/// and the others wait for its answer. Async queries only read and fill the cache, as waiting would block the
+/// thread, so concurrent ones of the same question are all resolved.
+///
+/// Once the cache is full, expired entries are dropped to make room, or the one expiring soonest if there is none.
+#[derive(Debug)]
+pub struct Cache {
+    state: Mutex<State>,
+    resolved: Condvar,
+    capacity: usize,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// Responses in wire format and when they expire.
+    answers: HashMap<Key, (Vec<u8>, Instant)>,
+    /// Names known not to exist in a class with the authority section saying so, and when they expire.
+    /// See [RFC 2308, 5 - Caching Negative Answers](https://www.rfc-editor.org/rfc/rfc2308.html).
+    nonexistent: HashMap<(String, u16, QueryOptions), (Vec<ResourceRecord>, Instant)>,
+    /// Questions being resolved and by which thread.
+    in_flight: HashMap<Key, ThreadId>,
+}
+
+/// What to do with a question, see [`Cache::lookup`].
+pub(crate) enum Lookup<'a> {
+    /// The response in wire format.
+    Cached(Vec<u8>),
+    /// The authority section of the response telling the name doesn't exist, usually with the SOA of the zone.
+    Nonexistent(Vec<ResourceRecord>),
+    /// Nobody has the answer yet. Others asking the same question wait until this is dropped.
+    Resolve(InFlight<'a>),
+}
+
+pub(crate) struct InFlight<'a> {
+    cache: &'a Cache,
+    key: Option<Key>,
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        if let Some(key) = self.key.take() {
+            self.cache.state.lock().unwrap().in_flight.remove(&key);
+            self.cache.resolved.notify_all();
+        }
+    }
+}
+
+impl Default for Cache {
+    fn default() -> Self {
+        Self::with_capacity(DEFAULT_CAPACITY)
+    }
+}
+
+impl Cache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cache at most `capacity` answers, and as many names known not to exist. Nothing is cached with `0`.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            state: Mutex::default(),
+            resolved: Condvar::new(),
+            capacity,
+        }
+    }
+
+    /// Look up a question. If another thread is resolving the same question, wait for it to finish first,
+    /// but not past `deadline`. The question is resolved again then, without others waiting for it.
+    pub(crate) fn lookup(
+        &self,
+        domain: &str,
+        record_type: &RecordType,
+        record_class: &RecordClass,
+        options: &QueryOptions,
+        deadline: Instant,
+    ) -> Lookup<'_> {
+        let key = Self::key(domain, record_type, record_class, options);
+        let current = thread::current().id();
+        let mut state = self.state.lock().unwrap();
+
+        loop {
+            let now = Instant::now();
+            if let Some(cached) = Self::cached(&mut state, &key, now) {
+                return cached;
+            }
+
+            match state.in_flight.get(&key) {
+                // Resolving a name server domain might lead back to the same question, e.g., in a referral loop.
+                // Waiting for ourselves never ends, so resolve it again and let the referral depth stop it.
+                Some(owner) if *owner == current => {
+                    return Lookup::Resolve(InFlight {
+                        cache: self,
+                        key: None,
+                    });
+                }
+                Some(_) if now >= deadline => {
+                    return Lookup::Resolve(InFlight {
+                        cache: self,
+                        key: None,
+                    });
+                }
+                Some(_) => state = self.resolved.wait_timeout(state, deadline - now).unwrap().0,
+                None => {
+                    state.in_flight.insert(key.clone(), current);
+                    return Lookup::Resolve(InFlight {
+                        cache: self,
+                        key: Some(key),
+                    });
+                }
+            }
+        }
+    }
+
+    /// Look up a question without waiting for others resolving it. Returns `None` if it's not in the cache.
+    #[cfg(feature = "tokio")]
+    pub(crate) fn peek(
+        &self,
+        domain: &str,
+        record_type: &RecordType,
+        record_class: &RecordClass,
+        options: &QueryOptions,
+    ) -> Option<Lookup<'_>> {
+        let key = Self::key(domain, record_type, record_class, options);
+        Self::cached(&mut self.state.lock().unwrap(), &key, Instant::now())
+    }
+
+    fn cached(state: &mut State, key: &Key, now: Instant) -> Option<Lookup<'static>> {
+        if let Some(response) = Self::get(&mut state.answers, key, now) {
+            return Some(Lookup::Cached(response));
+        }
+        let name = (key.0.clone(), key.2, key.3);
+        Self::get(&mut state.nonexistent, &name, now).map(Lookup::Nonexistent)
+    }
+
+    pub(crate) fn insert_answer(
+        &self,
+        domain: &str,
+        record_type: &RecordType,
+        record_class: &RecordClass,
+        options: &QueryOptions,
+        response: Vec<u8>,
+        ttl: Duration,
+    ) {
+        let key = Self::key(domain, record_type, record_class, options);
+        let answers = &mut self.state.lock().unwrap().answers;
+        self.insert(answers, key, response, ttl);
+    }
+
+    pub(crate) fn insert_nonexistent(
+        &self,
+        domain: &str,
+        record_class: &RecordClass,
+        options: &QueryOptions,
+        authorities: Vec<ResourceRecord>,
+        ttl: Duration,
+    ) {
+        let name = (Self::name(domain), record_class.to_u16(), *options);
+        let nonexistent = &mut self.state.lock().unwrap().nonexistent;
+        self.insert(nonexistent, name, authorities, ttl);
+    }
+
+    /// Entries are only checked for expiry when they are looked up, or when the cache is full.
+    fn get<K: Eq + Hash, V: Clone>(
+        entries: &mut HashMap<K, (V, Instant)>,
+        key: &K,
+        now: Instant,
+    ) -> Option<V> {
+        match entries.get(key) {
+            Some((value, expires_at)) if *expires_at > now => Some(value.clone()),
+            Some(_) => {
+                entries.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn insert<K: Clone + Eq + Hash, V>(
+        &self,
+        entries: &mut HashMap<K, (V, Instant)>,
+        key: K,
+        value: V,
+        ttl: Duration,
+    ) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let now = Instant::now();
+        if entries.len() >= self.capacity && !entries.contains_key(&key) {
+            entries.retain(|_, (_, expires_at)| *expires_at > now);
+        }
+        if entries.len() >= self.capacity && !entries.contains_key(&key) {
+            let soonest = entries
+                .iter()
+                .min_by_key(|(_, (_, expires_at))| *expires_at)
+                .map(|(key, _)| key.clone());
+            if let Some(soonest) = soonest {
+                entries.remove(&soonest);
+            }
+        }
+
+        entries.insert(key, (value, now + ttl));
+    }
+
+    fn key(
+        domain: &str,
+        record_type: &RecordType,
+        record_class: &RecordClass,
+        options: &QueryOptions,
+    ) -> Key {
+        (
+            Self::name(domain),
+            record_type.to_u16(),
+            record_class.to_u16(),
+            *options,
+        )
+    }
+
+    /// Domain names are case-insensitive, and the trailing dot of FQDN doesn't matter.
+    fn name(domain: &str) -> String {
+        domain.trim_end_matches('.').to_ascii_lowercase()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lookup<'a>(cache: &'a Cache, domain: &str) -> Lookup<'a> {
+        let deadline = Instant::now() + Duration::from_secs(1);
+        cache.lookup(
+            domain,
+            &RecordType::A,
+            &RecordClass::IN,
+            &QueryOptions::default(),
+            deadline,
+        )
+    }
+
+    #[test]
+    fn test_cache_expires() {
+        let cache = Cache::new();
+        cache.insert_nonexistent(
+            "Missing.wtcx.dev.",
+            &RecordClass::IN,
+            &QueryOptions::default(),
+            vec![],
+            Duration::from_secs(60),
+        );
+        cache.insert_nonexistent(
+            "expired.wtcx.dev",
+            &RecordClass::IN,
+            &QueryOptions::default(),
+            vec![],
+            Duration::ZERO,
+        );
+        cache.insert_answer(
+            "blog.wtcx.dev",
+            &RecordType::A,
+            &RecordClass::IN,
+            &QueryOptions::default(),
+            vec![1, 2, 3],
+            Duration::from_secs(60),
+        );
+
+        assert!(matches!(
+            lookup(&cache, "missing.wtcx.dev"),
+            Lookup::Nonexistent(_)
+        ));
+        assert!(matches!(
+            lookup(&cache, "BLOG.wtcx.dev"),
+            Lookup::Cached(response) if response == [1, 2, 3]
+        ));
+        assert!(matches!(
+            lookup(&cache, "expired.wtcx.dev"),
+            Lookup::Resolve(_)
+        ));
+        assert!(matches!(
+            cache.lookup(
+                "blog.wtcx.dev",
+                &RecordType::AAAA,
+                &RecordClass::IN,
+                &QueryOptions::default(),
+                Instant::now()
+            ),
+            Lookup::Resolve(_)
+        ));
+    }
+
+    #[test]
+    fn test_nonexistent_per_class() {
+        let cache = Cache::new();
+        cache.insert_nonexistent(
+            "version.wtcx.dev",
+            &RecordClass::CH,
+            &QueryOptions::default(),
+            vec![],
+            Duration::from_secs(60),
+        );
+
+        assert!(matches!(
+            lookup(&cache, "version.wtcx.dev"),
+            Lookup::Resolve(_)
+        ));
+        assert!(matches!(
+            cache.lookup(
+                "version.wtcx.dev",
+                &RecordType::TXT,
+                &RecordClass::CH,
+                &QueryOptions::default(),
+                Instant::now()
+            ),
+            Lookup::Nonexistent(_)
+        ));
+    }
+
+    #[test]
+    fn test_cache_capacity() {
+        let cache = Cache::with_capacity(2);
+        for (domain, ttl) in [("a.wtcx.dev", 60), ("b.wtcx.dev", 30), ("c.wtcx.dev", 90)] {
+            cache.insert_answer(
+                domain,
+                &RecordType::A,
+                &RecordClass::IN,
+                &QueryOptions::default(),
+                vec![1, 2, 3],
+                Duration::from_secs(ttl),
+            );
+        }
+
+        // The one expiring soonest makes room for the last one.
+        assert!(matches!(lookup(&cache, "a.wtcx.dev"), Lookup::Cached(_)));
+        assert!(matches!(lookup(&cache, "b.wtcx.dev"), Lookup::Resolve(_)));
+        assert!(matches!(lookup(&cache, "c.wtcx.dev"), Lookup::Cached(_)));
+    }
+
+    #[test]
+    fn test_wait_until_deadline() {
+        let cache = Cache::new();
+        let _in_flight = lookup(&cache, "blog.wtcx.dev");
+
+        // Another thread gives up waiting and resolves it on its own.
+        thread::scope(|scope| {
+            let waiting = scope.spawn(|| {
+                let deadline = Instant::now() + Duration::from_millis(50);
+                let lookup = cache.lookup(
+                    "blog.wtcx.dev",
+                    &RecordType::A,
+                    &RecordClass::IN,
+                    &QueryOptions::default(),
+                    deadline,
+                );
+                matches!(lookup, Lookup::Resolve(InFlight { key: None, .. }))
+            });
+            assert!(waiting.join().unwrap());
+        });
+    }
+
+    #[test]
+    fn test_cache_per_options() {
+        let cache = Cache::new();
+        let dnssec_ok = QueryOptions {
+            dnssec_ok: true,
+            ..QueryOptions::default()
+        };
+        cache.insert_answer(
+            "blog.wtcx.dev",
+            &RecordType::A,
+            &RecordClass::IN,
+            &dnssec_ok,
+            vec![1, 2, 3],
+            Duration::from_secs(60),
+        );
+        cache.insert_nonexistent(
+            "missing.wtcx.dev",
+            &RecordClass::IN,
+            &dnssec_ok,
+            vec![],
+            Duration::from_secs(60),
+        );
+
+        // Answers with DNSSEC records aren't for queries without the DO bit, and vice versa.
+        assert!(matches!(
+            lookup(&cache, "blog.wtcx.dev"),
+            Lookup::Resolve(_)
+        ));
+        assert!(matches!(
+            lookup(&cache, "missing.wtcx.dev"),
+            Lookup::Resolve(_)
+        ));
+        let deadline = Instant::now();
+        assert!(matches!(
+            cache.lookup(
+                "blog.wtcx.dev",
+                &RecordType::A,
+                &RecordClass::IN,
+                &dnssec_ok,
+                deadline
+            ),
+            Lookup::Cached(_)
+        ));
+    }
+}
@@ -0,0 +1,151 @@
+//! Render messages the way `dig` prints them.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+use crate::Message;
+
+/// Format a message like the output of `dig`, with the header, the question, and every non-empty section.
+///
+/// ```
+/// use tiny_resolver_rs::{format_dig, Message, RecordType};
+///
+/// let query = Message::new_query_with_id("blog.wtcx.dev", &RecordType::A, 4660);
+/// assert!(format_dig(&query).starts_with(";; ->>HEADER<<- opcode: QUERY, status: NOERROR, id: 4660\n"));
+/// ```
+pub fn format_dig(msg: &Message) -> String {
+    let header = &msg.header;
+    let header_flags = header.flags();
+    // In the order `dig` lists them
+    let flags: Vec<_> = [
+        (header_flags.response, "qr"),
+        (header_flags.authoritative, "aa"),
+        (header_flags.truncated, "tc"),
+        (header_flags.recursion_desired, "rd"),
+        (header_flags.recursion_available, "ra"),
+        (header_flags.authentic_data, "ad"),
+        (header_flags.checking_disabled, "cd"),
+    ]
+    .into_iter()
+    .filter(|(set, _)| *set)
+    .map(|(_, name)| name)
+    .collect();
+
+    let mut out = String::new();
+    // Writing to a String never fails.
+    let _ = writeln!(
+        out,
+        ";; ->>HEADER<<- opcode: {}, status: {}, id: {}",
+        opcode_name(header.opcode()),
+        status_name(msg.r_code()),
+        header.id()
+    );
+    let _ = writeln!(
+        out,
+        ";; flags: {}; QUERY: {}, ANSWER: {}, AUTHORITY: {}, ADDITIONAL: {}",
+        flags.join(" "),
+        msg.questions.len(),
+        msg.answers.len(),
+        msg.authorities.len(),
+        msg.additionals.len() + usize::from(msg.edns.is_some())
+    );
+
+    if let Some(edns) = &msg.edns {
+        let _ = write!(out, "\n;; OPT PSEUDOSECTION:\n; {edns}\n");
+        if let Some(client_subnet) = edns.client_subnet() {
+            let _ = writeln!(out, "; CLIENT-SUBNET: {client_subnet}");
+        }
+    }
+
+    out.push_str("\n;; QUESTION SECTION:\n");
+    for question in &msg.questions {
+        let _ = writeln!(
+            out,
+            ";{}.  {}  {}",
+            question.domain(),
+            question.q_class(),
+            question.q_type()
+        );
+    }
+
+    let sections = [
+        ("ANSWER", &msg.answers),
+        ("AUTHORITY", &msg.authorities),
+        ("ADDITIONAL", &msg.additionals),
+    ];
+    for (name, records) in sections {
+        if records.is_empty() {
+            continue;
+        }
+
+        let _ = write!(out, "\n;; {name} SECTION:\n");
+        for rr in records {
+            let _ = writeln!(out, "{rr}");
+        }
+    }
+
+    out
+}
+
+/// See [IANA, DNS OpCodes](https://www.iana.org/assignments/dns-parameters/dns-parameters.xhtml#dns-parameters-5).
+fn opcode_name(opcode: u8) -> String {
+    match opcode {
+        0 => "QUERY".into(),
+        1 => "IQUERY".into(),
+        2 => "STATUS".into(),
+        4 => "NOTIFY".into(),
+        5 => "UPDATE".into(),
+        _ => format!("RESERVED{opcode}"),
+    }
+}
+
+/// See [IANA, DNS RCODEs](https://www.iana.org/assignments/dns-parameters/dns-parameters.xhtml#dns-parameters-6).
+fn status_name(r_code: u16) -> String {
+    match r_code {
+        0 => "NOERROR".into(),
+        1 => "FORMERR".into(),
+        2 => "SERVFAIL".into(),
+        3 => "NXDOMAIN".into(),
+        4 => "NOTIMP".into(),
+        5 => "REFUSED".into(),
+        6 => "YXDOMAIN".into(),
+        7 => "YXRRSET".into(),
+        8 => "NXRRSET".into(),
+        9 => "NOTAUTH".into(),
+        10 => "NOTZONE".into(),
+        16 => "BADVERS".into(),
+        _ => format!("RESERVED{r_code}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{testing, RecordType};
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn test_format_a_record_response() {
+        let query = Message::new_query_with_id("blog.wtcx.dev", &RecordType::A, 4660)
+            .with_recursion_desired();
+        let answer = testing::a_record("blog.wtcx.dev", Ipv4Addr::new(1, 2, 3, 4));
+        let response = testing::response(&query.to_query_bytes().unwrap(), 0, &[answer], &[], &[]);
+        let message = Message::with_response(&response, &query).unwrap();
+
+        assert_eq!(
+            format_dig(&message),
+            "\
+;; ->>HEADER<<- opcode: QUERY, status: NOERROR, id: 4660
+;; flags: qr rd; QUERY: 1, ANSWER: 1, AUTHORITY: 0, ADDITIONAL: 0
+
+;; QUESTION SECTION:
+;blog.wtcx.dev.  IN  A
+
+;; ANSWER SECTION:
+blog.wtcx.dev.  300  IN  A  1.2.3.4
+"
+        );
+    }
+}
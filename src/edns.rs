@@ -0,0 +1,309 @@
+use alloc::format;
+use alloc::vec::Vec;
+use core::fmt;
+use core::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use crate::{Error, RecordType};
+
+// The OPT pseudo-record reuses the resource record format, but redefines some of the fields:
+//
+// +------------+--------------+------------------------------+
+// | Field Name | Field Type   | Description                  |
+// +------------+--------------+------------------------------+
+// | NAME       | domain name  | MUST be 0 (root domain)      |
+// | TYPE       | u_int16_t    | OPT (41)                     |
+// | CLASS      | u_int16_t    | requestor's UDP payload size |
+// | TTL        | u_int32_t    | extended RCODE and flags     |
+// | RDLEN      | u_int16_t    | length of all RDATA          |
+// | RDATA      | octet stream | {attribute,value} pairs      |
+// +------------+--------------+------------------------------+
+//
+/// EDNS(0) information carried by the OPT pseudo-record in the additional section.
+///
+/// See [RFC 6891, 6.1.2. Wire Format](https://www.rfc-editor.org/rfc/rfc6891.html).
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Edns {
+    /// The largest UDP payload the sender can reassemble and deliver.
+    pub udp_payload_size: u16,
+    /// Upper 8 bits of the 12-bit extended RCODE.
+    pub extended_rcode: u8,
+    /// Version of the implementation, which is always 0 for EDNS(0).
+    pub version: u8,
+    /// The DO bit and the other (currently reserved) flags.
+    pub flags: u16,
+    /// The EDNS Client Subnet option, the only option kept so far.
+    client_subnet: Option<ClientSubnet>,
+}
+
+impl Edns {
+    /// The DO bit in [`Edns::flags`], asking for DNSSEC records along with the answers.
+    ///
+    /// See [RFC 3225, 3. Protocol Changes](https://www.rfc-editor.org/rfc/rfc3225.html).
+    pub const DNSSEC_OK: u16 = 0x8000;
+
+    /// OPTION-CODE of EDNS Client Subnet.
+    const CLIENT_SUBNET: u16 = 8;
+
+    pub fn new(udp_payload_size: u16) -> Self {
+        Self {
+            udp_payload_size,
+            extended_rcode: 0,
+            version: 0,
+            flags: 0,
+            client_subnet: None,
+        }
+    }
+
+    /// Set the DO bit, without which name servers leave RRSIG and the other DNSSEC records out of responses.
+    pub fn with_dnssec_ok(mut self) -> Self {
+        self.flags |= Self::DNSSEC_OK;
+        self
+    }
+
+    /// Tell name servers which network the query comes from, so they can answer with addresses close to it,
+    /// e.g., to see what a CDN returns to clients elsewhere.
+    pub fn with_client_subnet(mut self, client_subnet: ClientSubnet) -> Self {
+        self.client_subnet = Some(client_subnet);
+        self
+    }
+
+    /// The EDNS Client Subnet option, see [`Edns::with_client_subnet`].
+    pub fn client_subnet(&self) -> Option<ClientSubnet> {
+        self.client_subnet
+    }
+
+    /// Build the OPT pseudo-record, including its (root) name.
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        let mut opt = Vec::with_capacity(11);
+        // `NAME`: root domain
+        opt.push(0);
+        opt.extend_from_slice(&RecordType::OPT.to_u16().to_be_bytes());
+        // `CLASS`: requestor's UDP payload size
+        opt.extend_from_slice(&self.udp_payload_size.to_be_bytes());
+        // `TTL`: extended RCODE (8 bits), version (8 bits) and flags (16 bits)
+        opt.push(self.extended_rcode);
+        opt.push(self.version);
+        opt.extend_from_slice(&self.flags.to_be_bytes());
+        // `RDLEN` and `RDATA`
+        let options = self.options_bytes();
+        opt.extend_from_slice(&(options.len() as u16).to_be_bytes());
+        opt.extend(options);
+
+        opt
+    }
+
+    /// Build the options in RDATA, each of which is OPTION-CODE, OPTION-LENGTH and OPTION-DATA.
+    pub(crate) fn options_bytes(&self) -> Vec<u8> {
+        let mut options = Vec::new();
+        if let Some(client_subnet) = &self.client_subnet {
+            let data = client_subnet.to_bytes();
+            options.extend_from_slice(&Self::CLIENT_SUBNET.to_be_bytes());
+            options.extend_from_slice(&(data.len() as u16).to_be_bytes());
+            options.extend(data);
+        }
+
+        options
+    }
+
+    /// Construct EDNS information from the fields following the name of an OPT pseudo-record.
+    /// Returns it and the position where the record ends.
+    pub(crate) fn from_response(buf: &[u8], start_pos: usize) -> Result<(Self, usize), Error> {
+        // TYPE, CLASS, TTL, and RDLEN take 2 + 2 + 4 + 2 bytes.
+        if buf.len() < start_pos + 10 {
+            return Err(Error::ResolverError("OPT record is out of bound".into()));
+        }
+
+        let rd_length = u16::from_be_bytes([buf[start_pos + 8], buf[start_pos + 9]]) as usize;
+        let end = start_pos + 10 + rd_length;
+
+        if buf.len() < end {
+            return Err(Error::ResolverError(
+                "OPT record doesn't contain enough space for RDATA".into(),
+            ));
+        }
+
+        let edns = Self {
+            udp_payload_size: u16::from_be_bytes([buf[start_pos + 2], buf[start_pos + 3]]),
+            extended_rcode: buf[start_pos + 4],
+            version: buf[start_pos + 5],
+            flags: u16::from_be_bytes([buf[start_pos + 6], buf[start_pos + 7]]),
+            client_subnet: Self::parse_client_subnet(&buf[start_pos + 10..end]),
+        };
+
+        Ok((edns, end))
+    }
+
+    /// Find EDNS Client Subnet among the options. Other options are skipped, and so are options that can't be
+    /// parsed, as the answers don't depend on them.
+    fn parse_client_subnet(options: &[u8]) -> Option<ClientSubnet> {
+        let mut pos = 0;
+        while let Some(header) = options.get(pos..pos + 4) {
+            let code = u16::from_be_bytes([header[0], header[1]]);
+            let length = u16::from_be_bytes([header[2], header[3]]) as usize;
+            let data = options.get(pos + 4..pos + 4 + length)?;
+
+            if code == Self::CLIENT_SUBNET {
+                if let Ok(client_subnet) = ClientSubnet::from_bytes(data) {
+                    return Some(client_subnet);
+                }
+            }
+            pos += 4 + length;
+        }
+
+        None
+    }
+}
+
+// +---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+
+// |                            FAMILY                             |
+// +---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+
+// |     SOURCE PREFIX-LENGTH      |     SCOPE PREFIX-LENGTH       |
+// +---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+
+// |                           ADDRESS...                          /
+// +---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+
+//
+/// The network a query comes from, carried by the EDNS Client Subnet (ECS) option.
+///
+/// See [RFC 7871, 6. Option Format](https://www.rfc-editor.org/rfc/rfc7871.html).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ClientSubnet {
+    address: IpAddr,
+    source_prefix: u8,
+    scope_prefix: u8,
+}
+
+impl ClientSubnet {
+    /// A subnet of `source_prefix` bits, e.g., `192.0.2.0/24`. Bits of `address` beyond the prefix are cleared,
+    /// as they aren't supposed to be sent. Fails if the prefix is longer than the address.
+    pub fn new(address: IpAddr, source_prefix: u8) -> Result<Self, Error> {
+        let max_prefix = match address {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        if source_prefix > max_prefix {
+            return Err(Error::ResolverError(format!(
+                "prefix length {source_prefix} is too long for {address}"
+            )));
+        }
+
+        let address = match address {
+            IpAddr::V4(ip) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - u32::from(source_prefix))
+                    .unwrap_or(0);
+                IpAddr::V4(Ipv4Addr::from(u32::from(ip) & mask))
+            }
+            IpAddr::V6(ip) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - u32::from(source_prefix))
+                    .unwrap_or(0);
+                IpAddr::V6(Ipv6Addr::from(u128::from(ip) & mask))
+            }
+        };
+
+        Ok(Self {
+            address,
+            source_prefix,
+            scope_prefix: 0,
+        })
+    }
+
+    pub fn address(&self) -> IpAddr {
+        self.address
+    }
+
+    pub fn source_prefix(&self) -> u8 {
+        self.source_prefix
+    }
+
+    /// How much of the subnet the answer covers, which is set by name servers in responses, and 0 in queries.
+    pub fn scope_prefix(&self) -> u8 {
+        self.scope_prefix
+    }
+
+    /// Build OPTION-DATA. Only the bytes of the address covered by the prefix are sent.
+    fn to_bytes(self) -> Vec<u8> {
+        let (family, octets): (u16, Vec<u8>) = match self.address {
+            IpAddr::V4(ip) => (1, ip.octets().to_vec()),
+            IpAddr::V6(ip) => (2, ip.octets().to_vec()),
+        };
+        let address_len = usize::from(self.source_prefix).div_ceil(8);
+
+        let mut data = Vec::with_capacity(4 + address_len);
+        data.extend_from_slice(&family.to_be_bytes());
+        data.push(self.source_prefix);
+        data.push(self.scope_prefix);
+        data.extend_from_slice(&octets[..address_len]);
+
+        data
+    }
+
+    fn from_bytes(data: &[u8]) -> Result<Self, Error> {
+        if data.len() < 4 {
+            return Err(Error::ResolverError("ECS option is too short".into()));
+        }
+
+        let family = u16::from_be_bytes([data[0], data[1]]);
+        let (source_prefix, scope_prefix) = (data[2], data[3]);
+        let address = &data[4..];
+        if address.len() != usize::from(source_prefix).div_ceil(8) {
+            return Err(Error::ResolverError(format!(
+                "ECS address of {} bytes doesn't match prefix length {source_prefix}",
+                address.len()
+            )));
+        }
+
+        let address = match family {
+            1 if address.len() <= 4 => {
+                let mut octets = [0; 4];
+                octets[..address.len()].copy_from_slice(address);
+                IpAddr::from(octets)
+            }
+            2 if address.len() <= 16 => {
+                let mut octets = [0; 16];
+                octets[..address.len()].copy_from_slice(address);
+                IpAddr::from(octets)
+            }
+            _ => {
+                return Err(Error::ResolverError(format!(
+                    "unsupported ECS family {family} or prefix length {source_prefix}"
+                )))
+            }
+        };
+
+        Ok(Self {
+            scope_prefix,
+            ..Self::new(address, source_prefix)?
+        })
+    }
+}
+
+impl fmt::Display for ClientSubnet {
+    /// Format the subnet the way `dig` prints it, e.g., `192.0.2.0/24/0` with the scope prefix length at the end.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}/{}/{}",
+            self.address, self.source_prefix, self.scope_prefix
+        )
+    }
+}
+
+impl fmt::Display for Edns {
+    /// Format EDNS information the way `dig` prints it, e.g., `EDNS: version: 0, flags: do; udp: 1232`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // DO is the only EDNS flag defined so far.
+        let flags = if self.flags & Self::DNSSEC_OK != 0 {
+            " do"
+        } else {
+            ""
+        };
+        write!(
+            f,
+            "EDNS: version: {}, flags:{flags}; udp: {}",
+            self.version, self.udp_payload_size
+        )
+    }
+}
@@ -1,25 +1,99 @@
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+#[cfg(feature = "std")]
 use std::io;
 
+use crate::{Message, ResourceRecord};
+
 #[derive(Debug)]
 pub enum Error {
     InvalidHostname,
+    #[cfg(feature = "std")]
     NetworkError(io::Error),
     ResolverError(String),
+    /// Every attempt is spent without an answer. `last` is the last response received, e.g., a referral,
+    /// which tells where the resolution stalls.
+    ResolutionFailed {
+        domain: String,
+        last: Option<Box<Message>>,
+    },
+    /// The domain doesn't exist. `authorities` is the authority section of the response, usually with the SOA
+    /// of the zone, whose minimum TTL tells how long the answer holds. Answers from the cache keep the authority
+    /// section they are cached with. It's empty when the answer doesn't come from a name server, e.g., from a
+    /// blocklist.
+    NxDomain {
+        authorities: Vec<ResourceRecord>,
+    },
+    /// The response is answered, but it's misconfigured, e.g., a CNAME with other data at the same name,
+    /// see [`ResolverBuilder::validate_cnames`](crate::ResolverBuilder::validate_cnames).
+    InvalidResponse(String),
     // The following error are from name servers
+    /// Any error RCODE but NXDOMAIN, which is always reported as [`Error::NxDomain`].
     ServerError(NameServerError),
 }
 
-#[derive(Debug)]
+impl fmt::Display for Error {
+    // The deprecated variant only lends its description.
+    #[allow(deprecated)]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::InvalidHostname => write!(f, "invalid hostname"),
+            #[cfg(feature = "std")]
+            Error::NetworkError(err) => write!(f, "network error: {err}"),
+            Error::ResolverError(reason) => write!(f, "resolver error: {reason}"),
+            Error::ResolutionFailed { domain, .. } => {
+                write!(f, "resolver error: problem resolving address: {domain}")
+            }
+            Error::InvalidResponse(reason) => write!(f, "invalid response: {reason}"),
+            Error::NxDomain { .. } => write!(f, "name server error: {}", NameServerError::NxDomain),
+            Error::ServerError(err) => write!(f, "name server error: {err}"),
+        }
+    }
+}
+
+impl core::error::Error for Error {
+    #[allow(deprecated)]
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            #[cfg(feature = "std")]
+            Error::NetworkError(err) => Some(err),
+            Error::NxDomain { .. } => Some(&NameServerError::NxDomain),
+            Error::ServerError(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+#[repr(u16)]
 pub enum NameServerError {
     FormatError = 1,
     ServerFailure = 2,
+    /// Never carried by [`Error::ServerError`], as NXDOMAIN is reported as [`Error::NxDomain`]
+    /// along with the authority section of the response.
+    #[deprecated(note = "NXDOMAIN is reported as `Error::NxDomain`")]
     NxDomain = 3,
     NotImplemented = 4,
     Refused = 5,
-    Unknown = 6,
+    /// Any other RCODE, e.g., `NOTZONE` (10) or `BADVERS` (16) of the extended RCODE.
+    Unknown(u16),
+}
+
+impl NameServerError {
+    /// Whether another name server might still answer. SERVFAIL and REFUSED are usually a problem of the name server
+    /// that sent them, while NXDOMAIN is the final say of the zone.
+    pub fn is_retriable(&self) -> bool {
+        matches!(
+            self,
+            NameServerError::ServerFailure | NameServerError::Refused
+        )
+    }
 }
 
 impl From<u16> for NameServerError {
+    #[allow(deprecated)]
     fn from(value: u16) -> NameServerError {
         match value {
             1 => NameServerError::FormatError,
@@ -27,13 +101,115 @@ impl From<u16> for NameServerError {
             3 => NameServerError::NxDomain,
             4 => NameServerError::NotImplemented,
             5 => NameServerError::Refused,
-            _ => NameServerError::Unknown,
+            _ => NameServerError::Unknown(value),
         }
     }
 }
 
+// Messages are taken from the RCODE description.
+// See [RFC 1035, 4.1.1. Header section format](https://www.rfc-editor.org/rfc/inline-errata/rfc1035.html).
+impl fmt::Display for NameServerError {
+    #[allow(deprecated)]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let message = match self {
+            NameServerError::FormatError => "the name server was unable to interpret the query",
+            NameServerError::ServerFailure => {
+                "the name server was unable to process this query due to a problem with the name server"
+            }
+            NameServerError::NxDomain => "the domain name referenced in the query does not exist",
+            NameServerError::NotImplemented => {
+                "the name server does not support the requested kind of query"
+            }
+            NameServerError::Refused => "the name server refuses to perform the specified operation",
+            NameServerError::Unknown(r_code) => {
+                return write!(
+                    f,
+                    "the name server responded with an unknown response code: {r_code}"
+                );
+            }
+        };
+
+        write!(f, "{message}")
+    }
+}
+
+impl core::error::Error for NameServerError {}
+
 impl PartialEq for Error {
+    /// Network errors are compared by their kinds, since `io::Error` can't be compared,
+    /// failed resolutions by their domains, regardless of the last message, and NXDOMAIN regardless of the authorities.
     fn eq(&self, other: &Self) -> bool {
-        matches!((self, other), (Error::InvalidHostname, Error::InvalidHostname))
+        match (self, other) {
+            (Error::InvalidHostname, Error::InvalidHostname) => true,
+            #[cfg(feature = "std")]
+            (Error::NetworkError(a), Error::NetworkError(b)) => a.kind() == b.kind(),
+            (Error::ResolverError(a), Error::ResolverError(b)) => a == b,
+            (Error::InvalidResponse(a), Error::InvalidResponse(b)) => a == b,
+            (
+                Error::ResolutionFailed { domain: a, .. },
+                Error::ResolutionFailed { domain: b, .. },
+            ) => a == b,
+            (Error::NxDomain { .. }, Error::NxDomain { .. }) => true,
+            (Error::ServerError(a), Error::ServerError(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_equality() {
+        assert_eq!(
+            Error::ServerError(NameServerError::Refused),
+            Error::ServerError(NameServerError::Refused)
+        );
+        assert_ne!(
+            Error::ServerError(NameServerError::ServerFailure),
+            Error::ServerError(NameServerError::Refused)
+        );
+        assert_eq!(
+            Error::NxDomain {
+                authorities: vec![]
+            },
+            Error::NxDomain {
+                authorities: vec![]
+            }
+        );
+        #[cfg(feature = "std")]
+        assert_eq!(
+            Error::NetworkError(io::ErrorKind::TimedOut.into()),
+            Error::NetworkError(io::Error::new(io::ErrorKind::TimedOut, "no response"))
+        );
+        assert_ne!(
+            Error::ResolverError("referral loop detected".into()),
+            Error::ResolverError("query budget exceeded".into())
+        );
+        assert_ne!(
+            Error::InvalidHostname,
+            Error::ResolverError("invalid hostname".into())
+        );
+        assert_eq!(
+            Error::InvalidResponse("www.wtcx.dev has a CNAME and other data".into()),
+            Error::InvalidResponse("www.wtcx.dev has a CNAME and other data".into())
+        );
+        assert_ne!(
+            Error::InvalidResponse("referral loop detected".into()),
+            Error::ResolverError("referral loop detected".into())
+        );
+    }
+
+    #[test]
+    fn test_unknown_rcode() {
+        assert!(matches!(
+            NameServerError::from(16),
+            NameServerError::Unknown(16)
+        ));
+        assert_eq!(
+            NameServerError::from(16).to_string(),
+            "the name server responded with an unknown response code: 16"
+        );
     }
 }
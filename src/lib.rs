@@ -1,9 +1,48 @@
+//! Without the default `std` feature, only parsing and building messages are left, which work with `alloc` alone,
+//! e.g., to decode messages from a transport of an embedded device.
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
+
 mod utils;
+#[cfg(feature = "std")]
+mod blocklist;
+#[cfg(feature = "std")]
+mod cache;
+mod dig;
+mod edns;
 mod error;
 mod message;
+#[cfg(feature = "std")]
+mod observer;
 mod record;
+#[cfg(feature = "std")]
+mod resolv_conf;
+#[cfg(feature = "std")]
 mod resolver;
+#[cfg(test)]
+mod testing;
+#[cfg(feature = "std")]
+mod transport;
 
+#[cfg(feature = "std")]
+pub use blocklist::BlockAction;
+#[cfg(feature = "std")]
+pub use cache::Cache;
+pub use dig::format_dig;
+pub use edns::*;
 pub use error::*;
+pub use message::{build_query, HeaderFlags, Message, MessageHeader, MessageQuestion};
+#[cfg(feature = "std")]
+pub use observer::ResolverObserver;
 pub use record::*;
-pub use resolver::{query, Protocol};
+#[cfg(feature = "std")]
+pub use resolv_conf::system_resolvers;
+#[cfg(feature = "std")]
+pub use resolver::{
+    query, reverse_lookup, AddressFamily, Protocol, QueryStats, Resolver, ResolverBuilder,
+};
+pub use utils::encode_name;
+
+#[cfg(feature = "tokio")]
+pub use resolver::query_async;
@@ -1,19 +1,32 @@
-use tiny_resolver_rs::{query, RecordData, RecordType};
+use log::{LevelFilter, Log, Metadata, Record};
+use tiny_resolver_rs::{query, RecordType};
+
+/// Prints everything the resolver logs, so we can follow how a domain is resolved.
+struct StdoutLogger;
+
+impl Log for StdoutLogger {
+    fn enabled(&self, _metadata: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        println!("[{}] {}", record.level(), record.args());
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: StdoutLogger = StdoutLogger;
 
 fn main() {
+    log::set_logger(&LOGGER).unwrap();
+    log::set_max_level(LevelFilter::Trace);
+
     for domain in ["blog.wtcx.dev", "www.google.com", "www.facebook.com"] {
-        let record_type = RecordType::A;
-        let res = query(domain, &record_type).unwrap();
-
-        for answer in res.answers {
-            let result: String = match answer.r_data {
-                RecordData::A(ip) => ip.to_string(),
-                RecordData::CNAME(domain) => domain,
-                RecordData::NS(domain) => domain,
-                _ => unimplemented!(),
-            };
-
-            println!("{} -> {}", domain, result);
+        let res = query(domain, &RecordType::A).unwrap();
+
+        for ip in res.a_records() {
+            println!("{} -> {}", domain, ip);
         }
     }
 }
@@ -1,6 +1,16 @@
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use alloc::{format, vec};
+use core::net::{Ipv4Addr, Ipv6Addr};
+use core::time::Duration;
+#[cfg(feature = "std")]
+use std::time::Instant;
+
+#[cfg(feature = "std")]
 use rand::Rng;
 
-use crate::{utils, RecordClass, RecordType, ResourceRecord};
+use crate::utils::NameCompressor;
+use crate::{utils, Edns, RecordClass, RecordData, RecordType, ResourceRecord};
 use crate::{Error, NameServerError};
 
 // Message format:
@@ -21,72 +31,649 @@ use crate::{Error, NameServerError};
 #[derive(Debug)]
 pub struct Message {
     pub header: MessageHeader,
-    pub question: MessageQuestion,
+    /// There is usually only one question, but the format allows more, see `QDCOUNT` of the header.
+    pub questions: Vec<MessageQuestion>,
     pub answers: Vec<ResourceRecord>,
     pub authorities: Vec<ResourceRecord>,
     pub additionals: Vec<ResourceRecord>,
+    /// EDNS information from the OPT pseudo-record, which is kept out of `additionals`.
+    pub edns: Option<Edns>,
+}
+
+/// Build the bytes of a standard query with a fixed ID, without sending it anywhere,
+/// e.g., to see exactly what goes on the wire.
+///
+/// ```
+/// use tiny_resolver_rs::{build_query, RecordType};
+///
+/// let query = build_query("blog.wtcx.dev", &RecordType::A, 0x1234).unwrap();
+/// assert_eq!(query[..2], [0x12, 0x34]);
+/// ```
+pub fn build_query(domain: &str, record_type: &RecordType, id: u16) -> Result<Vec<u8>, Error> {
+    let domain = utils::to_ascii(domain)?;
+    utils::validate_domain(&domain)?;
+
+    Message::new_query_with_id(&domain, record_type, id).to_query_bytes()
 }
 
 impl Message {
+    #[cfg(feature = "std")]
     pub fn new_query(domain: &str, record_type: &RecordType) -> Self {
+        Self::new_query_with_class(domain, record_type, &RecordClass::IN)
+    }
+
+    #[cfg(feature = "std")]
+    pub fn new_query_with_class(
+        domain: &str,
+        record_type: &RecordType,
+        record_class: &RecordClass,
+    ) -> Self {
+        Self::query_with_header(
+            MessageHeader::with_qd_count(1),
+            domain,
+            record_type,
+            record_class,
+        )
+    }
+
+    /// Same as [`Message::new_query`], but with a fixed ID instead of a random one, so the bytes of the query are predictable.
+    /// Without the `std` feature, it's the only way to build a query, as random IDs need the OS to seed them.
+    pub fn new_query_with_id(domain: &str, record_type: &RecordType, id: u16) -> Self {
+        Self::query_with_header(
+            MessageHeader::with_id(id, 1),
+            domain,
+            record_type,
+            &RecordClass::IN,
+        )
+    }
+
+    fn query_with_header(
+        header: MessageHeader,
+        domain: &str,
+        record_type: &RecordType,
+        record_class: &RecordClass,
+    ) -> Self {
         Self {
-            header: MessageHeader::with_qd_count(1),
-            question: MessageQuestion::with_domain(domain, record_type),
+            header,
+            questions: vec![MessageQuestion::with_domain(
+                domain,
+                record_type,
+                record_class,
+            )],
             // We don't need these fields for a query message.
             answers: vec![],
             authorities: vec![],
             additionals: vec![],
+            edns: None,
+        }
+    }
+
+    /// Attach an OPT pseudo-record to the query, e.g., to advertise a larger UDP payload size.
+    pub fn with_edns(mut self, edns: Edns) -> Self {
+        if self.edns.is_none() {
+            self.header.ar_count += 1;
+        }
+        self.edns = Some(edns);
+        self
+    }
+
+    /// Ask the name server to pursue the query recursively, e.g., when sending it to a public recursive resolver.
+    pub fn with_recursion_desired(mut self) -> Self {
+        self.header.flags.recursion_desired = true;
+        self
+    }
+
+    /// Ask the name server to return data that fails DNSSEC validation, e.g., to validate it locally instead.
+    pub fn with_checking_disabled(mut self) -> Self {
+        self.header.flags.checking_disabled = true;
+        self
+    }
+
+    /// Flip the case of letters in the question randomly, and require the response to echo it exactly (DNS 0x20).
+    /// A spoofed response then has to guess the casing as well as the ID, which is a bit per letter.
+    ///
+    /// The question of the response is given back in the original casing, but other names in it keep the
+    /// randomized one, e.g., the answers pointing to the question.
+    ///
+    /// See [Use of Bit 0x20 in DNS Labels to Improve Transaction Identity](https://datatracker.ietf.org/doc/html/draft-vixie-dnsext-dns0x20-00).
+    #[cfg(feature = "std")]
+    pub fn with_randomized_case(mut self) -> Self {
+        let mut rng = rand::thread_rng();
+        for question in &mut self.questions {
+            let randomized = question
+                .domain
+                .chars()
+                .map(|c| match rng.gen() {
+                    true => c.to_ascii_uppercase(),
+                    false => c.to_ascii_lowercase(),
+                })
+                .collect();
+            question.original_domain = Some(core::mem::replace(&mut question.domain, randomized));
+        }
+        self
+    }
+
+    /// Turn the query into a response with the answers, for answers made up without asking any name server.
+    #[cfg(feature = "std")]
+    pub(crate) fn into_response(mut self, answers: Vec<ResourceRecord>) -> Self {
+        self.header.flags.response = true;
+        self.header.an_count = answers.len() as u16;
+        self.answers = answers;
+        self
+    }
+
+    #[cfg(feature = "std")]
+    pub(crate) fn id(&self) -> u16 {
+        self.header.id
+    }
+
+    /// Whether the questions of a response packet match the ones of this query, e.g., with the same casing if it's
+    /// randomized. Only questions that can be parsed are compared, the rest is left to parsing the whole response.
+    #[cfg(feature = "std")]
+    pub(crate) fn matches_question(&self, buf: &[u8]) -> bool {
+        let qd_count = match buf.get(4..6) {
+            Some(count) => u16::from_be_bytes([count[0], count[1]]),
+            None => return true,
+        };
+        let mut pos = 12;
+
+        for query_question in self.questions.iter().take(qd_count.into()) {
+            let (question, end) = match MessageQuestion::from_response(buf, pos) {
+                Ok(parsed) => parsed,
+                Err(_) => break,
+            };
+            if MessageQuestion::validate(query_question, &question).is_err() {
+                return false;
+            }
+            pos = end;
+        }
+
+        true
+    }
+
+    /// Whether the answer comes from a name server that is an authority for the domain, i.e., the AA bit.
+    ///
+    /// See [RFC 1035, 4.1.1. Header section format](https://www.rfc-editor.org/rfc/inline-errata/rfc1035.html).
+    pub fn is_authoritative(&self) -> bool {
+        self.header.flags.authoritative
+    }
+
+    /// The 12 bit RCODE, made of the 4 bits in the header and the upper 8 bits in the OPT pseudo-record if there is one.
+    ///
+    /// See [RFC 6891, 6.1.3. OPT Record TTL Field Use](https://www.rfc-editor.org/rfc/rfc6891.html).
+    pub fn r_code(&self) -> u16 {
+        let extended = self.edns.as_ref().map_or(0, |edns| edns.extended_rcode);
+        (u16::from(extended) << 4) | self.header.r_code()
+    }
+
+    pub(crate) fn check_rcode(&self) -> Result<(), Error> {
+        match self.r_code() {
+            // The SOA of the zone in the authority section tells how long the domain is known not to exist.
+            3 => Err(Error::NxDomain {
+                authorities: self.authorities.clone(),
+            }),
+            r_code => MessageHeader::check_rcode(r_code),
         }
     }
 
     /// Build byte array. This is only used for a standard query.
     ///
     /// See [RFC 1035, section 4.1. Format: MESSAGES](https://www.rfc-editor.org/rfc/inline-errata/rfc1035.html).
-    pub fn to_query_bytes(&self) -> Vec<u8> {
-        // We only need to include header and question secotions.
+    pub fn to_query_bytes(&self) -> Result<Vec<u8>, Error> {
+        // We only need to include header and question secotions, plus the OPT pseudo-record if there is one.
         let mut payload = self.header.to_be_bytes();
-        payload.extend_from_slice(&self.question.to_bytes());
+        for question in &self.questions {
+            payload.extend_from_slice(&question.to_bytes()?);
+        }
+
+        if let Some(edns) = &self.edns {
+            payload.extend_from_slice(&edns.to_bytes());
+        }
+
+        Ok(payload)
+    }
+
+    /// Build byte array of the whole message, including the answer, authority, and additional sections.
+    /// The counts in the header are taken from the sections, and names are not compressed.
+    ///
+    /// See [RFC 1035, section 4.1. Format: MESSAGES](https://www.rfc-editor.org/rfc/inline-errata/rfc1035.html).
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        let mut payload = self.counted_header().to_be_bytes();
+        for question in &self.questions {
+            payload.extend_from_slice(&question.to_bytes()?);
+        }
+
+        for rr in self.all_records() {
+            payload.extend_from_slice(&rr.to_bytes());
+        }
+
+        if let Some(edns) = &self.edns {
+            payload.extend_from_slice(&edns.to_bytes());
+        }
+
+        Ok(payload)
+    }
+
+    /// Same as [`Message::to_bytes`], but names are compressed the way name servers send them, i.e., a name, or the end
+    /// of it, written before is replaced by a pointer to it.
+    ///
+    /// See [RFC 1035, 4.1.4. Message compression](https://www.rfc-editor.org/rfc/inline-errata/rfc1035.html).
+    pub fn to_compressed_bytes(&self) -> Result<Vec<u8>, Error> {
+        let mut payload = self.counted_header().to_be_bytes();
+        let mut names = NameCompressor::default();
+        for question in &self.questions {
+            question.write_compressed(&mut payload, &mut names)?;
+        }
+
+        for rr in self.all_records() {
+            rr.write_compressed(&mut payload, &mut names);
+        }
+
+        if let Some(edns) = &self.edns {
+            payload.extend_from_slice(&edns.to_bytes());
+        }
+
+        Ok(payload)
+    }
+
+    /// The header with the counts taken from the sections.
+    fn counted_header(&self) -> MessageHeader {
+        MessageHeader {
+            id: self.header.id,
+            flags: self.header.flags,
+            qd_count: self.questions.len() as u16,
+            an_count: self.answers.len() as u16,
+            ns_count: self.authorities.len() as u16,
+            ar_count: (self.additionals.len() + usize::from(self.edns.is_some())) as u16,
+        }
+    }
+
+    /// Every record in the answer, authority, and additional sections, in that order.
+    ///
+    /// ```
+    /// use std::net::Ipv4Addr;
+    /// use tiny_resolver_rs::{Message, RecordClass, RecordData, RecordType, ResourceRecord};
+    ///
+    /// let record = |name: &str, r_data| ResourceRecord {
+    ///     name: name.into(),
+    ///     r_type: Some(RecordType::A),
+    ///     r_class: RecordClass::IN,
+    ///     ttl: 300,
+    ///     rd_length: 4,
+    ///     r_data,
+    /// };
+    ///
+    /// let mut message = Message::new_query_with_id("blog.wtcx.dev", &RecordType::A, 0x1234);
+    /// message.answers.push(record("blog.wtcx.dev", RecordData::A(Ipv4Addr::new(1, 2, 3, 4))));
+    /// message.additionals.push(record("ns.wtcx.dev", RecordData::A(Ipv4Addr::new(5, 6, 7, 8))));
+    ///
+    /// assert_eq!(message.all_records().count(), 2);
+    /// ```
+    pub fn all_records(&self) -> impl Iterator<Item = &ResourceRecord> {
+        self.answers
+            .iter()
+            .chain(&self.authorities)
+            .chain(&self.additionals)
+    }
+
+    /// The smallest TTL of the answers, i.e., how long the answer section can be cached as a whole.
+    /// Returns `None` if there is no answer.
+    pub fn min_ttl(&self) -> Option<Duration> {
+        self.answers
+            .iter()
+            .map(|rr| rr.ttl)
+            .min()
+            .map(|ttl| Duration::from_secs(ttl.into()))
+    }
+
+    /// When the answer section expires if it's received at `now`, see [`Message::min_ttl`].
+    #[cfg(feature = "std")]
+    pub fn expires_at(&self, now: Instant) -> Option<Instant> {
+        self.min_ttl().map(|ttl| now + ttl)
+    }
+
+    /// Records of the given type in the answer section.
+    pub fn records_of_type(&self, record_type: RecordType) -> Vec<&ResourceRecord> {
+        self.answers
+            .iter()
+            .filter(|rr| rr.r_type == Some(record_type))
+            .collect()
+    }
+
+    /// IPv4 addresses in the answer section.
+    pub fn a_records(&self) -> Vec<Ipv4Addr> {
+        self.answers.iter().filter_map(|rr| rr.ipv4_ip()).collect()
+    }
+
+    /// IPv6 addresses in the answer section.
+    pub fn aaaa_records(&self) -> Vec<Ipv6Addr> {
+        self.answers
+            .iter()
+            .filter_map(|rr| match rr.r_data {
+                RecordData::AAAA(ip) => Some(ip),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Targets of the CNAME records in the answer section.
+    pub fn cname_targets(&self) -> Vec<&str> {
+        self.answers
+            .iter()
+            .filter_map(|rr| match &rr.r_data {
+                RecordData::CNAME(domain) => Some(domain.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
 
-        payload
+    /// `(name, target)` of each CNAME record in the answer section, in the order they appear,
+    /// e.g., to see how an alias gets to the final name.
+    pub fn cname_chain(&self) -> Vec<(String, String)> {
+        self.answers
+            .iter()
+            .filter_map(|rr| match &rr.r_data {
+                RecordData::CNAME(target) => Some((rr.name.clone(), target.clone())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Check CNAME records in the answer section for misconfigurations, e.g., to lint a zone. It fails with
+    /// [`Error::InvalidResponse`] if a name has a CNAME along with other data, see
+    /// [RFC 1034, 3.6.2. Aliases and canonical names](https://www.rfc-editor.org/rfc/rfc1034#section-3.6.2).
+    ///
+    /// RRSIG records are allowed along with a CNAME, see [RFC 2181, 10.1. CNAME resource records](https://www.rfc-editor.org/rfc/rfc2181#section-10.1).
+    ///
+    /// A CNAME pointing to a name without any record in the answer section isn't necessarily dangling,
+    /// as the target might be in a zone the name server isn't an authority for.
+    pub fn check_cnames(&self) -> Result<(), Error> {
+        for (name, _) in self.cname_chain() {
+            let owned = self
+                .answers
+                .iter()
+                .filter(|rr| {
+                    rr.name.eq_ignore_ascii_case(&name) && rr.r_type != Some(RecordType::RRSIG)
+                })
+                .count();
+            if owned > 1 {
+                return Err(Error::InvalidResponse(format!(
+                    "{name} has a CNAME and other data"
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// `(name, target)` of the first CNAME in the answer section pointing to a name without any record there,
+    /// unless CNAME itself is asked for.
+    #[cfg(feature = "std")]
+    pub(crate) fn unresolved_cname(&self) -> Option<(String, String)> {
+        let asks_cname = self
+            .questions
+            .first()
+            .is_some_and(|question| question.q_type() == RecordType::CNAME);
+        if asks_cname {
+            return None;
+        }
+
+        self.cname_chain().into_iter().find(|(_, target)| {
+            !self
+                .answers
+                .iter()
+                .any(|rr| rr.name.eq_ignore_ascii_case(target))
+        })
     }
 }
 
 impl Message {
-    pub(crate) fn with_response(buf: &[u8], query: &Self) -> Result<Self, Error> {
+    /// Parse a message on its own, e.g., one captured off the wire, without checking it against a query.
+    /// An error RCODE is kept as it is, see [`Message::r_code`].
+    /// Queries can be parsed as well, e.g., the bytes of [`Message::to_query_bytes`].
+    ///
+    /// ```
+    /// use tiny_resolver_rs::Message;
+    ///
+    /// // A response without any question or record
+    /// let message = Message::parse(&[0x12, 0x34, 0x81, 0x80, 0, 0, 0, 0, 0, 0, 0, 0]).unwrap();
+    /// assert_eq!(message.header.id(), 0x1234);
+    /// ```
+    pub fn parse(buf: &[u8]) -> Result<Self, Error> {
+        Self::parse_prefix(buf).map(|(message, _)| message)
+    }
+
+    /// Same as [`Message::parse`], but the message only has to be at the start of `buf`.
+    /// Returns the message and the position where it ends, e.g., where the next message starts in a stream.
+    pub fn parse_prefix(buf: &[u8]) -> Result<(Self, usize), Error> {
+        // headers take fixed 12 bytes (or 96 bits = 16 bits * 6 fields)
+        let header = MessageHeader::try_from(&buf[..buf.len().min(12)])?;
+
+        Self::parse_sections(buf, header, false)
+    }
+
+    /// Parse the response to `query`. An error RCODE in the response is returned as [`Error::ServerError`],
+    /// except NXDOMAIN, which is returned as [`Error::NxDomain`] with the authority section of the response.
+    ///
+    /// A record of a type [`RecordType`] doesn't have fails it, unless a parser is registered for the type.
+    pub fn with_response(buf: &[u8], query: &Self) -> Result<Self, Error> {
+        Self::with_response_prefix(buf, query).map(|(message, _)| message)
+    }
+
+    /// Same as [`Message::with_response`], but the response only has to be at the start of `buf`.
+    /// Returns the message and the position where it ends.
+    pub fn with_response_prefix(buf: &[u8], query: &Self) -> Result<(Self, usize), Error> {
+        let (message, end) = Self::parse_response_prefix(buf, query, false)?;
+        message.check_rcode()?;
+
+        Ok((message, end))
+    }
+
+    /// Same as [`Message::with_response`], but `buf` has to be the response alone, e.g., only the bytes received.
+    /// Bytes left after the message, like data smuggled behind a valid response, are an error instead of being ignored.
+    pub fn with_response_strict(buf: &[u8], query: &Self) -> Result<Self, Error> {
+        let (message, end) = Self::with_response_prefix(buf, query)?;
+        Self::check_consumed(buf, end)?;
+
+        Ok(message)
+    }
+
+    /// Parse one of the messages responding to a zone transfer query.
+    ///
+    /// Messages after the first one might leave the question out, see
+    /// [RFC 5936, 2.2. AXFR Response](https://www.rfc-editor.org/rfc/rfc5936.html).
+    #[cfg(feature = "std")]
+    pub(crate) fn with_transfer_response(
+        buf: &[u8],
+        query: &Self,
+        keep_unknown: bool,
+    ) -> Result<Self, Error> {
+        let header = MessageHeader::try_from(&buf[..buf.len().min(12)])?;
+        let message = match header.qd_count {
+            0 => {
+                let query = Self {
+                    header: MessageHeader::with_id(query.id(), 0),
+                    questions: vec![],
+                    answers: vec![],
+                    authorities: vec![],
+                    additionals: vec![],
+                    edns: None,
+                };
+                Self::parse_response(buf, &query, keep_unknown)?
+            }
+            _ => Self::parse_response(buf, query, keep_unknown)?,
+        };
+        message.check_rcode()?;
+
+        Ok(message)
+    }
+
+    /// Same as [`Message::with_response`], but NXDOMAIN responses are returned as they are
+    /// instead of being an error, so their authority section can be inspected.
+    /// Check it with [`Message::check_rcode`].
+    ///
+    /// Records of types we don't know are kept as [`RecordData::Unknown`] with `keep_unknown`.
+    #[cfg(feature = "std")]
+    pub(crate) fn parse_response(
+        buf: &[u8],
+        query: &Self,
+        keep_unknown: bool,
+    ) -> Result<Self, Error> {
+        Self::parse_response_prefix(buf, query, keep_unknown).map(|(message, _)| message)
+    }
+
+    /// Same as [`Message::parse_response`], but bytes left after the message are an error,
+    /// see [`Message::with_response_strict`].
+    #[cfg(feature = "std")]
+    pub(crate) fn parse_response_strict(
+        buf: &[u8],
+        query: &Self,
+        keep_unknown: bool,
+    ) -> Result<Self, Error> {
+        let (message, end) = Self::parse_response_prefix(buf, query, keep_unknown)?;
+        Self::check_consumed(buf, end)?;
+
+        Ok(message)
+    }
+
+    /// Fail if the message ending at `end` doesn't take up the whole buffer.
+    fn check_consumed(buf: &[u8], end: usize) -> Result<(), Error> {
+        match buf.len() - end {
+            0 => Ok(()),
+            left => Err(Error::ResolverError(format!(
+                "{left} bytes left after the message"
+            ))),
+        }
+    }
+
+    fn parse_response_prefix(
+        buf: &[u8],
+        query: &Self,
+        keep_unknown: bool,
+    ) -> Result<(Self, usize), Error> {
         // headers take fixed 12 bytes (or 96 bits = 16 bits * 6 fields)
-        let header = MessageHeader::try_from(&buf[0..=11])?;
+        let header = MessageHeader::try_from(&buf[..buf.len().min(12)])?;
+        let header_r_code = header.r_code();
+        // A packet for another query, or not a response at all, says nothing about this one, whatever its RCODE is.
         MessageHeader::validate(&query.header, &header)?;
 
-        // question starts with 13th bytes but has variant length
-        let (question, question_end) = MessageQuestion::from_response(buf, 12)?;
-        MessageQuestion::validate(&query.question, &question)?;
+        let parsed = match header.qd_count == query.header.qd_count {
+            true => Self::parse_sections(buf, header, keep_unknown),
+            false => Err(Error::ResolverError("mismatched question count".into())),
+        }
+        .and_then(|(mut message, end)| {
+            // The number of questions has been checked against the query above.
+            for (query_question, question) in query.questions.iter().zip(&mut message.questions) {
+                MessageQuestion::validate(query_question, question)?;
+                if let Some(domain) = &query_question.original_domain {
+                    question.domain.clone_from(domain);
+                }
+            }
+            Ok((message, end))
+        });
+        let (message, end) = match parsed {
+            Ok(parsed) => parsed,
+            // Error responses might not even have the question, so the RCODE in the header explains more.
+            Err(_) if header_r_code != 0 => {
+                return Err(MessageHeader::r_code_error(header_r_code));
+            }
+            Err(err) => return Err(err),
+        };
+
+        // NXDOMAIN comes with the SOA of the zone in the authority section, which tells how long it can be cached.
+        match message.check_rcode() {
+            Err(Error::NxDomain { .. }) | Ok(()) => Ok((message, end)),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Parse everything after the header. Returns the message and the position where it ends.
+    fn parse_sections(
+        buf: &[u8],
+        header: MessageHeader,
+        keep_unknown: bool,
+    ) -> Result<(Self, usize), Error> {
+        // questions start with 13th bytes but have variant length
+        let mut last_pos = 12;
+        let mut questions = Vec::with_capacity(header.qd_count as usize);
+
+        for _ in 0..header.qd_count {
+            let (question, question_end) = MessageQuestion::from_response(buf, last_pos)?;
+            questions.push(question);
+            last_pos = question_end;
+        }
 
-        let mut last_pos = question_end;
         let mut answer_records = vec![];
         let mut authority_records = vec![];
         let mut additional_records = vec![];
 
         let rr_looper = vec![
-            (header.an_count, &mut answer_records),
-            (header.ns_count, &mut authority_records),
-            (header.ar_count, &mut additional_records),
+            ("answer", header.an_count, &mut answer_records),
+            ("authority", header.ns_count, &mut authority_records),
         ];
 
-        for (count, records) in rr_looper {
-            for _ in 0..count {
-                let (resource_record, record_end) = ResourceRecord::from_response(buf, last_pos)?;
+        for (section, count, records) in rr_looper {
+            for index in 0..count {
+                let (resource_record, record_end) = Self::parse_record(buf, last_pos, keep_unknown)
+                    .map_err(|err| Self::section_error(section, index, count, err))?;
                 records.push(resource_record);
                 last_pos = record_end;
             }
         }
 
-        Ok(Self {
+        let mut edns = None;
+
+        for index in 0..header.ar_count {
+            let (resource_record, record_end) = Self::parse_record(buf, last_pos, keep_unknown)
+                .map_err(|err| Self::section_error("additional", index, header.ar_count, err))?;
+            last_pos = record_end;
+
+            // The OPT pseudo-record isn't really a record, so it's kept out of `additionals`.
+            match resource_record.r_data {
+                RecordData::OPT(opt) => edns = Some(opt),
+                _ => additional_records.push(resource_record),
+            }
+        }
+
+        let message = Self {
             header,
-            question,
+            questions,
             answers: answer_records,
             authorities: authority_records,
             additionals: additional_records,
-        })
+            edns,
+        };
+
+        Ok((message, last_pos))
+    }
+
+    /// Parse a record, which fails for a type we don't know unless it's kept as raw RDATA.
+    fn parse_record(
+        buf: &[u8],
+        start_pos: usize,
+        keep_unknown: bool,
+    ) -> Result<(ResourceRecord, usize), Error> {
+        let (rr, end) = ResourceRecord::from_response(buf, start_pos)?;
+
+        match (rr.r_type, &rr.r_data) {
+            (None, RecordData::Unknown { type_code, .. }) if !keep_unknown => Err(
+                Error::ResolverError(format!("unknown record type {type_code}")),
+            ),
+            _ => Ok((rr, end)),
+        }
+    }
+
+    /// Tell which record fails to parse, as a broken count in the header usually leads to a confusing bounds error.
+    fn section_error(section: &str, index: u16, count: u16, err: Error) -> Error {
+        let reason = match err {
+            Error::ResolverError(reason) => reason,
+            err => err.to_string(),
+        };
+
+        Error::ResolverError(format!(
+            "can't parse record {} of {count} in the {section} section: {reason}",
+            index + 1
+        ))
     }
 }
 
@@ -110,7 +697,7 @@ impl Message {
 #[derive(Debug)]
 pub struct MessageHeader {
     id: u16,
-    flags: u16,
+    flags: HeaderFlags,
     qd_count: u16,
     an_count: u16,
     ns_count: u16,
@@ -118,13 +705,18 @@ pub struct MessageHeader {
 }
 
 impl MessageHeader {
+    #[cfg(feature = "std")]
     fn with_qd_count(qd_count: u16) -> Self {
+        Self::with_id(rand::thread_rng().gen(), qd_count)
+    }
+
+    fn with_id(id: u16, qd_count: u16) -> Self {
         Self {
-            id: rand::thread_rng().gen(),
+            id,
             // For a standard query, we only care about QR & OPCODE fields.
             // And QR = 0 stands for query (1 = response); OPCODE = 0 stands for standard query (1 = inverse query, etc)
-            // So we can safely put a 0 here for a question header.
-            flags: 0,
+            // So we can safely leave every flag unset for a question header.
+            flags: HeaderFlags::default(),
             qd_count,
             // Left 0 here because we don't need it in a question;
             an_count: 0,
@@ -141,7 +733,7 @@ impl MessageHeader {
         // id header
         header.extend_from_slice(&self.id.to_be_bytes());
         // flag header
-        header.extend_from_slice(&self.flags.to_be_bytes());
+        header.extend_from_slice(&self.flags.to_u16().to_be_bytes());
         // `QDCOUNT`: hardcoded `1` as we just have 1 question in question section.
         header.extend_from_slice(&self.qd_count.to_be_bytes());
         // `ANCOUNT` (the number of resource records in the answer section)
@@ -154,26 +746,71 @@ impl MessageHeader {
         header
     }
 
+    pub fn id(&self) -> u16 {
+        self.id
+    }
+
+    /// The 16 bits between ID and `QDCOUNT`, i.e., QR, Opcode, AA, TC, RD, RA, Z, and RCODE.
+    pub fn flags(&self) -> HeaderFlags {
+        self.flags
+    }
+
+    /// Kind of query, e.g., 0 for a standard query.
+    pub fn opcode(&self) -> u8 {
+        self.flags.opcode
+    }
+
+    pub fn qd_count(&self) -> u16 {
+        self.qd_count
+    }
+
+    pub fn an_count(&self) -> u16 {
+        self.an_count
+    }
+
+    pub fn ns_count(&self) -> u16 {
+        self.ns_count
+    }
+
+    pub fn ar_count(&self) -> u16 {
+        self.ar_count
+    }
+
     fn validate(question: &Self, response: &Self) -> Result<(), Error> {
-        Self::check_rcode(&response.flags)?;
+        if !response.flags.response {
+            return Err(Error::ResolverError(
+                "expected a response, got a query".into(),
+            ));
+        }
 
-        if question.id == response.id && question.qd_count == response.qd_count
-        // we don't compare other fields like an_count, ns_count, ar_count here as we don't have the corresponding data yet.
-        {
+        // QDCOUNT is compared with the questions, as error responses might leave them out.
+        // We don't compare other fields like an_count, ns_count, ar_count here as we don't have the corresponding data yet.
+        if question.id == response.id {
             Ok(())
         } else {
             Err(Error::ResolverError("mismatched response header".into()))
         }
     }
 
-    /// See [RFC 1035, 4.1.1. Header section format](https://www.rfc-editor.org/rfc/inline-errata/rfc1035.html).
-    fn check_rcode(flags: &u16) -> Result<(), Error> {
-        // Last 4 bit indicating the RCODE section
-        let r_code = flags & 0x000F;
+    fn r_code(&self) -> u16 {
+        u16::from(self.flags.r_code)
+    }
 
+    /// See [RFC 1035, 4.1.1. Header section format](https://www.rfc-editor.org/rfc/inline-errata/rfc1035.html).
+    fn check_rcode(r_code: u16) -> Result<(), Error> {
         match r_code {
             0 => Ok(()),
-            _ => Err(Error::ServerError(NameServerError::from(r_code))),
+            _ => Err(Self::r_code_error(r_code)),
+        }
+    }
+
+    /// The error of a non-zero RCODE, without the sections of the message.
+    fn r_code_error(r_code: u16) -> Error {
+        match r_code {
+            3 => Error::NxDomain {
+                authorities: Vec::new(),
+            },
+            _ => Error::ServerError(NameServerError::from(r_code)),
         }
     }
 }
@@ -195,7 +832,7 @@ impl TryFrom<&[u8]> for MessageHeader {
 
         Ok(Self {
             id: u16::from_be_bytes([header[0], header[1]]),
-            flags: u16::from_be_bytes([header[2], header[3]]),
+            flags: HeaderFlags::from_u16(u16::from_be_bytes([header[2], header[3]])),
             qd_count: u16::from_be_bytes([header[4], header[5]]),
             an_count: u16::from_be_bytes([header[6], header[7]]),
             ns_count: u16::from_be_bytes([header[8], header[9]]),
@@ -204,26 +841,113 @@ impl TryFrom<&[u8]> for MessageHeader {
     }
 }
 
+/// The flags of [`MessageHeader`] by name.
+///
+/// See [RFC 1035, 4.1.1. Header section format](https://www.rfc-editor.org/rfc/inline-errata/rfc1035.html),
+/// and [RFC 4035, 3.2. Recursive Name Servers](https://www.rfc-editor.org/rfc/rfc4035.html) for AD and CD,
+/// which take 2 of the 3 bits of `Z`.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct HeaderFlags {
+    /// `QR`: whether the message is a response rather than a query.
+    pub response: bool,
+    /// `Opcode`: kind of query in 4 bits, e.g., 0 for a standard query.
+    pub opcode: u8,
+    /// `AA`: whether the responding name server is an authority for the domain.
+    pub authoritative: bool,
+    /// `TC`: whether the message is truncated to fit in a UDP datagram.
+    pub truncated: bool,
+    /// `RD`: whether the name server is asked to pursue the query recursively.
+    pub recursion_desired: bool,
+    /// `RA`: whether the name server supports recursive queries.
+    pub recursion_available: bool,
+    /// The first bit of `Z`, which is reserved and should be 0.
+    pub z: bool,
+    /// `AD`: whether the name server has verified the data with DNSSEC.
+    pub authentic_data: bool,
+    /// `CD`: whether DNSSEC checking is disabled for the query.
+    pub checking_disabled: bool,
+    /// `RCODE`: response code in 4 bits, e.g., 3 for NXDOMAIN.
+    pub r_code: u8,
+}
+
+impl HeaderFlags {
+    pub fn from_u16(flags: u16) -> Self {
+        let bit = |mask: u16| flags & mask != 0;
+
+        Self {
+            response: bit(0x8000),
+            opcode: ((flags >> 11) & 0x000F) as u8,
+            authoritative: bit(0x0400),
+            truncated: bit(0x0200),
+            recursion_desired: bit(0x0100),
+            recursion_available: bit(0x0080),
+            z: bit(0x0040),
+            authentic_data: bit(0x0020),
+            checking_disabled: bit(0x0010),
+            r_code: (flags & 0x000F) as u8,
+        }
+    }
+
+    /// Opcode and RCODE are truncated to 4 bits.
+    pub fn to_u16(&self) -> u16 {
+        let bits = [
+            (self.response, 0x8000),
+            (self.authoritative, 0x0400),
+            (self.truncated, 0x0200),
+            (self.recursion_desired, 0x0100),
+            (self.recursion_available, 0x0080),
+            (self.z, 0x0040),
+            (self.authentic_data, 0x0020),
+            (self.checking_disabled, 0x0010),
+        ];
+
+        bits.iter()
+            .filter(|(set, _)| *set)
+            .fold(0, |flags, (_, mask)| flags | mask)
+            | (u16::from(self.opcode & 0x0F) << 11)
+            | u16::from(self.r_code & 0x0F)
+    }
+}
+
 #[derive(Debug)]
 pub struct MessageQuestion {
     domain: String,
     q_type: RecordType,
     q_class: RecordClass,
+    /// The domain before its casing is randomized, see [`Message::with_randomized_case`].
+    /// The response has to echo `domain` with the exact casing then.
+    original_domain: Option<String>,
 }
 
 impl MessageQuestion {
     /// Construct a new question with given domain name.
-    fn with_domain(domain: &str, record_type: &RecordType) -> Self {
+    ///
+    /// The trailing dot of an FQDN is dropped, as domains parsed from responses don't have one.
+    fn with_domain(domain: &str, record_type: &RecordType, record_class: &RecordClass) -> Self {
         Self {
-            domain: domain.to_string(),
+            domain: domain.trim_end_matches('.').to_string(),
             q_type: *record_type,
-            q_class: RecordClass::IN,
+            q_class: *record_class,
+            original_domain: None,
         }
     }
 
+    pub fn domain(&self) -> &str {
+        &self.domain
+    }
+
+    pub fn q_type(&self) -> RecordType {
+        self.q_type
+    }
+
+    pub fn q_class(&self) -> RecordClass {
+        self.q_class
+    }
+
     /// Construct a new question with response buffer.
     /// Returns the Question and the position where it ends
     fn from_response(buf: &[u8], start_pos: usize) -> Result<(Self, usize), Error> {
+        Self::check_uncompressed(buf, start_pos)?;
         let (domain, qname_end_pos) = utils::parse_domain(buf, start_pos)?;
 
         // qname_end is the 0 byte indicating QNAME's end, followed by 2 bytes for QTYPE, 2 bytes for QCLASS
@@ -248,12 +972,34 @@ impl MessageQuestion {
                 domain,
                 q_type,
                 q_class,
+                original_domain: None,
             },
             qname_end_pos + 4,
         ))
     }
 
-    fn to_bytes(&self) -> Vec<u8> {
+    /// There is nothing before the question for QNAME to point to, so a compression pointer there
+    /// can only lead into the records following it.
+    fn check_uncompressed(buf: &[u8], start_pos: usize) -> Result<(), Error> {
+        let mut pos = start_pos;
+
+        while let Some(&len) = buf.get(pos) {
+            if len == 0 {
+                break;
+            }
+            // 0xC0 = 0b11000000, see `utils::parse_domain`
+            if len & 0xC0 == 0xC0 {
+                return Err(Error::ResolverError(
+                    "question name can't be compressed".into(),
+                ));
+            }
+            pos += 1 + len as usize;
+        }
+
+        Ok(())
+    }
+
+    fn to_bytes(&self) -> Result<Vec<u8>, Error> {
         // The binary representation of Question section:
         //
         //                               1  1  1  1  1  1
@@ -267,39 +1013,47 @@ impl MessageQuestion {
         // +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
         // |                     QCLASS                    |
         // +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
-        let mut question = self.build_qname();
+        let mut question = self.build_qname()?;
         question.extend_from_slice(&self.q_type.to_u16().to_be_bytes());
         question.extend_from_slice(&self.q_class.to_u16().to_be_bytes());
 
-        question
+        Ok(question)
     }
 
-    fn build_qname(&self) -> Vec<u8> {
-        let mut qname = vec![];
+    /// Same as [`MessageQuestion::to_bytes`], but QNAME is compressed with `names`.
+    fn write_compressed(&self, buf: &mut Vec<u8>, names: &mut NameCompressor) -> Result<(), Error> {
+        // Checked the same way as an uncompressed QNAME.
+        self.build_qname()?;
+        names.write(buf, &self.domain);
+        buf.extend_from_slice(&self.q_type.to_u16().to_be_bytes());
+        buf.extend_from_slice(&self.q_class.to_u16().to_be_bytes());
 
+        Ok(())
+    }
+
+    fn build_qname(&self) -> Result<Vec<u8>, Error> {
+        // An empty label in the middle, e.g., "foo..bar", would be encoded as the null label,
+        // which ends QNAME early. Not every domain here has been through `utils::validate_domain`,
+        // e.g., the ones of name servers, so it's checked again while encoding.
+        //
         // A domain representation is made of labels.
         //
         // For domain "blog.wtcx.dev", we make it look like: `"4blog4wtcx3dev0"` in a byte array
         // the 0 byte indicates the domain (QNAME) is terminated.
-        for label in self.domain.split('.') {
-            qname.push(label.len() as u8);
-            qname.extend_from_slice(label.as_bytes());
-        }
-
-        // Every domain ends with a null label.
-        // > ...Since every domain name ends with the null label of the root, a domain name is terminated by a length byte of zero.
-        //
-        // Ref: [3.1. Name space definitions](https://www.rfc-editor.org/rfc/inline-errata/rfc1035.html)
-        qname.push(0);
-
-        qname
+        utils::encode_name(&self.domain)
     }
 
+    /// Domain names are compared case-insensitively, as some name servers echo the question with different casing,
+    /// unless the casing of the query is randomized.
+    ///
+    /// See [RFC 4343, 2. Case Independence of DNS Labels](https://www.rfc-editor.org/rfc/rfc4343.html).
     fn validate(query: &Self, response: &Self) -> Result<(), Error> {
-        if query.domain == response.domain
-            && query.q_class == response.q_class
-            && query.q_type == response.q_type
-        {
+        let same_domain = match query.original_domain {
+            Some(_) => query.domain == response.domain,
+            None => query.domain.eq_ignore_ascii_case(&response.domain),
+        };
+
+        if same_domain && query.q_class == response.q_class && query.q_type == response.q_type {
             Ok(())
         } else {
             Err(Error::ResolverError(
@@ -308,3 +1062,676 @@ impl MessageQuestion {
         }
     }
 }
+
+// Most of the tests build queries with random IDs, which need the `std` feature.
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn test_query_with_edns() {
+        let query = Message::new_query("blog.wtcx.dev", &RecordType::A).with_edns(Edns::new(4096));
+        let bytes = query.to_query_bytes().unwrap();
+
+        // `ARCOUNT` in the header
+        assert_eq!(bytes[10..12], [0, 1]);
+        // The OPT pseudo-record comes right after the question
+        assert_eq!(
+            bytes[bytes.len() - 11..],
+            [0, 0, 41, 0x10, 0x00, 0, 0, 0, 0, 0, 0]
+        );
+    }
+
+    #[test]
+    fn test_parse_query_round_trip() {
+        const CHARS: &[u8] = b"abcdefghijklmnopqrstuvwxyz0123456789";
+        let record_types = [
+            RecordType::A,
+            RecordType::AAAA,
+            RecordType::NS,
+            RecordType::CNAME,
+            RecordType::MX,
+            RecordType::TXT,
+            RecordType::PTR,
+            RecordType::SOA,
+        ];
+        let mut rng = rand::thread_rng();
+
+        for _ in 0..200 {
+            // At most 4 labels of 60 characters, so the name stays within 255 bytes.
+            let labels: Vec<String> = (0..rng.gen_range(1..=4))
+                .map(|_| {
+                    (0..rng.gen_range(1..=60))
+                        .map(|_| char::from(CHARS[rng.gen_range(0..CHARS.len())]))
+                        .collect()
+                })
+                .collect();
+            let domain = labels.join(".");
+            let record_type = record_types[rng.gen_range(0..record_types.len())];
+            let mut query = Message::new_query(&domain, &record_type);
+            if rng.gen() {
+                query = query.with_edns(Edns::new(4096));
+            }
+
+            let message = Message::parse(&query.to_query_bytes().unwrap()).unwrap();
+
+            assert!(!message.header.flags().response);
+            assert_eq!(message.header.id(), query.header.id());
+            assert_eq!(message.questions.len(), 1);
+            assert_eq!(message.questions[0].domain(), domain);
+            assert_eq!(message.questions[0].q_type(), record_type);
+            assert!(message.answers.is_empty());
+            assert_eq!(message.edns, query.edns);
+        }
+    }
+
+    #[test]
+    fn test_query_with_id() {
+        let query = Message::new_query_with_id("blog.wtcx.dev", &RecordType::A, 0x1234);
+        let bytes = query.to_query_bytes().unwrap();
+
+        assert_eq!(bytes[0..2], [0x12, 0x34]);
+        assert_eq!(
+            bytes,
+            Message::new_query_with_id("blog.wtcx.dev", &RecordType::A, 0x1234)
+                .to_query_bytes()
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_build_query() {
+        let query = build_query("example.com", &RecordType::A, 0xabcd).unwrap();
+
+        assert_eq!(
+            query,
+            [
+                // ID, flags, QDCOUNT, ANCOUNT, NSCOUNT, and ARCOUNT
+                0xab, 0xcd, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+                // QNAME
+                7, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 3, b'c', b'o', b'm', 0,
+                // QTYPE and QCLASS
+                0x00, 0x01, 0x00, 0x01,
+            ]
+        );
+        assert_eq!(
+            build_query("example..com", &RecordType::A, 0xabcd),
+            Err(Error::InvalidHostname)
+        );
+    }
+
+    #[test]
+    fn test_query_with_empty_label() {
+        // e.g., a name server domain, which doesn't go through `utils::validate_domain`
+        let query = Message::new_query("ns1..wtcx.dev", &RecordType::A);
+
+        assert_eq!(query.to_query_bytes(), Err(Error::InvalidHostname));
+        assert!(Message::new_query(".", &RecordType::NS)
+            .to_query_bytes()
+            .is_ok());
+    }
+
+    #[test]
+    fn test_query_with_trailing_dot() {
+        let query = Message::new_query("google.com.", &RecordType::A);
+        let bytes = query.to_query_bytes().unwrap();
+
+        assert_eq!(bytes[12..24], *b"\x06google\x03com\x00");
+
+        let response = testing::response(&bytes, 0, &[], &[], &[]);
+        assert!(Message::with_response(&response, &query).is_ok());
+    }
+
+    #[test]
+    fn test_response_with_different_casing() {
+        let query = Message::new_query("blog.wtcx.dev", &RecordType::A);
+        let mut bytes = query.to_query_bytes().unwrap();
+        // "BLog.wTcX.dev"
+        for pos in [13, 14, 19, 21] {
+            bytes[pos].make_ascii_uppercase();
+        }
+
+        let response = testing::response(&bytes, 0, &[], &[], &[]);
+        let message = Message::with_response(&response, &query).unwrap();
+
+        assert_eq!(message.questions[0].domain(), "BLog.wTcX.dev");
+    }
+
+    #[test]
+    fn test_any_query() {
+        let query = Message::new_query("wtcx.dev", &RecordType::ANY);
+        let bytes = query.to_query_bytes().unwrap();
+
+        // QTYPE and QCLASS are the last 4 bytes
+        assert_eq!(bytes[bytes.len() - 4..], [0x00, 0xFF, 0x00, 0x01]);
+
+        // Each answer is parsed by its own type.
+        let answers = [
+            testing::a_record("wtcx.dev", Ipv4Addr::new(1, 2, 3, 4)),
+            testing::record("wtcx.dev", RecordType::MX, 300, b"\x00\x0a\x04mail\xc0\x0c"),
+        ];
+        let response = testing::response(&bytes, 0, &answers, &[], &[]);
+        let message = Message::with_response(&response, &query).unwrap();
+
+        assert_eq!(message.questions[0].q_type(), RecordType::ANY);
+        assert_eq!(message.a_records(), [Ipv4Addr::new(1, 2, 3, 4)]);
+        assert_eq!(
+            message.answers[1].to_string(),
+            "wtcx.dev.  300  IN  MX  10 mail.wtcx.dev."
+        );
+    }
+
+    #[test]
+    fn test_answers_by_type() {
+        let query = Message::new_query("www.wtcx.dev", &RecordType::A);
+        let answers = [
+            testing::record(
+                "www.wtcx.dev",
+                RecordType::CNAME,
+                300,
+                &testing::encode("blog.wtcx.dev"),
+            ),
+            testing::a_record("blog.wtcx.dev", Ipv4Addr::new(1, 2, 3, 4)),
+            testing::a_record("blog.wtcx.dev", Ipv4Addr::new(5, 6, 7, 8)),
+            testing::aaaa_record("blog.wtcx.dev", Ipv6Addr::LOCALHOST),
+        ];
+        let response = testing::response(&query.to_query_bytes().unwrap(), 0, &answers, &[], &[]);
+        let message = Message::with_response(&response, &query).unwrap();
+
+        assert_eq!(
+            message.a_records(),
+            [Ipv4Addr::new(1, 2, 3, 4), Ipv4Addr::new(5, 6, 7, 8)]
+        );
+        assert_eq!(message.aaaa_records(), [Ipv6Addr::LOCALHOST]);
+        assert_eq!(message.cname_targets(), ["blog.wtcx.dev"]);
+        assert_eq!(message.records_of_type(RecordType::A).len(), 2);
+        assert!(message.records_of_type(RecordType::MX).is_empty());
+    }
+
+    #[test]
+    fn test_cname_chain() {
+        let query = Message::new_query("www.wtcx.dev", &RecordType::A);
+        let answers = [
+            testing::record(
+                "www.wtcx.dev",
+                RecordType::CNAME,
+                300,
+                &testing::encode("cdn.wtcx.dev"),
+            ),
+            testing::record(
+                "cdn.wtcx.dev",
+                RecordType::CNAME,
+                300,
+                &testing::encode("blog.wtcx.dev"),
+            ),
+            testing::a_record("blog.wtcx.dev", Ipv4Addr::new(1, 2, 3, 4)),
+        ];
+        let response = testing::response(&query.to_query_bytes().unwrap(), 0, &answers, &[], &[]);
+        let message = Message::with_response(&response, &query).unwrap();
+
+        assert_eq!(
+            message.cname_chain(),
+            [
+                ("www.wtcx.dev".to_string(), "cdn.wtcx.dev".to_string()),
+                ("cdn.wtcx.dev".to_string(), "blog.wtcx.dev".to_string()),
+            ]
+        );
+        assert!(Message::new_query("www.wtcx.dev", &RecordType::A)
+            .cname_chain()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_randomized_case() {
+        let query = Message::new_query("blog.wtcx.dev", &RecordType::A).with_randomized_case();
+        let answer = testing::a_record("blog.wtcx.dev", Ipv4Addr::new(1, 2, 3, 4));
+        let response = testing::response(&query.to_query_bytes().unwrap(), 0, &[answer], &[], &[]);
+
+        assert!(query.questions[0]
+            .domain()
+            .eq_ignore_ascii_case("blog.wtcx.dev"));
+        assert!(Message::with_response(&response, &query).is_ok());
+    }
+
+    #[test]
+    fn test_check_cnames() {
+        let query = Message::new_query("www.wtcx.dev", &RecordType::A);
+        let cname = testing::record(
+            "www.wtcx.dev",
+            RecordType::CNAME,
+            300,
+            &testing::encode("blog.wtcx.dev"),
+        );
+        let parse = |answers: &[Vec<u8>]| {
+            let response =
+                testing::response(&query.to_query_bytes().unwrap(), 0, answers, &[], &[]);
+            Message::with_response(&response, &query).unwrap()
+        };
+
+        let answer = testing::a_record("blog.wtcx.dev", Ipv4Addr::new(1, 2, 3, 4));
+        assert!(parse(&[cname.clone(), answer]).check_cnames().is_ok());
+
+        let coexisting = testing::a_record("www.wtcx.dev", Ipv4Addr::new(1, 2, 3, 4));
+        assert_eq!(
+            parse(&[cname.clone(), coexisting]).check_cnames(),
+            Err(Error::InvalidResponse(
+                "www.wtcx.dev has a CNAME and other data".into()
+            ))
+        );
+
+        // The target might be in another zone.
+        let message = parse(&[cname]);
+        assert!(message.check_cnames().is_ok());
+        assert_eq!(
+            message.unresolved_cname(),
+            Some(("www.wtcx.dev".to_string(), "blog.wtcx.dev".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_response_with_unknown_record_type() {
+        let query = Message::new_query("blog.wtcx.dev", &RecordType::A);
+        let mut unknown = testing::record("blog.wtcx.dev", RecordType::A, 300, &[0xde, 0xad]);
+        // Replace TYPE, which follows the name, with a made-up type code.
+        let type_pos = testing::encode("blog.wtcx.dev").len();
+        unknown[type_pos..type_pos + 2].copy_from_slice(&9999u16.to_be_bytes());
+        let answers = [
+            testing::a_record("blog.wtcx.dev", Ipv4Addr::new(1, 2, 3, 4)),
+            unknown,
+            testing::a_record("blog.wtcx.dev", Ipv4Addr::new(5, 6, 7, 8)),
+        ];
+        let response = testing::response(&query.to_query_bytes().unwrap(), 0, &answers, &[], &[]);
+
+        assert!(matches!(
+            Message::with_response(&response, &query),
+            Err(Error::ResolverError(reason))
+                if reason == "can't parse record 2 of 3 in the answer section: unknown record type 9999"
+        ));
+
+        let message = Message::parse_response(&response, &query, true).unwrap();
+
+        assert_eq!(
+            message.a_records(),
+            [Ipv4Addr::new(1, 2, 3, 4), Ipv4Addr::new(5, 6, 7, 8)]
+        );
+        assert_eq!(message.answers[1].r_type, None);
+        assert_eq!(
+            message.answers[1].r_data,
+            RecordData::Unknown {
+                type_code: 9999,
+                data: vec![0xde, 0xad],
+            }
+        );
+        assert_eq!(
+            message.answers[1].to_string(),
+            "blog.wtcx.dev.  300  IN  TYPE9999  \\# 2 dead"
+        );
+        assert_eq!(message.to_bytes().unwrap(), response);
+    }
+
+    #[test]
+    fn test_min_ttl() {
+        let query = Message::new_query("www.wtcx.dev", &RecordType::A).with_edns(Edns::new(1232));
+        let answers = [
+            testing::record(
+                "www.wtcx.dev",
+                RecordType::CNAME,
+                300,
+                &testing::encode("blog.wtcx.dev"),
+            ),
+            testing::record("blog.wtcx.dev", RecordType::A, 60, &[1, 2, 3, 4]),
+        ];
+        // The OPT pseudo-record has zero TTL, but it's not an answer.
+        let additionals = [Edns::new(1232).to_bytes()];
+        let response = testing::response(
+            &query.to_query_bytes().unwrap(),
+            0,
+            &answers,
+            &[],
+            &additionals,
+        );
+        let message = Message::with_response(&response, &query).unwrap();
+        let now = Instant::now();
+
+        assert_eq!(message.min_ttl(), Some(Duration::from_secs(60)));
+        assert_eq!(message.expires_at(now), Some(now + Duration::from_secs(60)));
+        assert_eq!(query.min_ttl(), None);
+        assert_eq!(query.expires_at(now), None);
+    }
+
+    #[test]
+    fn test_response_with_missing_answer() {
+        let query = Message::new_query("blog.wtcx.dev", &RecordType::A);
+        let answer = testing::a_record("blog.wtcx.dev", Ipv4Addr::new(1, 2, 3, 4));
+        let mut response =
+            testing::response(&query.to_query_bytes().unwrap(), 0, &[answer], &[], &[]);
+        // `ANCOUNT` claims 2 answers but there is only 1.
+        response[7] = 2;
+
+        assert!(matches!(
+            Message::with_response(&response, &query),
+            Err(Error::ResolverError(reason))
+                if reason.starts_with("can't parse record 2 of 2 in the answer section")
+        ));
+    }
+
+    #[test]
+    fn test_response_with_trailing_bytes() {
+        let query = Message::new_query("blog.wtcx.dev", &RecordType::A);
+        let answer = testing::a_record("blog.wtcx.dev", Ipv4Addr::new(1, 2, 3, 4));
+        let mut response =
+            testing::response(&query.to_query_bytes().unwrap(), 0, &[answer], &[], &[]);
+        Message::with_response_strict(&response, &query).unwrap();
+
+        response.extend_from_slice(b"junk");
+
+        assert!(Message::with_response(&response, &query).is_ok());
+        assert!(matches!(
+            Message::with_response_strict(&response, &query),
+            Err(Error::ResolverError(reason)) if reason == "4 bytes left after the message"
+        ));
+    }
+
+    #[test]
+    fn test_response_with_compressed_question() {
+        let query = Message::new_query("blog.wtcx.dev", &RecordType::A);
+        let mut response = query.to_query_bytes().unwrap()[..12].to_vec();
+        // QR = 1
+        response[2] |= 0x80;
+        // QNAME points to the name right after QTYPE and QCLASS.
+        response.extend_from_slice(&[0xC0, 18, 0, 1, 0, 1]);
+        response.extend(testing::encode("blog.wtcx.dev"));
+
+        assert!(matches!(
+            Message::with_response(&response, &query),
+            Err(Error::ResolverError(reason)) if reason == "question name can't be compressed"
+        ));
+    }
+
+    #[test]
+    fn test_parse_captured_response() {
+        // example.com A, with the answer pointing to the name in the question.
+        let captured = b"\xab\xcd\x81\x80\x00\x01\x00\x01\x00\x00\x00\x00\
+            \x07example\x03com\x00\x00\x01\x00\x01\
+            \xc0\x0c\x00\x01\x00\x01\x00\x00\x0e\x10\x00\x04\x5d\xb8\xd8\x22";
+
+        let message = Message::parse(captured).unwrap();
+
+        assert_eq!(message.header.id(), 0xabcd);
+        assert_eq!(message.questions[0].domain(), "example.com");
+        assert_eq!(
+            message.answers[0].to_string(),
+            "example.com.  3600  IN  A  93.184.216.34"
+        );
+    }
+
+    #[test]
+    fn test_parse_back_to_back_messages() {
+        let query = Message::new_query("blog.wtcx.dev", &RecordType::A);
+        let answer = testing::a_record("blog.wtcx.dev", Ipv4Addr::new(1, 2, 3, 4));
+        let first = testing::response(&query.to_query_bytes().unwrap(), 0, &[answer], &[], &[]);
+        let second = testing::response(&query.to_query_bytes().unwrap(), 0, &[], &[], &[]);
+        let buf = [first.as_slice(), second.as_slice()].concat();
+
+        let (message, end) = Message::with_response_prefix(&buf, &query).unwrap();
+        assert_eq!(end, first.len());
+        assert_eq!(message.a_records(), [Ipv4Addr::new(1, 2, 3, 4)]);
+
+        let (message, end) = Message::parse_prefix(&buf[end..]).unwrap();
+        assert_eq!(end, second.len());
+        assert!(message.answers.is_empty());
+    }
+
+    #[test]
+    fn test_parse_error_response() {
+        let query = Message::new_query("missing.wtcx.dev", &RecordType::A);
+        let soa = testing::soa_record("wtcx.dev", 60);
+        let response = testing::response(&query.to_query_bytes().unwrap(), 3, &[], &[soa], &[]);
+
+        let message = Message::parse(&response).unwrap();
+
+        assert_eq!(message.r_code(), 3);
+        assert_eq!(message.authorities.len(), 1);
+    }
+
+    #[test]
+    fn test_short_response() {
+        let query = Message::new_query("blog.wtcx.dev", &RecordType::A);
+
+        assert!(Message::with_response(&[0, 1, 2], &query).is_err());
+    }
+
+    #[test]
+    fn test_compressed_response_round_trip() {
+        let query = Message::new_query("www.wtcx.dev", &RecordType::MX).with_edns(Edns::new(1232));
+        let mut mx = 10u16.to_be_bytes().to_vec();
+        mx.extend(testing::encode("mail.wtcx.dev"));
+        let answers = [
+            testing::record(
+                "www.wtcx.dev",
+                RecordType::CNAME,
+                300,
+                &testing::encode("blog.wtcx.dev"),
+            ),
+            testing::record("blog.wtcx.dev", RecordType::MX, 300, &mx),
+        ];
+        let authorities = [
+            testing::ns_record("wtcx.dev", "ns1.wtcx.dev"),
+            testing::ns_record("wtcx.dev", "ns2.wtcx.dev"),
+            testing::soa_record("wtcx.dev", 300),
+        ];
+        let additionals = [
+            testing::a_record("ns1.wtcx.dev", Ipv4Addr::new(1, 2, 3, 4)),
+            testing::aaaa_record("ns2.wtcx.dev", Ipv6Addr::LOCALHOST),
+            Edns::new(1232).to_bytes(),
+        ];
+        let response = testing::response(
+            &query.to_query_bytes().unwrap(),
+            0,
+            &answers,
+            &authorities,
+            &additionals,
+        );
+        let message = Message::with_response(&response, &query).unwrap();
+
+        let compressed = message.to_compressed_bytes().unwrap();
+        let reparsed = Message::with_response(&compressed, &query).unwrap();
+
+        assert!(compressed.len() < response.len());
+        // RDLENGTH of the records differs, but they are the same once written without compression.
+        assert_eq!(reparsed.to_bytes().unwrap(), response);
+        assert_eq!(reparsed.edns, message.edns);
+        let records = |message: &Message| -> Vec<_> {
+            message.all_records().map(|rr| rr.to_string()).collect()
+        };
+        assert_eq!(records(&reparsed), records(&message));
+    }
+
+    #[test]
+    fn test_response_round_trip() {
+        let query = Message::new_query("www.wtcx.dev", &RecordType::A).with_edns(Edns::new(1232));
+        let answers = [
+            testing::record(
+                "www.wtcx.dev",
+                RecordType::CNAME,
+                300,
+                &testing::encode("blog.wtcx.dev"),
+            ),
+            testing::a_record("blog.wtcx.dev", Ipv4Addr::new(1, 2, 3, 4)),
+        ];
+        let authorities = [testing::ns_record("wtcx.dev", "ns.wtcx.dev")];
+        let additionals = [
+            testing::aaaa_record("ns.wtcx.dev", Ipv6Addr::LOCALHOST),
+            Edns::new(1232).to_bytes(),
+        ];
+        let response = testing::response(
+            &query.to_query_bytes().unwrap(),
+            0,
+            &answers,
+            &authorities,
+            &additionals,
+        );
+
+        let message = Message::with_response(&response, &query).unwrap();
+        let bytes = message.to_bytes().unwrap();
+        assert_eq!(bytes, response);
+
+        let reparsed = Message::with_response(&bytes, &query).unwrap();
+        let answers: Vec<_> = reparsed.answers.iter().map(|rr| rr.to_string()).collect();
+        assert_eq!(
+            answers,
+            [
+                "www.wtcx.dev.  300  IN  CNAME  blog.wtcx.dev.",
+                "blog.wtcx.dev.  300  IN  A  1.2.3.4"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_response_with_two_questions() {
+        let mut query = Message::new_query("blog.wtcx.dev", &RecordType::A);
+        query.questions.push(MessageQuestion::with_domain(
+            "blog.wtcx.dev",
+            &RecordType::AAAA,
+            &RecordClass::IN,
+        ));
+        query.header.qd_count = 2;
+
+        let mut response = query.to_query_bytes().unwrap();
+        // QR = 1, ANCOUNT = 1
+        response[2] |= 0x80;
+        response[7] = 1;
+        response.extend(testing::a_record(
+            "blog.wtcx.dev",
+            Ipv4Addr::new(1, 2, 3, 4),
+        ));
+
+        let message = Message::with_response(&response, &query).unwrap();
+
+        assert_eq!(message.questions.len(), 2);
+        assert_eq!(message.questions[1].q_type, RecordType::AAAA);
+        assert_eq!(message.a_records(), [Ipv4Addr::new(1, 2, 3, 4)]);
+        assert_eq!(message.to_bytes().unwrap(), response);
+    }
+
+    #[test]
+    fn test_extended_rcode() {
+        let query = Message::new_query("blog.wtcx.dev", &RecordType::A).with_edns(Edns::new(1232));
+        // BADVERS (16): 0 in the header, 1 in the OPT pseudo-record.
+        let mut opt = Edns::new(1232);
+        opt.extended_rcode = 1;
+        let response = testing::response(
+            &query.to_query_bytes().unwrap(),
+            0,
+            &[],
+            &[],
+            &[opt.to_bytes()],
+        );
+
+        assert!(matches!(
+            Message::with_response(&response, &query),
+            Err(Error::ServerError(NameServerError::Unknown(16)))
+        ));
+    }
+
+    #[test]
+    fn test_error_response_without_question() {
+        let query = Message::new_query("blog.wtcx.dev", &RecordType::A);
+        let mut response = query.to_query_bytes().unwrap()[..12].to_vec();
+        // QR = 1, RCODE = SERVFAIL, QDCOUNT = 0
+        response[2] |= 0x80;
+        response[3] = 2;
+        response[5] = 0;
+
+        assert!(matches!(
+            Message::with_response(&response, &query),
+            Err(Error::ServerError(NameServerError::ServerFailure))
+        ));
+    }
+
+    #[test]
+    fn test_error_response_to_other_query() {
+        let query = Message::new_query("blog.wtcx.dev", &RecordType::A);
+        let mut response = testing::response(&query.to_query_bytes().unwrap(), 2, &[], &[], &[]);
+        // Another ID
+        response[0] = !response[0];
+
+        assert!(matches!(
+            Message::with_response(&response, &query),
+            Err(Error::ResolverError(reason)) if reason == "mismatched response header"
+        ));
+    }
+
+    #[test]
+    fn test_authoritative_answer() {
+        let query = Message::new_query("blog.wtcx.dev", &RecordType::A);
+        let mut response = testing::response(&query.to_query_bytes().unwrap(), 0, &[], &[], &[]);
+        let message = Message::with_response(&response, &query).unwrap();
+        assert!(!message.is_authoritative());
+
+        response[2] |= 0x04;
+        let message = Message::with_response(&response, &query).unwrap();
+        assert!(message.is_authoritative());
+    }
+
+    #[test]
+    fn test_question_with_class() {
+        let question =
+            MessageQuestion::with_domain("version.bind", &RecordType::TXT, &RecordClass::CH);
+        let bytes = question.to_bytes().unwrap();
+
+        // QTYPE and QCLASS are the last 4 bytes
+        assert_eq!(bytes[bytes.len() - 4..], [0x00, 0x10, 0x00, 0x03]);
+    }
+
+    #[test]
+    fn test_header_flags_round_trip() {
+        // A query with RD, and a response with QR, AA, RD, RA, and NXDOMAIN
+        for flags in [0x0100, 0x8583] {
+            assert_eq!(HeaderFlags::from_u16(flags).to_u16(), flags);
+        }
+
+        // A truncated response to a query with CD, which has AD and SERVFAIL
+        let flags = HeaderFlags::from_u16(0x82b2);
+        assert_eq!(
+            flags,
+            HeaderFlags {
+                response: true,
+                truncated: true,
+                recursion_available: true,
+                authentic_data: true,
+                checking_disabled: true,
+                r_code: 2,
+                ..Default::default()
+            }
+        );
+        assert_eq!(flags.to_u16(), 0x82b2);
+
+        // An inverse query
+        let flags = HeaderFlags {
+            opcode: 1,
+            ..Default::default()
+        };
+        assert_eq!(flags.to_u16(), 0x0800);
+        assert_eq!(HeaderFlags::from_u16(0x0800), flags);
+    }
+
+    #[test]
+    fn test_header_without_qr_bit() {
+        let query = MessageHeader::with_qd_count(1);
+        let response = MessageHeader {
+            id: query.id,
+            flags: HeaderFlags::from_u16(0x0100),
+            qd_count: 1,
+            an_count: 1,
+            ns_count: 0,
+            ar_count: 0,
+        };
+
+        assert!(matches!(
+            MessageHeader::validate(&query, &response),
+            Err(Error::ResolverError(reason)) if reason == "expected a response, got a query"
+        ));
+    }
+}
@@ -0,0 +1,76 @@
+//! Hooks to watch how domains are resolved, e.g., to feed metrics.
+
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::ops::Deref;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::RecordType;
+
+/// Receives events of the iterative resolution, set with
+/// [`ResolverBuilder::observer`](crate::ResolverBuilder::observer).
+///
+/// Every method does nothing by default, so only the events of interest need to be implemented.
+/// They are called on the thread doing the resolution, so they should return quickly.
+pub trait ResolverObserver: Send + Sync {
+    /// A query is sent to a name server.
+    fn on_query_sent(&self, _server: &SocketAddr, _record_type: &RecordType) {}
+
+    /// A name server responds `latency` after the query was sent.
+    /// `r_code` is the 4 bit RCODE in the header, e.g., 3 for NXDOMAIN.
+    fn on_response(
+        &self,
+        _server: &SocketAddr,
+        _record_type: &RecordType,
+        _r_code: u16,
+        _latency: Duration,
+    ) {
+    }
+
+    /// None of the name servers respond in time.
+    fn on_timeout(&self, _servers: &[IpAddr], _record_type: &RecordType) {}
+
+    /// The name servers can't be reached or fail to answer, so the next attempt asks other ones.
+    fn on_retry(&self, _servers: &[IpAddr], _record_type: &RecordType) {}
+
+    /// The answer, or that the domain doesn't exist, comes from the cache.
+    fn on_cache_hit(&self, _domain: &str, _record_type: &RecordType) {}
+
+    /// The question isn't in the cache, so it's resolved.
+    fn on_cache_miss(&self, _domain: &str, _record_type: &RecordType) {}
+}
+
+/// Observer of a resolver, which ignores every event unless one is set.
+#[derive(Clone)]
+pub(crate) struct Observer(Arc<dyn ResolverObserver>);
+
+struct Noop;
+
+impl ResolverObserver for Noop {}
+
+impl Observer {
+    pub(crate) fn new(observer: Arc<dyn ResolverObserver>) -> Self {
+        Self(observer)
+    }
+}
+
+impl Default for Observer {
+    fn default() -> Self {
+        Self(Arc::new(Noop))
+    }
+}
+
+impl Deref for Observer {
+    type Target = dyn ResolverObserver;
+
+    fn deref(&self) -> &Self::Target {
+        self.0.as_ref()
+    }
+}
+
+impl fmt::Debug for Observer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Observer")
+    }
+}
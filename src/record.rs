@@ -1,10 +1,21 @@
-use std::net::{Ipv4Addr, Ipv6Addr};
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use alloc::{format, vec};
+use core::fmt;
+use core::net::{Ipv4Addr, Ipv6Addr};
 
-use crate::{utils, Error};
+use crate::utils::NameCompressor;
+use crate::{utils, Edns, Error};
+
+mod parser;
+#[cfg(feature = "std")]
+pub use parser::register_rdata_parser;
+pub use parser::RdataParser;
 
 /// See See [RFC 1035, 3.2.2. TYPE values](https://www.rfc-editor.org/rfc/inline-errata/rfc1035.html).
 #[non_exhaustive]
 #[derive(Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum RecordType {
     /// host address
     A = 1,
@@ -40,6 +51,28 @@ pub enum RecordType {
     TXT = 16,
     /// IPv6 address
     AAAA = 28,
+    /// naming authority pointer, see [RFC 3403](https://www.rfc-editor.org/rfc/rfc3403.html)
+    NAPTR = 35,
+    /// redirection of a subtree, see [RFC 6672](https://www.rfc-editor.org/rfc/rfc6672.html)
+    DNAME = 39,
+    /// EDNS pseudo-record, see [RFC 6891](https://www.rfc-editor.org/rfc/rfc6891.html)
+    OPT = 41,
+    /// delegation signer, the digest of a DNSKEY of the child zone, see [RFC 4034](https://www.rfc-editor.org/rfc/rfc4034.html)
+    DS = 43,
+    /// SSH key fingerprint, see [RFC 4255](https://www.rfc-editor.org/rfc/rfc4255.html)
+    SSHFP = 44,
+    /// signature of a record set, see [RFC 4034](https://www.rfc-editor.org/rfc/rfc4034.html)
+    RRSIG = 46,
+    /// public key of a zone for DNSSEC, see [RFC 4034](https://www.rfc-editor.org/rfc/rfc4034.html)
+    DNSKEY = 48,
+    /// TLS certificate association, see [RFC 6698](https://www.rfc-editor.org/rfc/rfc6698.html)
+    TLSA = 52,
+    /// sender policy, the same as TXT (Obsolete - use TXT), see [RFC 7208](https://www.rfc-editor.org/rfc/rfc7208.html)
+    SPF = 99,
+    /// transfer of an entire zone, only valid as QTYPE, see [RFC 5936](https://www.rfc-editor.org/rfc/rfc5936.html)
+    AXFR = 252,
+    /// all records of a name, only valid as QTYPE
+    ANY = 255,
 }
 
 impl RecordType {
@@ -48,6 +81,13 @@ impl RecordType {
     }
 }
 
+impl fmt::Display for RecordType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Variant names are the same as the mnemonics used in zone files.
+        write!(f, "{self:?}")
+    }
+}
+
 impl TryFrom<u16> for RecordType {
     type Error = Error;
 
@@ -73,6 +113,17 @@ impl TryFrom<u16> for RecordType {
             // The AAAA resource record type is a record specific to the Internet class that stores a single IPv6 address.
             // The IANA assigned value of the type is 28 (decimal).
             28 => Ok(RecordType::AAAA),
+            35 => Ok(RecordType::NAPTR),
+            39 => Ok(RecordType::DNAME),
+            41 => Ok(RecordType::OPT),
+            43 => Ok(RecordType::DS),
+            44 => Ok(RecordType::SSHFP),
+            46 => Ok(RecordType::RRSIG),
+            48 => Ok(RecordType::DNSKEY),
+            52 => Ok(RecordType::TLSA),
+            99 => Ok(RecordType::SPF),
+            252 => Ok(RecordType::AXFR),
+            255 => Ok(RecordType::ANY),
             _ => Err(Error::ResolverError(format!(
                 "can't parse unknown record type: {value}"
             ))),
@@ -82,6 +133,7 @@ impl TryFrom<u16> for RecordType {
 
 /// See See [RFC 1035, 3.2.3. QTYPE values](https://www.rfc-editor.org/rfc/inline-errata/rfc1035.html).
 #[derive(Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum RecordClass {
     /// The Internet. We probably only care about this.
     IN = 1,
@@ -99,6 +151,12 @@ impl RecordClass {
     }
 }
 
+impl fmt::Display for RecordClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
 impl TryFrom<u16> for RecordClass {
     type Error = Error;
 
@@ -117,17 +175,97 @@ impl TryFrom<u16> for RecordClass {
 }
 
 #[non_exhaustive]
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "type", content = "value"))]
 pub enum RecordData {
     CNAME(String),
     NS(String),
     A(Ipv4Addr),
     AAAA(Ipv6Addr),
     SOA(SoaRecord),
+    MX {
+        preference: u16,
+        exchange: String,
+    },
+    TXT(Vec<String>),
+    /// Character-strings of a sender policy, e.g., `v=spf1 -all`, in the same format as TXT.
+    SPF(Vec<String>),
+    PTR(String),
+    DNAME(String),
+    MB(String),
+    MG(String),
+    MR(String),
+    NAPTR {
+        order: u16,
+        preference: u16,
+        flags: String,
+        services: String,
+        regexp: String,
+        replacement: String,
+    },
+    TLSA {
+        usage: u8,
+        selector: u8,
+        matching_type: u8,
+        cert_association: Vec<u8>,
+    },
+    SSHFP {
+        algorithm: u8,
+        fp_type: u8,
+        fingerprint: Vec<u8>,
+    },
+    DS {
+        key_tag: u16,
+        algorithm: u8,
+        digest_type: u8,
+        digest: Vec<u8>,
+    },
+    DNSKEY {
+        flags: u16,
+        protocol: u8,
+        algorithm: u8,
+        public_key: Vec<u8>,
+    },
+    RRSIG {
+        type_covered: u16,
+        algorithm: u8,
+        labels: u8,
+        original_ttl: u32,
+        expiration: u32,
+        inception: u32,
+        key_tag: u16,
+        signer_name: String,
+        signature: Vec<u8>,
+    },
+    WKS {
+        address: Ipv4Addr,
+        protocol: u8,
+        bitmap: Vec<u8>,
+    },
+    HINFO {
+        cpu: String,
+        os: String,
+    },
+    /// EDNS information, taken from CLASS and TTL of the OPT pseudo-record rather than RDATA.
+    OPT(Edns),
+    /// RDATA of a type we can't parse, kept as it is.
+    Unknown {
+        type_code: u16,
+        data: Vec<u8>,
+    },
+    /// RDATA of a type [`RecordType`] doesn't have, parsed by a parser from [`register_rdata_parser`].
+    /// `text` is how it's presented in zone files, and `data` is RDATA as it is.
+    Custom {
+        type_code: u16,
+        text: String,
+        data: Vec<u8>,
+    },
 }
 
-#[allow(dead_code)]
-#[derive(Debug)]
+/// See [RFC 1035, 3.3.13. SOA RDATA format](https://www.rfc-editor.org/rfc/inline-errata/rfc1035.html).
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct SoaRecord {
     m_name: String,
     r_name: String,
@@ -138,21 +276,65 @@ pub struct SoaRecord {
     minimum: u32,
 }
 
+impl SoaRecord {
+    /// `MNAME`: the name server that was the original or primary source of data for this zone.
+    pub fn m_name(&self) -> &str {
+        &self.m_name
+    }
+
+    /// `RNAME`: the mailbox of the person responsible for this zone.
+    pub fn r_name(&self) -> &str {
+        &self.r_name
+    }
+
+    /// `SERIAL`: the version number of the original copy of the zone.
+    pub fn serial(&self) -> u32 {
+        self.serial
+    }
+
+    /// `REFRESH`: time interval (in seconds) before the zone should be refreshed.
+    pub fn refresh(&self) -> i32 {
+        self.refresh
+    }
+
+    /// `RETRY`: time interval (in seconds) that should elapse before a failed refresh should be retried.
+    pub fn retry(&self) -> i32 {
+        self.retry
+    }
+
+    /// `EXPIRE`: time value (in seconds) that specifies the upper limit on the time interval
+    /// that can elapse before the zone is no longer authoritative.
+    pub fn expire(&self) -> i32 {
+        self.expire
+    }
+
+    /// `MINIMUM`: the minimum TTL field that should be exported with any RR from this zone.
+    /// It's also the TTL of negative responses, see [RFC 2308](https://www.rfc-editor.org/rfc/rfc2308.html).
+    pub fn minimum(&self) -> u32 {
+        self.minimum
+    }
+}
+
 impl RecordData {
     /// Returns the Question and the position where it ends
+    ///
+    /// RDATA is parsed by the parser of the type, see [`register_rdata_parser`].
     pub fn from_response(
         buf: &[u8],
         record_type: &RecordType,
         start_pos: usize,
     ) -> Result<(RecordData, usize), Error> {
-        match record_type {
-            RecordType::A => Self::parse_a(buf, start_pos),
-            RecordType::AAAA => Self::parse_aaaa(buf, start_pos),
-            RecordType::CNAME => Self::parse_cname(buf, start_pos),
-            RecordType::NS => Self::parse_ns(buf, start_pos),
-            RecordType::SOA => Self::parse_soa(buf, start_pos),
-            _ => unimplemented!(),
-        }
+        parser::parse(buf, record_type.to_u16(), start_pos)
+    }
+
+    /// Keep the rest of RDATA as it is, which needs the buffer to end where RDATA ends.
+    ///
+    /// See [RFC 3597, 5. Text Representation](https://www.rfc-editor.org/rfc/rfc3597.html).
+    fn parse_unknown(buf: &[u8], start_pos: usize, type_code: u16) -> (RecordData, usize) {
+        let data = buf.get(start_pos..).unwrap_or_default().to_vec();
+        let end = start_pos + data.len();
+
+        (RecordData::Unknown { type_code, data }, end)
     }
 
     /// A record has fixed 32 bit IPv4 data
@@ -215,6 +397,49 @@ impl RecordData {
         Ok((RecordData::NS(domain), domain_end))
     }
 
+    fn parse_ptr(buf: &[u8], start_pos: usize) -> Result<(RecordData, usize), Error> {
+        let (domain, domain_end) = utils::parse_domain(buf, start_pos)?;
+
+        Ok((RecordData::PTR(domain), domain_end))
+    }
+
+    /// The target of DNAME must not be compressed, but some servers compress it anyway,
+    /// so both are accepted.
+    ///
+    /// See [RFC 6672, 2.5. Names in RDATA](https://www.rfc-editor.org/rfc/rfc6672.html).
+    fn parse_dname(buf: &[u8], start_pos: usize) -> Result<(RecordData, usize), Error> {
+        let (domain, domain_end) = utils::parse_domain(buf, start_pos)?;
+
+        Ok((RecordData::DNAME(domain), domain_end))
+    }
+
+    /// MB record has the host which has the mailbox.
+    ///
+    /// See [RFC 1035, 3.3.3. MB RDATA format (EXPERIMENTAL)](https://www.rfc-editor.org/rfc/inline-errata/rfc1035.html).
+    fn parse_mb(buf: &[u8], start_pos: usize) -> Result<(RecordData, usize), Error> {
+        let (domain, domain_end) = utils::parse_domain(buf, start_pos)?;
+
+        Ok((RecordData::MB(domain), domain_end))
+    }
+
+    /// MG record has a mailbox which is a member of the mail group.
+    ///
+    /// See [RFC 1035, 3.3.6. MG RDATA format (EXPERIMENTAL)](https://www.rfc-editor.org/rfc/inline-errata/rfc1035.html).
+    fn parse_mg(buf: &[u8], start_pos: usize) -> Result<(RecordData, usize), Error> {
+        let (domain, domain_end) = utils::parse_domain(buf, start_pos)?;
+
+        Ok((RecordData::MG(domain), domain_end))
+    }
+
+    /// MR record has the mailbox which is the proper rename of the owner mailbox.
+    ///
+    /// See [RFC 1035, 3.3.8. MR RDATA format (EXPERIMENTAL)](https://www.rfc-editor.org/rfc/inline-errata/rfc1035.html).
+    fn parse_mr(buf: &[u8], start_pos: usize) -> Result<(RecordData, usize), Error> {
+        let (domain, domain_end) = utils::parse_domain(buf, start_pos)?;
+
+        Ok((RecordData::MR(domain), domain_end))
+    }
+
     fn parse_soa(buf: &[u8], start_pos: usize) -> Result<(RecordData, usize), Error> {
         let (m_name, domain_end) = utils::parse_domain(buf, start_pos)?;
         let (r_name, domain_end) = utils::parse_domain(buf, domain_end)?;
@@ -277,6 +502,577 @@ impl RecordData {
             len,
         ))
     }
+
+    /// MX record has a 16 bit preference followed by the domain of the exchange.
+    fn parse_mx(buf: &[u8], start_pos: usize) -> Result<(RecordData, usize), Error> {
+        if buf.len() < start_pos + 2 {
+            return Err(Error::ResolverError(
+                "can't parse MX record preference".into(),
+            ));
+        }
+
+        let preference = u16::from_be_bytes([buf[start_pos], buf[start_pos + 1]]);
+        let (exchange, domain_end) = utils::parse_domain(buf, start_pos + 2)?;
+
+        Ok((
+            RecordData::MX {
+                preference,
+                exchange,
+            },
+            domain_end,
+        ))
+    }
+
+    /// NAPTR record has 16 bit order and preference, 3 character-strings (flags, services, and regexp),
+    /// and the domain of the replacement.
+    ///
+    /// See [RFC 3403, 4.1 Packet Format](https://www.rfc-editor.org/rfc/rfc3403.html).
+    fn parse_naptr(buf: &[u8], start_pos: usize) -> Result<(RecordData, usize), Error> {
+        if buf.len() < start_pos + 4 {
+            return Err(Error::ResolverError(
+                "can't parse NAPTR record order and preference".into(),
+            ));
+        }
+
+        let order = u16::from_be_bytes([buf[start_pos], buf[start_pos + 1]]);
+        let preference = u16::from_be_bytes([buf[start_pos + 2], buf[start_pos + 3]]);
+        let (flags, pos) = utils::parse_character_string(buf, start_pos + 4)?;
+        let (services, pos) = utils::parse_character_string(buf, pos)?;
+        let (regexp, pos) = utils::parse_character_string(buf, pos)?;
+        let (replacement, domain_end) = utils::parse_domain(buf, pos)?;
+
+        Ok((
+            RecordData::NAPTR {
+                order,
+                preference,
+                flags,
+                services,
+                regexp,
+                replacement,
+            },
+            domain_end,
+        ))
+    }
+
+    /// SSHFP record has 2 one octet fields (algorithm and fingerprint type),
+    /// and the fingerprint taking up the rest of RDATA.
+    ///
+    /// The buffer is expected to end where RDATA ends, see [`ResourceRecord::from_response`].
+    ///
+    /// See [RFC 4255, 3.1. The SSHFP RDATA Format](https://www.rfc-editor.org/rfc/rfc4255.html).
+    fn parse_sshfp(buf: &[u8], start_pos: usize) -> Result<(RecordData, usize), Error> {
+        if buf.len() < start_pos + 2 {
+            return Err(Error::ResolverError(format!(
+                "can't parse SSHFP record with length {}, expect at least {}",
+                buf.len(),
+                start_pos + 2
+            )));
+        }
+
+        Ok((
+            RecordData::SSHFP {
+                algorithm: buf[start_pos],
+                fp_type: buf[start_pos + 1],
+                fingerprint: buf[start_pos + 2..].to_vec(),
+            },
+            buf.len(),
+        ))
+    }
+
+    /// DS record has a 16 bit key tag, 2 one octet fields (algorithm and digest type),
+    /// and the digest of the DNSKEY taking up the rest of RDATA.
+    ///
+    /// The buffer is expected to end where RDATA ends, see [`ResourceRecord::from_response`].
+    ///
+    /// See [RFC 4034, 5.1. DS RDATA Wire Format](https://www.rfc-editor.org/rfc/rfc4034.html).
+    fn parse_ds(buf: &[u8], start_pos: usize) -> Result<(RecordData, usize), Error> {
+        if buf.len() < start_pos + 4 {
+            return Err(Error::ResolverError(format!(
+                "can't parse DS record with length {}, expect at least {}",
+                buf.len(),
+                start_pos + 4
+            )));
+        }
+
+        Ok((
+            RecordData::DS {
+                key_tag: u16::from_be_bytes([buf[start_pos], buf[start_pos + 1]]),
+                algorithm: buf[start_pos + 2],
+                digest_type: buf[start_pos + 3],
+                digest: buf[start_pos + 4..].to_vec(),
+            },
+            buf.len(),
+        ))
+    }
+
+    /// DNSKEY record has 16 bit flags, 2 one octet fields (protocol and algorithm),
+    /// and the public key taking up the rest of RDATA.
+    ///
+    /// The buffer is expected to end where RDATA ends, see [`ResourceRecord::from_response`].
+    ///
+    /// See [RFC 4034, 2.1. DNSKEY RDATA Wire Format](https://www.rfc-editor.org/rfc/rfc4034.html).
+    fn parse_dnskey(buf: &[u8], start_pos: usize) -> Result<(RecordData, usize), Error> {
+        if buf.len() < start_pos + 4 {
+            return Err(Error::ResolverError(format!(
+                "can't parse DNSKEY record with length {}, expect at least {}",
+                buf.len(),
+                start_pos + 4
+            )));
+        }
+
+        Ok((
+            RecordData::DNSKEY {
+                flags: u16::from_be_bytes([buf[start_pos], buf[start_pos + 1]]),
+                protocol: buf[start_pos + 2],
+                algorithm: buf[start_pos + 3],
+                public_key: buf[start_pos + 4..].to_vec(),
+            },
+            buf.len(),
+        ))
+    }
+
+    /// RRSIG record has 18 octets of fixed fields, the signer name, and the signature taking up the rest of RDATA.
+    /// Expiration and inception are seconds since the epoch, modulo 2^32.
+    ///
+    /// The signer name must not be compressed, so a pointer is rejected rather than followed.
+    ///
+    /// See [RFC 4034, 3.1. RRSIG RDATA Wire Format](https://www.rfc-editor.org/rfc/rfc4034.html).
+    fn parse_rrsig(buf: &[u8], start_pos: usize) -> Result<(RecordData, usize), Error> {
+        if buf.len() < start_pos + 18 {
+            return Err(Error::ResolverError(format!(
+                "can't parse RRSIG record with length {}, expect at least {}",
+                buf.len(),
+                start_pos + 18
+            )));
+        }
+
+        let u16_at = |pos: usize| u16::from_be_bytes([buf[pos], buf[pos + 1]]);
+        let u32_at =
+            |pos: usize| u32::from_be_bytes([buf[pos], buf[pos + 1], buf[pos + 2], buf[pos + 3]]);
+        let (signer_name, name_end) = utils::parse_uncompressed_domain(buf, start_pos + 18)?;
+
+        Ok((
+            RecordData::RRSIG {
+                type_covered: u16_at(start_pos),
+                algorithm: buf[start_pos + 2],
+                labels: buf[start_pos + 3],
+                original_ttl: u32_at(start_pos + 4),
+                expiration: u32_at(start_pos + 8),
+                inception: u32_at(start_pos + 12),
+                key_tag: u16_at(start_pos + 16),
+                signer_name,
+                signature: buf[name_end..].to_vec(),
+            },
+            buf.len(),
+        ))
+    }
+
+    /// TLSA record has 3 one octet fields (certificate usage, selector, and matching type),
+    /// and the certificate association data taking up the rest of RDATA.
+    ///
+    /// The buffer is expected to end where RDATA ends, see [`ResourceRecord::from_response`].
+    ///
+    /// See [RFC 6698, 2.1. TLSA RDATA Wire Format](https://www.rfc-editor.org/rfc/rfc6698.html).
+    fn parse_tlsa(buf: &[u8], start_pos: usize) -> Result<(RecordData, usize), Error> {
+        if buf.len() < start_pos + 3 {
+            return Err(Error::ResolverError(format!(
+                "can't parse TLSA record with length {}, expect at least {}",
+                buf.len(),
+                start_pos + 3
+            )));
+        }
+
+        Ok((
+            RecordData::TLSA {
+                usage: buf[start_pos],
+                selector: buf[start_pos + 1],
+                matching_type: buf[start_pos + 2],
+                cert_association: buf[start_pos + 3..].to_vec(),
+            },
+            buf.len(),
+        ))
+    }
+
+    /// WKS record has a 32 bit IPv4 address, an 8 bit IP protocol number,
+    /// and a bitmap of the ports taking up the rest of RDATA.
+    ///
+    /// The buffer is expected to end where RDATA ends, see [`ResourceRecord::from_response`].
+    ///
+    /// See [RFC 1035, 3.4.2. WKS RDATA format](https://www.rfc-editor.org/rfc/inline-errata/rfc1035.html).
+    fn parse_wks(buf: &[u8], start_pos: usize) -> Result<(RecordData, usize), Error> {
+        if buf.len() < start_pos + 5 {
+            return Err(Error::ResolverError(format!(
+                "can't parse WKS record with length {}, expect at least {}",
+                buf.len(),
+                start_pos + 5
+            )));
+        }
+
+        let address = Ipv4Addr::new(
+            buf[start_pos],
+            buf[start_pos + 1],
+            buf[start_pos + 2],
+            buf[start_pos + 3],
+        );
+
+        Ok((
+            RecordData::WKS {
+                address,
+                protocol: buf[start_pos + 4],
+                bitmap: buf[start_pos + 5..].to_vec(),
+            },
+            buf.len(),
+        ))
+    }
+
+    /// HINFO record has 2 character-strings, the CPU and the OS of the host.
+    /// It's also how some name servers answer ANY queries, with "RFC8482" as the CPU,
+    /// see [RFC 8482, 4.2. Answer with a Synthesized HINFO RRset](https://www.rfc-editor.org/rfc/rfc8482.html).
+    ///
+    /// The buffer is expected to end where RDATA ends, see [`ResourceRecord::from_response`].
+    fn parse_hinfo(buf: &[u8], start_pos: usize) -> Result<(RecordData, usize), Error> {
+        let (cpu, pos) = utils::parse_character_string(buf, start_pos)?;
+        let (os, pos) = utils::parse_character_string(buf, pos)?;
+
+        Ok((RecordData::HINFO { cpu, os }, pos))
+    }
+
+    /// TXT record contains one or more character-strings taking up the whole RDATA.
+    ///
+    /// The buffer is expected to end where RDATA ends, see [`ResourceRecord::from_response`].
+    fn parse_txt(buf: &[u8], start_pos: usize) -> Result<(RecordData, usize), Error> {
+        let (texts, pos) = Self::parse_character_strings(buf, start_pos)?;
+
+        Ok((RecordData::TXT(texts), pos))
+    }
+
+    /// SPF record has the same RDATA as TXT.
+    ///
+    /// See [RFC 7208, 3.1. DNS Resource Records](https://www.rfc-editor.org/rfc/rfc7208.html).
+    fn parse_spf(buf: &[u8], start_pos: usize) -> Result<(RecordData, usize), Error> {
+        let (texts, pos) = Self::parse_character_strings(buf, start_pos)?;
+
+        Ok((RecordData::SPF(texts), pos))
+    }
+
+    /// Character-strings till the end of the buffer.
+    fn parse_character_strings(
+        buf: &[u8],
+        start_pos: usize,
+    ) -> Result<(Vec<String>, usize), Error> {
+        let mut texts = vec![];
+        let mut pos = start_pos;
+
+        while pos < buf.len() {
+            let (text, text_end) = utils::parse_character_string(buf, pos)?;
+            texts.push(text);
+            pos = text_end;
+        }
+
+        Ok((texts, pos))
+    }
+
+    /// Build RDATA. Domains are not compressed.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut r_data = vec![];
+        self.write(&mut r_data, None);
+
+        r_data
+    }
+
+    /// Append RDATA to `r_data`, compressing domains with `names` if there is one.
+    ///
+    /// Only domains in RDATA of the types in RFC 1035 are compressed. Servers that don't know a later type can't
+    /// decompress its RDATA, so its domains are always written as they are.
+    ///
+    /// See [RFC 3597, 4. Domain Name Compression](https://www.rfc-editor.org/rfc/rfc3597.html).
+    fn write(&self, r_data: &mut Vec<u8>, mut names: Option<&mut NameCompressor>) {
+        let mut write_domain = |r_data: &mut Vec<u8>, domain: &str| match names.as_deref_mut() {
+            Some(names) => names.write(r_data, domain),
+            None => r_data.extend(utils::encode_domain(domain)),
+        };
+
+        match self {
+            RecordData::CNAME(domain)
+            | RecordData::NS(domain)
+            | RecordData::PTR(domain)
+            | RecordData::MB(domain)
+            | RecordData::MG(domain)
+            | RecordData::MR(domain) => write_domain(r_data, domain),
+            RecordData::DNAME(domain) => r_data.extend(utils::encode_domain(domain)),
+            RecordData::A(ip) => r_data.extend_from_slice(&ip.octets()),
+            RecordData::AAAA(ip) => r_data.extend_from_slice(&ip.octets()),
+            RecordData::SOA(soa) => {
+                write_domain(r_data, &soa.m_name);
+                write_domain(r_data, &soa.r_name);
+                r_data.extend_from_slice(&soa.serial.to_be_bytes());
+                r_data.extend_from_slice(&soa.refresh.to_be_bytes());
+                r_data.extend_from_slice(&soa.retry.to_be_bytes());
+                r_data.extend_from_slice(&soa.expire.to_be_bytes());
+                r_data.extend_from_slice(&soa.minimum.to_be_bytes());
+            }
+            RecordData::MX {
+                preference,
+                exchange,
+            } => {
+                r_data.extend_from_slice(&preference.to_be_bytes());
+                write_domain(r_data, exchange);
+            }
+            RecordData::TXT(texts) | RecordData::SPF(texts) => {
+                for text in texts {
+                    Self::push_character_string(r_data, text);
+                }
+            }
+            RecordData::NAPTR {
+                order,
+                preference,
+                flags,
+                services,
+                regexp,
+                replacement,
+            } => {
+                r_data.extend_from_slice(&order.to_be_bytes());
+                r_data.extend_from_slice(&preference.to_be_bytes());
+                for text in [flags, services, regexp] {
+                    Self::push_character_string(r_data, text);
+                }
+                r_data.extend(utils::encode_domain(replacement));
+            }
+            RecordData::TLSA {
+                usage,
+                selector,
+                matching_type,
+                cert_association,
+            } => {
+                r_data.extend_from_slice(&[*usage, *selector, *matching_type]);
+                r_data.extend_from_slice(cert_association);
+            }
+            RecordData::SSHFP {
+                algorithm,
+                fp_type,
+                fingerprint,
+            } => {
+                r_data.extend_from_slice(&[*algorithm, *fp_type]);
+                r_data.extend_from_slice(fingerprint);
+            }
+            RecordData::DS {
+                key_tag,
+                algorithm,
+                digest_type,
+                digest,
+            } => {
+                r_data.extend_from_slice(&key_tag.to_be_bytes());
+                r_data.extend_from_slice(&[*algorithm, *digest_type]);
+                r_data.extend_from_slice(digest);
+            }
+            RecordData::DNSKEY {
+                flags,
+                protocol,
+                algorithm,
+                public_key,
+            } => {
+                r_data.extend_from_slice(&flags.to_be_bytes());
+                r_data.extend_from_slice(&[*protocol, *algorithm]);
+                r_data.extend_from_slice(public_key);
+            }
+            RecordData::RRSIG {
+                type_covered,
+                algorithm,
+                labels,
+                original_ttl,
+                expiration,
+                inception,
+                key_tag,
+                signer_name,
+                signature,
+            } => {
+                r_data.extend_from_slice(&type_covered.to_be_bytes());
+                r_data.extend_from_slice(&[*algorithm, *labels]);
+                r_data.extend_from_slice(&original_ttl.to_be_bytes());
+                r_data.extend_from_slice(&expiration.to_be_bytes());
+                r_data.extend_from_slice(&inception.to_be_bytes());
+                r_data.extend_from_slice(&key_tag.to_be_bytes());
+                r_data.extend(utils::encode_domain(signer_name));
+                r_data.extend_from_slice(signature);
+            }
+            RecordData::WKS {
+                address,
+                protocol,
+                bitmap,
+            } => {
+                r_data.extend_from_slice(&address.octets());
+                r_data.push(*protocol);
+                r_data.extend_from_slice(bitmap);
+            }
+            RecordData::HINFO { cpu, os } => {
+                Self::push_character_string(r_data, cpu);
+                Self::push_character_string(r_data, os);
+            }
+            RecordData::OPT(edns) => r_data.extend(edns.options_bytes()),
+            RecordData::Unknown { data, .. } | RecordData::Custom { data, .. } => {
+                r_data.extend_from_slice(data)
+            }
+        }
+    }
+
+    /// A `<character-string>` is at most 255 characters long, the rest is dropped.
+    fn push_character_string(buf: &mut Vec<u8>, text: &str) {
+        let text = &text.as_bytes()[..text.len().min(255)];
+        buf.push(text.len() as u8);
+        buf.extend_from_slice(text);
+    }
+}
+
+impl fmt::Display for RecordData {
+    /// Format RDATA the way it's presented in zone files.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecordData::CNAME(domain)
+            | RecordData::NS(domain)
+            | RecordData::PTR(domain)
+            | RecordData::DNAME(domain)
+            | RecordData::MB(domain)
+            | RecordData::MG(domain)
+            | RecordData::MR(domain) => write!(f, "{domain}."),
+            RecordData::A(ip) => write!(f, "{ip}"),
+            RecordData::AAAA(ip) => write!(f, "{ip}"),
+            RecordData::SOA(soa) => write!(
+                f,
+                "{}. {}. {} {} {} {} {}",
+                soa.m_name, soa.r_name, soa.serial, soa.refresh, soa.retry, soa.expire, soa.minimum
+            ),
+            RecordData::MX {
+                preference,
+                exchange,
+            } => write!(f, "{preference} {exchange}."),
+            RecordData::NAPTR {
+                order,
+                preference,
+                flags,
+                services,
+                regexp,
+                replacement,
+            } => write!(
+                f,
+                "{order} {preference} {flags:?} {services:?} {regexp:?} {replacement}."
+            ),
+            RecordData::TLSA {
+                usage,
+                selector,
+                matching_type,
+                cert_association,
+            } => {
+                write!(f, "{usage} {selector} {matching_type} ")?;
+                write_hex(f, cert_association)
+            }
+            RecordData::SSHFP {
+                algorithm,
+                fp_type,
+                fingerprint,
+            } => {
+                write!(f, "{algorithm} {fp_type} ")?;
+                write_hex(f, fingerprint)
+            }
+            RecordData::DS {
+                key_tag,
+                algorithm,
+                digest_type,
+                digest,
+            } => {
+                write!(f, "{key_tag} {algorithm} {digest_type} ")?;
+                write_hex(f, digest)
+            }
+            RecordData::DNSKEY {
+                flags,
+                protocol,
+                algorithm,
+                public_key,
+            } => {
+                write!(f, "{flags} {protocol} {algorithm} ")?;
+                write_base64(f, public_key)
+            }
+            RecordData::RRSIG {
+                type_covered,
+                algorithm,
+                labels,
+                original_ttl,
+                expiration,
+                inception,
+                key_tag,
+                signer_name,
+                signature,
+            } => {
+                // See [RFC 3597, 5. Text Representation](https://www.rfc-editor.org/rfc/rfc3597.html).
+                match RecordType::try_from(*type_covered) {
+                    Ok(r_type) => write!(f, "{r_type} ")?,
+                    Err(_) => write!(f, "TYPE{type_covered} ")?,
+                }
+                // Times may be presented as seconds since the epoch, see RFC 4034, 3.2.
+                write!(
+                    f,
+                    "{algorithm} {labels} {original_ttl} {expiration} {inception} {key_tag} {signer_name}. "
+                )?;
+                write_base64(f, signature)
+            }
+            RecordData::WKS {
+                address,
+                protocol,
+                bitmap,
+            } => {
+                write!(f, "{address} {protocol}")?;
+                // The first bit of the bitmap is port 0, the most significant bit comes first.
+                for (index, byte) in bitmap.iter().enumerate() {
+                    for bit in 0..8 {
+                        if byte & (0x80 >> bit) != 0 {
+                            write!(f, " {}", index * 8 + bit)?;
+                        }
+                    }
+                }
+                Ok(())
+            }
+            RecordData::HINFO { cpu, os } => write!(f, "{cpu:?} {os:?}"),
+            RecordData::OPT(edns) => write!(f, "{edns}"),
+            RecordData::Unknown { data, .. } => {
+                let hex: String = data.iter().map(|byte| format!("{byte:02x}")).collect();
+                write!(f, "\\# {} {hex}", data.len())
+            }
+            RecordData::Custom { text, .. } => write!(f, "{text}"),
+            RecordData::TXT(texts) | RecordData::SPF(texts) => {
+                let quoted: Vec<_> = texts.iter().map(|text| format!("{text:?}")).collect();
+                write!(f, "{}", quoted.join(" "))
+            }
+        }
+    }
+}
+
+/// Binary RDATA like hashes is presented in hex in zone files.
+fn write_hex(f: &mut fmt::Formatter<'_>, bytes: &[u8]) -> fmt::Result {
+    bytes.iter().try_for_each(|byte| write!(f, "{byte:02X}"))
+}
+
+/// Keys are presented in base64 in zone files.
+///
+/// See [RFC 4648, 4. Base 64 Encoding](https://www.rfc-editor.org/rfc/rfc4648.html).
+fn write_base64(f: &mut fmt::Formatter<'_>, bytes: &[u8]) -> fmt::Result {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    // Every 3 bytes become 4 characters of 6 bits, and the last group is padded with `=`.
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0u32, |group, (i, byte)| {
+            group | u32::from(*byte) << (16 - 8 * i)
+        });
+        for i in 0..4 {
+            match i <= chunk.len() {
+                true => {
+                    let index = (group >> (18 - 6 * i)) & 0x3F;
+                    write!(f, "{}", char::from(ALPHABET[index as usize]))?
+                }
+                false => f.write_str("=")?,
+            }
+        }
+    }
+
+    Ok(())
 }
 
 // 4.1.3. Resource record format
@@ -307,13 +1103,16 @@ impl RecordData {
 //     +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
 //
 /// See See [RFC 1035, section 4.1.3. Resource record format](https://www.rfc-editor.org/rfc/inline-errata/rfc1035.html).
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ResourceRecord {
     /// `NAME`: a domain name to which this resource record pertains
     pub name: String,
     /// `TYPE`: two octets containing one of the RR type codes.
     /// This field specifies the meaning of the data in the RDATA field.
-    pub r_type: RecordType,
+    /// It's `None` for a type [`RecordType`] doesn't have, whose type code is kept in RDATA, see
+    /// [`ResourceRecord::type_code`].
+    pub r_type: Option<RecordType>,
     // `CLASS`: two octets which specify the class of the data in the RDATA field.
     pub r_class: RecordClass,
     // `TTL`: a 32 bit unsigned integer that specifies the time interval (in seconds) that the resource record may be cached before it should be discarded.
@@ -340,7 +1139,26 @@ impl ResourceRecord {
             ));
         }
 
-        let rr_type = RecordType::try_from(u16::from_be_bytes([buf[name_end], buf[name_end + 1]]))?;
+        let type_code = u16::from_be_bytes([buf[name_end], buf[name_end + 1]]);
+        // A type we don't know doesn't stop us from parsing the rest, as RDLENGTH tells where it ends.
+        let rr_type = RecordType::try_from(type_code).ok();
+        let rd_length = u16::from_be_bytes([buf[name_end + 8], buf[name_end + 9]]);
+
+        // The OPT pseudo-record reuses CLASS and TTL for other purposes, so they are kept in RDATA instead.
+        if rr_type == Some(RecordType::OPT) {
+            let (edns, opt_end) = Edns::from_response(buf, name_end)?;
+            let rr = Self {
+                name,
+                r_type: rr_type,
+                r_class: RecordClass::IN,
+                ttl: 0,
+                rd_length,
+                r_data: RecordData::OPT(edns),
+            };
+
+            return Ok((rr, opt_end));
+        }
+
         let rr_class =
             RecordClass::try_from(u16::from_be_bytes([buf[name_end + 2], buf[name_end + 3]]))?;
         let ttl = u32::from_be_bytes([
@@ -349,7 +1167,6 @@ impl ResourceRecord {
             buf[name_end + 6],
             buf[name_end + 7],
         ]);
-        let rd_length = u16::from_be_bytes([buf[name_end + 8], buf[name_end + 9]]);
         let rdata_len = name_end + 10 + rd_length as usize;
 
         if buf.len() < rdata_len {
@@ -360,7 +1177,17 @@ impl ResourceRecord {
             )));
         }
 
-        let (r_data, rdata_end) = RecordData::from_response(buf, &rr_type, name_end + 10)?;
+        // Only hand RDATA (and everything before it, for compressed domains) to the parsers,
+        // so variable length data like TXT knows where to stop.
+        let (r_data, rdata_end) = parser::parse(&buf[..rdata_len], type_code, name_end + 10)?;
+        // The next record starts where RDLENGTH says, so RDATA left unparsed, or a parser reading past it,
+        // would have the rest of the message read from the wrong place.
+        if rdata_end != rdata_len {
+            return Err(Error::ResolverError(format!(
+                "RDATA is {rd_length} bytes, but {} bytes are parsed",
+                rdata_end as isize - (name_end + 10) as isize
+            )));
+        }
 
         let rr = Self {
             name,
@@ -371,7 +1198,56 @@ impl ResourceRecord {
             r_data,
         };
 
-        Ok((rr, rdata_end))
+        Ok((rr, rdata_len))
+    }
+
+    /// Build the resource record. `RDLENGTH` is calculated from RDATA rather than taken from `rd_length`.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        if let RecordData::OPT(edns) = &self.r_data {
+            return edns.to_bytes();
+        }
+
+        let r_data = self.r_data.to_bytes();
+
+        let mut rr = utils::encode_domain(&self.name);
+        rr.extend_from_slice(&self.type_code().to_be_bytes());
+        rr.extend_from_slice(&self.r_class.to_u16().to_be_bytes());
+        rr.extend_from_slice(&self.ttl.to_be_bytes());
+        rr.extend_from_slice(&(r_data.len() as u16).to_be_bytes());
+        rr.extend(r_data);
+
+        rr
+    }
+
+    /// Append the resource record to `buf`, which is the message written so far, compressing domains with `names`.
+    pub(crate) fn write_compressed(&self, buf: &mut Vec<u8>, names: &mut NameCompressor) {
+        if let RecordData::OPT(edns) = &self.r_data {
+            buf.extend(edns.to_bytes());
+            return;
+        }
+
+        names.write(buf, &self.name);
+        buf.extend_from_slice(&self.type_code().to_be_bytes());
+        buf.extend_from_slice(&self.r_class.to_u16().to_be_bytes());
+        buf.extend_from_slice(&self.ttl.to_be_bytes());
+
+        // RDLENGTH is only known once RDATA is written.
+        let rd_length_pos = buf.len();
+        buf.extend_from_slice(&[0, 0]);
+        self.r_data.write(buf, Some(names));
+        let rd_length = (buf.len() - rd_length_pos - 2) as u16;
+        buf[rd_length_pos..rd_length_pos + 2].copy_from_slice(&rd_length.to_be_bytes());
+    }
+
+    /// The type code on the wire, which is only kept in RDATA for types we don't know.
+    pub fn type_code(&self) -> u16 {
+        match self.r_data {
+            RecordData::Unknown { type_code, .. } | RecordData::Custom { type_code, .. } => {
+                type_code
+            }
+            // A record built without a type has nothing else to tell, and 0 is reserved.
+            _ => self.r_type.map_or(0, |r_type| r_type.to_u16()),
+        }
     }
 
     pub fn ipv4_ip(&self) -> Option<Ipv4Addr> {
@@ -381,3 +1257,638 @@ impl ResourceRecord {
         }
     }
 }
+
+impl fmt::Display for ResourceRecord {
+    /// Format the record the way it's presented in zone files, e.g., `blog.wtcx.dev.  300  IN  A  1.2.3.4`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let r_type = match self.r_type {
+            Some(r_type) => r_type.to_string(),
+            // See [RFC 3597, 5. Text Representation](https://www.rfc-editor.org/rfc/rfc3597.html).
+            None => format!("TYPE{}", self.type_code()),
+        };
+
+        write!(
+            f,
+            "{}.  {}  {}  {}  {}",
+            self.name, self.ttl, self.r_class, r_type, self.r_data
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ClientSubnet;
+    use core::net::IpAddr;
+
+    fn record(name: &str, r_type: RecordType, r_data: RecordData) -> ResourceRecord {
+        ResourceRecord {
+            name: name.into(),
+            r_type: Some(r_type),
+            r_class: RecordClass::IN,
+            ttl: 300,
+            rd_length: 0,
+            r_data,
+        }
+    }
+
+    #[test]
+    fn test_display_a_record() {
+        let rr = record(
+            "blog.wtcx.dev",
+            RecordType::A,
+            RecordData::A(Ipv4Addr::new(1, 2, 3, 4)),
+        );
+
+        assert_eq!(rr.to_string(), "blog.wtcx.dev.  300  IN  A  1.2.3.4");
+    }
+
+    #[test]
+    fn test_display_cname_record() {
+        let rr = record(
+            "www.wtcx.dev",
+            RecordType::CNAME,
+            RecordData::CNAME("blog.wtcx.dev".into()),
+        );
+
+        assert_eq!(
+            rr.to_string(),
+            "www.wtcx.dev.  300  IN  CNAME  blog.wtcx.dev."
+        );
+    }
+
+    #[test]
+    fn test_record_equality() {
+        let a = record(
+            "blog.wtcx.dev",
+            RecordType::A,
+            RecordData::A(Ipv4Addr::new(1, 2, 3, 4)),
+        );
+        let b = record(
+            "blog.wtcx.dev",
+            RecordType::A,
+            RecordData::A(Ipv4Addr::new(1, 2, 3, 4)),
+        );
+        let c = record(
+            "blog.wtcx.dev",
+            RecordType::A,
+            RecordData::A(Ipv4Addr::new(5, 6, 7, 8)),
+        );
+
+        assert_eq!(a, b);
+        assert_eq!(a.clone(), a);
+        assert_ne!(a, c);
+    }
+
+    #[test]
+    fn test_parse_txt_record() {
+        // 2 character-strings: "v=spf1" and "-all"
+        let buf = b"\x06v=spf1\x04-all";
+        let (r_data, end) = RecordData::from_response(buf, &RecordType::TXT, 0).unwrap();
+
+        assert_eq!(end, buf.len());
+        assert_eq!(r_data.to_string(), r#""v=spf1" "-all""#);
+    }
+
+    #[test]
+    fn test_parse_spf_record() {
+        let buf = b"\x06v=spf1\x04-all";
+        let (r_data, end) = RecordData::from_response(buf, &RecordType::SPF, 0).unwrap();
+
+        assert_eq!(end, buf.len());
+        assert_eq!(
+            r_data,
+            RecordData::SPF(vec!["v=spf1".into(), "-all".into()])
+        );
+        assert_eq!(r_data.to_string(), r#""v=spf1" "-all""#);
+        assert_eq!(r_data.to_bytes(), buf);
+    }
+
+    #[test]
+    fn test_parse_dname_record() {
+        let buf = b"\x07example\x03net\x00";
+        let (r_data, end) = RecordData::from_response(buf, &RecordType::DNAME, 0).unwrap();
+
+        assert_eq!(end, buf.len());
+        assert!(matches!(r_data, RecordData::DNAME(ref domain) if domain == "example.net"));
+
+        // The target points to "example.net" at the start of the buffer.
+        let buf = b"\x07example\x03net\x00\x03new\xc0\x00";
+        let (r_data, end) = RecordData::from_response(buf, &RecordType::DNAME, 13).unwrap();
+
+        assert_eq!(end, buf.len());
+        assert!(matches!(r_data, RecordData::DNAME(ref domain) if domain == "new.example.net"));
+    }
+
+    #[test]
+    fn test_parse_mailbox_records() {
+        // Each target points to "wtcx.dev" at the start of the buffer.
+        let buf = b"\x04wtcx\x03dev\x00\x04mail\xc0\x00";
+        let (r_data, end) = RecordData::from_response(buf, &RecordType::MB, 10).unwrap();
+
+        assert_eq!(end, buf.len());
+        assert_eq!(r_data, RecordData::MB("mail.wtcx.dev".into()));
+
+        let buf = b"\x04wtcx\x03dev\x00\x05alice\xc0\x00";
+        let (r_data, end) = RecordData::from_response(buf, &RecordType::MG, 10).unwrap();
+
+        assert_eq!(end, buf.len());
+        assert_eq!(r_data, RecordData::MG("alice.wtcx.dev".into()));
+
+        let buf = b"\x04wtcx\x03dev\x00\x03bob\xc0\x00";
+        let (r_data, end) = RecordData::from_response(buf, &RecordType::MR, 10).unwrap();
+
+        assert_eq!(end, buf.len());
+        assert_eq!(r_data, RecordData::MR("bob.wtcx.dev".into()));
+        assert_eq!(r_data.to_string(), "bob.wtcx.dev.");
+    }
+
+    #[test]
+    fn test_rdata_not_matching_rd_length() {
+        // An A record with 6 bytes of RDATA
+        let mut buf = utils::encode_domain("blog.wtcx.dev");
+        buf.extend_from_slice(&[0, 1, 0, 1, 0, 0, 1, 44, 0, 6, 1, 2, 3, 4, 5, 6]);
+
+        assert_eq!(
+            ResourceRecord::from_response(&buf, 0).unwrap_err(),
+            Error::ResolverError("RDATA is 6 bytes, but 4 bytes are parsed".into())
+        );
+    }
+
+    #[test]
+    fn test_parse_opt_record() {
+        let mut edns = Edns::new(1232);
+        edns.flags = 0x8000;
+        let buf = edns.to_bytes();
+        let (rr, end) = ResourceRecord::from_response(&buf, 0).unwrap();
+
+        assert_eq!(end, buf.len());
+        assert_eq!(rr.r_type, Some(RecordType::OPT));
+        assert_eq!(rr.r_data, RecordData::OPT(edns));
+        assert_eq!(rr.to_bytes(), buf);
+        assert_eq!(
+            rr.r_data.to_string(),
+            "EDNS: version: 0, flags: do; udp: 1232"
+        );
+    }
+
+    #[test]
+    fn test_parse_opt_record_with_client_subnet() {
+        let subnet = ClientSubnet::new(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 123)), 24).unwrap();
+        let edns = Edns::new(1232).with_client_subnet(subnet);
+        let buf = edns.to_bytes();
+
+        // OPTION-CODE 8, OPTION-LENGTH 7, FAMILY 1, SOURCE PREFIX-LENGTH 24, SCOPE PREFIX-LENGTH 0,
+        // and the first 3 bytes of the address
+        assert_eq!(buf[9..], [0, 11, 0, 8, 0, 7, 0, 1, 24, 0, 192, 0, 2]);
+        assert_eq!(subnet.to_string(), "192.0.2.0/24/0");
+
+        let (rr, end) = ResourceRecord::from_response(&buf, 0).unwrap();
+        assert_eq!(end, buf.len());
+        assert_eq!(rr.r_data, RecordData::OPT(edns));
+        assert_eq!(rr.r_data.to_bytes(), buf[11..]);
+
+        assert!(ClientSubnet::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 33).is_err());
+    }
+
+    #[test]
+    fn test_parse_opt_record_with_broken_options() {
+        let mut buf = Edns::new(1232).to_bytes();
+        // ECS of an unknown family 3, followed by an option cut in the middle
+        let options = [0, 8, 0, 4, 0, 3, 0, 0, 0, 10, 0, 8, 1, 2];
+        buf[9..11].copy_from_slice(&(options.len() as u16).to_be_bytes());
+        buf.extend_from_slice(&options);
+
+        let (rr, end) = ResourceRecord::from_response(&buf, 0).unwrap();
+        assert_eq!(end, buf.len());
+        match rr.r_data {
+            RecordData::OPT(edns) => assert_eq!(edns.client_subnet(), None),
+            r_data => panic!("expect OPT, got {r_data:?}"),
+        }
+    }
+
+    #[test]
+    fn test_parse_naptr_record() {
+        let mut buf = vec![0, 100, 0, 10];
+        for text in ["u", "E2U+sip", "!^.*$!sip:info@example.com!"] {
+            buf.push(text.len() as u8);
+            buf.extend_from_slice(text.as_bytes());
+        }
+        // The replacement is the root
+        buf.push(0);
+
+        let (r_data, end) = RecordData::from_response(&buf, &RecordType::NAPTR, 0).unwrap();
+        let RecordData::NAPTR {
+            order,
+            preference,
+            ref flags,
+            ref services,
+            ref regexp,
+            ref replacement,
+        } = r_data
+        else {
+            panic!("expect NAPTR record, got {r_data:?}");
+        };
+
+        assert_eq!(end, buf.len());
+        assert_eq!((order, preference), (100, 10));
+        assert_eq!(flags, "u");
+        assert_eq!(services, "E2U+sip");
+        assert_eq!(regexp, "!^.*$!sip:info@example.com!");
+        assert_eq!(replacement, "");
+        assert_eq!(
+            r_data.to_string(),
+            r#"100 10 "u" "E2U+sip" "!^.*$!sip:info@example.com!" ."#
+        );
+    }
+
+    #[test]
+    fn test_parse_tlsa_record() {
+        // 3 1 1: DANE-EE, SubjectPublicKeyInfo, SHA-256
+        let mut buf = vec![3, 1, 1];
+        buf.extend(0..32);
+
+        let (r_data, end) = RecordData::from_response(&buf, &RecordType::TLSA, 0).unwrap();
+        let RecordData::TLSA {
+            usage,
+            selector,
+            matching_type,
+            ref cert_association,
+        } = r_data
+        else {
+            panic!("expect TLSA record, got {r_data:?}");
+        };
+
+        assert_eq!(end, buf.len());
+        assert_eq!((usage, selector, matching_type), (3, 1, 1));
+        assert_eq!(cert_association, &buf[3..]);
+        assert_eq!(
+            r_data.to_string(),
+            "3 1 1 000102030405060708090A0B0C0D0E0F101112131415161718191A1B1C1D1E1F"
+        );
+    }
+
+    #[test]
+    fn test_parse_tlsa_record_too_short() {
+        assert!(RecordData::from_response(&[3, 1], &RecordType::TLSA, 0).is_err());
+    }
+
+    #[test]
+    fn test_parse_ds_record() {
+        // The DS of example.com in RFC 8080, 6.1: key tag 3613, Ed25519, SHA-256
+        let digest = [
+            0x3A, 0xA5, 0xAB, 0x37, 0xEF, 0xCE, 0x57, 0xF7, 0x37, 0xFC, 0x16, 0x27, 0x01, 0x3F,
+            0xEE, 0x07, 0xBD, 0xF2, 0x41, 0xBD, 0x10, 0xF3, 0xB1, 0x96, 0x4A, 0xB5, 0x5C, 0x78,
+            0xE7, 0x9A, 0x30, 0x4B,
+        ];
+        let mut buf = vec![0x0E, 0x1D, 15, 2];
+        buf.extend(digest);
+
+        let (r_data, end) = RecordData::from_response(&buf, &RecordType::DS, 0).unwrap();
+
+        assert_eq!(end, buf.len());
+        assert_eq!(
+            r_data,
+            RecordData::DS {
+                key_tag: 3613,
+                algorithm: 15,
+                digest_type: 2,
+                digest: digest.to_vec(),
+            }
+        );
+        assert_eq!(
+            r_data.to_string(),
+            "3613 15 2 3AA5AB37EFCE57F737FC1627013FEE07BDF241BD10F3B1964AB55C78E79A304B"
+        );
+        assert!(RecordData::from_response(&buf[..3], &RecordType::DS, 0).is_err());
+    }
+
+    #[test]
+    fn test_parse_dnskey_record() {
+        // The DNSKEY of example.com in RFC 8080, 6.1: a KSK (257), protocol 3, Ed25519
+        let public_key = [
+            0x97, 0x4D, 0x96, 0xA2, 0x2D, 0x22, 0x4B, 0xC0, 0x1A, 0xDB, 0x91, 0x50, 0x91, 0x47,
+            0x7D, 0x44, 0xCC, 0xD9, 0x1C, 0x9A, 0x41, 0xA1, 0x14, 0x30, 0x01, 0x01, 0x17, 0xD5,
+            0x2C, 0x59, 0x24, 0x0E,
+        ];
+        let mut buf = vec![0x01, 0x01, 3, 15];
+        buf.extend(public_key);
+
+        let (r_data, end) = RecordData::from_response(&buf, &RecordType::DNSKEY, 0).unwrap();
+
+        assert_eq!(end, buf.len());
+        assert_eq!(
+            r_data,
+            RecordData::DNSKEY {
+                flags: 257,
+                protocol: 3,
+                algorithm: 15,
+                public_key: public_key.to_vec(),
+            }
+        );
+        assert_eq!(
+            r_data.to_string(),
+            "257 3 15 l02Woi0iS8Aa25FQkUd9RMzZHJpBoRQwAQEX1SxZJA4="
+        );
+        assert!(RecordData::from_response(&buf[..3], &RecordType::DNSKEY, 0).is_err());
+    }
+
+    fn rrsig_rdata(signer_name: &[u8]) -> Vec<u8> {
+        // A, ECDSA P-256 with SHA-256, 3 labels of blog.wtcx.dev, TTL 300
+        let mut buf = vec![0, 1, 13, 3, 0, 0, 1, 44];
+        buf.extend_from_slice(&1795132800u32.to_be_bytes());
+        buf.extend_from_slice(&1792540800u32.to_be_bytes());
+        buf.extend_from_slice(&34505u16.to_be_bytes());
+        buf.extend_from_slice(signer_name);
+        buf.extend([0xAB; 64]);
+        buf
+    }
+
+    #[test]
+    fn test_parse_rrsig_record() {
+        let buf = rrsig_rdata(b"\x04wtcx\x03dev\x00");
+
+        let (r_data, end) = RecordData::from_response(&buf, &RecordType::RRSIG, 0).unwrap();
+
+        assert_eq!(end, buf.len());
+        assert_eq!(
+            r_data,
+            RecordData::RRSIG {
+                type_covered: RecordType::A.to_u16(),
+                algorithm: 13,
+                labels: 3,
+                original_ttl: 300,
+                expiration: 1795132800,
+                inception: 1792540800,
+                key_tag: 34505,
+                signer_name: "wtcx.dev".into(),
+                signature: vec![0xAB; 64],
+            }
+        );
+        assert_eq!(
+            r_data.to_string(),
+            format!(
+                "A 13 3 300 1795132800 1792540800 34505 wtcx.dev. {}qw==",
+                "q6ur".repeat(21)
+            )
+        );
+        assert_eq!(r_data.to_bytes(), buf);
+    }
+
+    #[test]
+    fn test_parse_rrsig_with_compressed_signer() {
+        // The signer name points back to the start of the record.
+        let buf = rrsig_rdata(&[0xC0, 0x00]);
+
+        assert!(RecordData::from_response(&buf, &RecordType::RRSIG, 0).is_err());
+        assert!(RecordData::from_response(&buf[..17], &RecordType::RRSIG, 0).is_err());
+    }
+
+    #[test]
+    fn test_parse_sshfp_record() {
+        // 4 2: Ed25519, SHA-256
+        let mut buf = vec![4, 2];
+        buf.extend([0xAB; 32]);
+
+        let (r_data, end) = RecordData::from_response(&buf, &RecordType::SSHFP, 0).unwrap();
+        let RecordData::SSHFP {
+            algorithm,
+            fp_type,
+            ref fingerprint,
+        } = r_data
+        else {
+            panic!("expect SSHFP record, got {r_data:?}");
+        };
+
+        assert_eq!(end, buf.len());
+        assert_eq!((algorithm, fp_type), (4, 2));
+        assert_eq!(fingerprint, &[0xAB; 32]);
+        assert_eq!(r_data.to_string(), format!("4 2 {}", "AB".repeat(32)));
+    }
+
+    #[test]
+    fn test_parse_wks_record() {
+        // 192.0.2.1, TCP, port 25 and 80
+        let mut buf = vec![192, 0, 2, 1, 6];
+        let mut bitmap = vec![0; 11];
+        bitmap[3] = 0x40;
+        bitmap[10] = 0x80;
+        buf.extend(&bitmap);
+
+        let (r_data, end) = RecordData::from_response(&buf, &RecordType::WKS, 0).unwrap();
+        let RecordData::WKS {
+            address,
+            protocol,
+            bitmap: ref parsed,
+        } = r_data
+        else {
+            panic!("expect WKS record, got {r_data:?}");
+        };
+
+        assert_eq!(end, buf.len());
+        assert_eq!(address, Ipv4Addr::new(192, 0, 2, 1));
+        assert_eq!(protocol, 6);
+        assert_eq!(parsed, &bitmap);
+        assert_eq!(r_data.to_string(), "192.0.2.1 6 25 80");
+    }
+
+    #[test]
+    fn test_parse_wks_record_too_short() {
+        assert!(RecordData::from_response(&[192, 0, 2, 1], &RecordType::WKS, 0).is_err());
+    }
+
+    #[test]
+    fn test_parse_hinfo_record() {
+        let buf = b"\x07RFC8482\x00";
+        let (r_data, end) = RecordData::from_response(buf, &RecordType::HINFO, 0).unwrap();
+
+        assert_eq!(end, buf.len());
+        assert!(matches!(
+            r_data,
+            RecordData::HINFO { ref cpu, ref os } if cpu == "RFC8482" && os.is_empty()
+        ));
+        assert_eq!(r_data.to_string(), r#""RFC8482" """#);
+
+        // The OS is missing
+        assert!(RecordData::from_response(b"\x07RFC8482", &RecordType::HINFO, 0).is_err());
+    }
+
+    #[test]
+    fn test_record_data_round_trip() {
+        let mut soa = b"\x02ns\x04wtcx\x03dev\x00\x0ahostmaster\x04wtcx\x03dev\x00".to_vec();
+        for field in [1u32, 7200, 3600, 1209600, 300] {
+            soa.extend_from_slice(&field.to_be_bytes());
+        }
+        let (soa, _) = RecordData::from_response(&soa, &RecordType::SOA, 0).unwrap();
+
+        let records = [
+            (RecordType::A, RecordData::A(Ipv4Addr::new(1, 2, 3, 4))),
+            (RecordType::AAAA, RecordData::AAAA(Ipv6Addr::LOCALHOST)),
+            (RecordType::CNAME, RecordData::CNAME("blog.wtcx.dev".into())),
+            (RecordType::NS, RecordData::NS("ns.wtcx.dev".into())),
+            (RecordType::PTR, RecordData::PTR("blog.wtcx.dev".into())),
+            (RecordType::DNAME, RecordData::DNAME("wtcx.dev".into())),
+            (RecordType::MB, RecordData::MB("mail.wtcx.dev".into())),
+            (RecordType::MG, RecordData::MG("alice.wtcx.dev".into())),
+            (RecordType::MR, RecordData::MR("bob.wtcx.dev".into())),
+            (RecordType::SOA, soa),
+            (
+                RecordType::MX,
+                RecordData::MX {
+                    preference: 10,
+                    exchange: "mail.wtcx.dev".into(),
+                },
+            ),
+            (
+                RecordType::TXT,
+                RecordData::TXT(vec!["v=spf1".into(), "-all".into()]),
+            ),
+            (RecordType::SPF, RecordData::SPF(vec!["v=spf1 -all".into()])),
+            (
+                RecordType::NAPTR,
+                RecordData::NAPTR {
+                    order: 100,
+                    preference: 10,
+                    flags: "u".into(),
+                    services: "E2U+sip".into(),
+                    regexp: "!^.*$!sip:info@example.com!".into(),
+                    replacement: "".into(),
+                },
+            ),
+            (
+                RecordType::TLSA,
+                RecordData::TLSA {
+                    usage: 3,
+                    selector: 1,
+                    matching_type: 1,
+                    cert_association: vec![0xAB; 32],
+                },
+            ),
+            (
+                RecordType::SSHFP,
+                RecordData::SSHFP {
+                    algorithm: 4,
+                    fp_type: 2,
+                    fingerprint: vec![0xCD; 32],
+                },
+            ),
+            (
+                RecordType::DS,
+                RecordData::DS {
+                    key_tag: 3613,
+                    algorithm: 15,
+                    digest_type: 2,
+                    digest: vec![0xEF; 32],
+                },
+            ),
+            (
+                RecordType::DNSKEY,
+                RecordData::DNSKEY {
+                    flags: 256,
+                    protocol: 3,
+                    algorithm: 15,
+                    public_key: vec![0x12; 32],
+                },
+            ),
+            (
+                RecordType::RRSIG,
+                RecordData::RRSIG {
+                    type_covered: RecordType::AAAA.to_u16(),
+                    algorithm: 15,
+                    labels: 2,
+                    original_ttl: 3600,
+                    expiration: 1795132800,
+                    inception: 1792540800,
+                    key_tag: 3613,
+                    signer_name: "wtcx.dev".into(),
+                    signature: vec![0x34; 64],
+                },
+            ),
+            (
+                RecordType::WKS,
+                RecordData::WKS {
+                    address: Ipv4Addr::new(192, 0, 2, 1),
+                    protocol: 17,
+                    bitmap: vec![0, 0, 0, 0, 0, 0, 0x20],
+                },
+            ),
+            (
+                RecordType::HINFO,
+                RecordData::HINFO {
+                    cpu: "INTEL-386".into(),
+                    os: "UNIX".into(),
+                },
+            ),
+        ];
+
+        for (r_type, r_data) in records {
+            let buf = r_data.to_bytes();
+            let (parsed, end) = RecordData::from_response(&buf, &r_type, 0).unwrap();
+
+            assert_eq!(end, buf.len());
+            assert_eq!(parsed, r_data);
+        }
+    }
+
+    #[test]
+    fn test_parse_soa_record() {
+        let mut buf = b"\x03max\x02ns\x0acloudflare\x03com\x00\x03dns\xc0\x07".to_vec();
+        for field in [2338424937u32, 10000, 2400, 604800, 1800] {
+            buf.extend_from_slice(&field.to_be_bytes());
+        }
+
+        let (r_data, end) = RecordData::from_response(&buf, &RecordType::SOA, 0).unwrap();
+        let RecordData::SOA(soa) = r_data else {
+            panic!("expect SOA record, got {r_data:?}");
+        };
+
+        assert_eq!(end, buf.len());
+        assert_eq!(soa.m_name(), "max.ns.cloudflare.com");
+        assert_eq!(soa.r_name(), "dns.cloudflare.com");
+        assert_eq!(soa.serial(), 2338424937);
+        assert_eq!(soa.refresh(), 10000);
+        assert_eq!(soa.retry(), 2400);
+        assert_eq!(soa.expire(), 604800);
+        assert_eq!(soa.minimum(), 1800);
+        assert_eq!(soa.clone(), soa);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_a_record() {
+        let rr = record(
+            "blog.wtcx.dev",
+            RecordType::A,
+            RecordData::A(Ipv4Addr::new(1, 2, 3, 4)),
+        );
+
+        let json = serde_json::to_value(&rr).unwrap();
+        assert_eq!(
+            json["r_data"],
+            serde_json::json!({"type": "A", "value": "1.2.3.4"})
+        );
+
+        let deserialized: ResourceRecord = serde_json::from_value(json).unwrap();
+        assert_eq!(deserialized, rr);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_soa_record() {
+        let soa = SoaRecord {
+            m_name: "max.ns.cloudflare.com".into(),
+            r_name: "dns.cloudflare.com".into(),
+            serial: 2338424937,
+            refresh: 10000,
+            retry: 2400,
+            expire: 604800,
+            minimum: 1800,
+        };
+        let rr = record("wtcx.dev", RecordType::SOA, RecordData::SOA(soa));
+
+        let json = serde_json::to_string(&rr).unwrap();
+        let deserialized: ResourceRecord = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(deserialized, rr);
+    }
+}
@@ -0,0 +1,204 @@
+#[cfg(feature = "std")]
+use std::collections::HashMap;
+#[cfg(feature = "std")]
+use std::sync::{Arc, OnceLock, RwLock};
+
+use super::{RecordData, RecordType};
+use crate::Error;
+
+/// Parses RDATA of a record type.
+///
+/// `buf` ends where RDATA ends, and RDATA starts at `start_pos`. Bytes before it are kept for compressed domain names.
+/// Returns the data and the position where it ends.
+///
+/// Functions and closures with the same signature are parsers as well.
+pub trait RdataParser: Send + Sync {
+    fn parse(&self, buf: &[u8], start_pos: usize) -> Result<(RecordData, usize), Error>;
+}
+
+impl<F> RdataParser for F
+where
+    F: Fn(&[u8], usize) -> Result<(RecordData, usize), Error> + Send + Sync,
+{
+    fn parse(&self, buf: &[u8], start_pos: usize) -> Result<(RecordData, usize), Error> {
+        self(buf, start_pos)
+    }
+}
+
+#[cfg(feature = "std")]
+type Parsers = HashMap<u16, Arc<dyn RdataParser>>;
+
+type ParseFn = fn(&[u8], usize) -> Result<(RecordData, usize), Error>;
+
+/// Parse RDATA of a type code with a parser in place of the built-in one, or for a type [`RecordType`] doesn't have.
+/// It applies to every response parsed afterwards.
+///
+/// A type outside [`RecordType`] has no `r_type` in [`ResourceRecord`](super::ResourceRecord),
+/// so its parser is expected to return [`RecordData::Custom`] with the type code.
+///
+/// ```
+/// use std::sync::Arc;
+/// use tiny_resolver_rs::{register_rdata_parser, RecordData};
+///
+/// register_rdata_parser(
+///     65280,
+///     Arc::new(|buf: &[u8], start_pos: usize| {
+///         let data = buf[start_pos..].to_vec();
+///         let text = String::from_utf8_lossy(&data).into_owned();
+///         let r_data = RecordData::Custom { type_code: 65280, text, data };
+///         Ok((r_data, buf.len()))
+///     }),
+/// );
+/// ```
+#[cfg(feature = "std")]
+pub fn register_rdata_parser(type_code: u16, parser: Arc<dyn RdataParser>) {
+    parsers().write().unwrap().insert(type_code, parser);
+}
+
+/// Parse RDATA with the parser of the type code, or keep it as it is if there is none.
+#[cfg(feature = "std")]
+pub(crate) fn parse(
+    buf: &[u8],
+    type_code: u16,
+    start_pos: usize,
+) -> Result<(RecordData, usize), Error> {
+    // The lock isn't held while parsing, in case a parser registers another one.
+    let parser = parsers().read().unwrap().get(&type_code).cloned();
+
+    match parser {
+        Some(parser) => parser.parse(buf, start_pos),
+        None => Ok(RecordData::parse_unknown(buf, start_pos, type_code)),
+    }
+}
+
+/// Parse RDATA with the built-in parser of the type code. Parsers can't be registered without the `std` feature.
+#[cfg(not(feature = "std"))]
+pub(crate) fn parse(
+    buf: &[u8],
+    type_code: u16,
+    start_pos: usize,
+) -> Result<(RecordData, usize), Error> {
+    let parser = built_in_parsers()
+        .into_iter()
+        .find(|(record_type, _)| record_type.to_u16() == type_code);
+
+    match parser {
+        Some((_, parser)) => parser(buf, start_pos),
+        None => Ok(RecordData::parse_unknown(buf, start_pos, type_code)),
+    }
+}
+
+#[cfg(feature = "std")]
+fn parsers() -> &'static RwLock<Parsers> {
+    static PARSERS: OnceLock<RwLock<Parsers>> = OnceLock::new();
+    PARSERS.get_or_init(|| {
+        let parsers = built_in_parsers()
+            .into_iter()
+            .map(|(record_type, parser)| {
+                let parser: Arc<dyn RdataParser> = Arc::new(parser);
+                (record_type.to_u16(), parser)
+            })
+            .collect();
+        RwLock::new(parsers)
+    })
+}
+
+fn built_in_parsers() -> [(RecordType, ParseFn); 24] {
+    [
+        (RecordType::A, RecordData::parse_a),
+        (RecordType::AAAA, RecordData::parse_aaaa),
+        (RecordType::CNAME, RecordData::parse_cname),
+        (RecordType::NS, RecordData::parse_ns),
+        (RecordType::SOA, RecordData::parse_soa),
+        (RecordType::MX, RecordData::parse_mx),
+        (RecordType::TXT, RecordData::parse_txt),
+        (RecordType::SPF, RecordData::parse_spf),
+        (RecordType::PTR, RecordData::parse_ptr),
+        (RecordType::DNAME, RecordData::parse_dname),
+        (RecordType::NAPTR, RecordData::parse_naptr),
+        (RecordType::SSHFP, RecordData::parse_sshfp),
+        (RecordType::DS, RecordData::parse_ds),
+        (RecordType::DNSKEY, RecordData::parse_dnskey),
+        (RecordType::RRSIG, RecordData::parse_rrsig),
+        (RecordType::TLSA, RecordData::parse_tlsa),
+        (RecordType::WKS, RecordData::parse_wks),
+        (RecordType::HINFO, RecordData::parse_hinfo),
+        (RecordType::MB, RecordData::parse_mb),
+        (RecordType::MG, RecordData::parse_mg),
+        (RecordType::MR, RecordData::parse_mr),
+        (RecordType::OPT, |_, _| {
+            Err(Error::ResolverError(
+                "OPT record can't be parsed without its CLASS and TTL".into(),
+            ))
+        }),
+        (RecordType::AXFR, |_, _| {
+            Err(Error::ResolverError(
+                "AXFR is only valid as a query type".into(),
+            ))
+        }),
+        (RecordType::ANY, |_, _| {
+            Err(Error::ResolverError(
+                "ANY is only valid as a query type".into(),
+            ))
+        }),
+    ]
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::record::ResourceRecord;
+    use crate::testing;
+
+    #[test]
+    fn test_custom_parser_for_private_type() {
+        // A private type whose RDATA is a 16 bit counter.
+        register_rdata_parser(
+            65281,
+            Arc::new(|buf: &[u8], start_pos: usize| {
+                let data = buf
+                    .get(start_pos..start_pos + 2)
+                    .ok_or_else(|| Error::ResolverError("counter is too short".into()))?;
+                let counter = u16::from_be_bytes([data[0], data[1]]);
+                let r_data = RecordData::Custom {
+                    type_code: 65281,
+                    text: counter.to_string(),
+                    data: data.to_vec(),
+                };
+                Ok((r_data, start_pos + 2))
+            }),
+        );
+        let mut buf = testing::encode("blog.wtcx.dev");
+        buf.extend_from_slice(&[0xFF, 0x01, 0, 1, 0, 0, 1, 44, 0, 2, 0x01, 0x02]);
+
+        let (rr, end) = ResourceRecord::from_response(&buf, 0).unwrap();
+
+        assert_eq!(end, buf.len());
+        assert_eq!(rr.r_type, None);
+        assert_eq!(rr.type_code(), 65281);
+        assert_eq!(rr.to_string(), "blog.wtcx.dev.  300  IN  TYPE65281  258");
+        assert_eq!(rr.to_bytes(), buf);
+    }
+
+    #[test]
+    fn test_custom_parser_reading_past_rdata() {
+        register_rdata_parser(
+            65282,
+            Arc::new(|buf: &[u8], _: usize| {
+                let r_data = RecordData::Custom {
+                    type_code: 65282,
+                    text: String::new(),
+                    data: vec![],
+                };
+                Ok((r_data, buf.len() + 2))
+            }),
+        );
+        let mut buf = testing::encode("blog.wtcx.dev");
+        buf.extend_from_slice(&[0xFF, 0x02, 0, 1, 0, 0, 1, 44, 0, 2, 0x01, 0x02]);
+
+        assert_eq!(
+            ResourceRecord::from_response(&buf, 0).unwrap_err(),
+            Error::ResolverError("RDATA is 2 bytes, but 4 bytes are parsed".into())
+        );
+    }
+}
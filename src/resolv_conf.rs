@@ -0,0 +1,100 @@
+//! Find the name servers the system is configured with, e.g., to send recursive queries to them like a stub resolver.
+
+use std::io::BufRead;
+use std::net::IpAddr;
+
+use log::debug;
+
+use crate::Error;
+
+#[cfg(unix)]
+const RESOLV_CONF: &str = "/etc/resolv.conf";
+
+/// Addresses of the name servers in `/etc/resolv.conf`, in the order they are listed.
+///
+/// They are usually recursive resolvers, so queries to them should ask for recursion,
+/// see [`Resolver::system`](crate::Resolver::system).
+#[cfg(unix)]
+pub fn system_resolvers() -> Result<Vec<IpAddr>, Error> {
+    let file = std::fs::File::open(RESOLV_CONF).map_err(Error::NetworkError)?;
+
+    parse_resolv_conf(std::io::BufReader::new(file))
+}
+
+/// There is no `/etc/resolv.conf` to read on other platforms.
+#[cfg(not(unix))]
+pub fn system_resolvers() -> Result<Vec<IpAddr>, Error> {
+    Err(Error::ResolverError(
+        "system resolvers can only be read on Unix".into(),
+    ))
+}
+
+/// Take the addresses of `nameserver` lines. Other options, and comments starting with `#` or `;`, are ignored.
+///
+/// See [resolv.conf(5)](https://man7.org/linux/man-pages/man5/resolv.conf.5.html).
+pub(crate) fn parse_resolv_conf<R: BufRead>(reader: R) -> Result<Vec<IpAddr>, Error> {
+    let mut name_servers = vec![];
+
+    for line in reader.lines() {
+        let line = line.map_err(Error::NetworkError)?;
+        let mut fields = line.split_whitespace();
+        if fields.next() != Some("nameserver") {
+            continue;
+        }
+
+        // A link-local IPv6 address might come with its zone, e.g., `fe80::1%eth0`, which `IpAddr` can't keep.
+        let addr = fields.next().and_then(|addr| addr.split('%').next());
+        match addr.and_then(|addr| addr.parse().ok()) {
+            Some(ip) => name_servers.push(ip),
+            None => debug!("ignored invalid name server line: {line}"),
+        }
+    }
+
+    if name_servers.is_empty() {
+        return Err(Error::ResolverError(
+            "no name server found in resolv.conf".into(),
+        ));
+    }
+
+    Ok(name_servers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    #[test]
+    fn test_parse_resolv_conf() {
+        let conf = "\
+# Generated by NetworkManager
+search wtcx.dev
+nameserver 192.168.1.1
+nameserver   1.1.1.1  # Cloudflare
+;nameserver 8.8.8.8
+nameserver 2001:4860:4860::8888
+nameserver fe80::1%eth0
+nameserver not-an-address
+options edns0 trust-ad
+";
+
+        let name_servers = parse_resolv_conf(conf.as_bytes()).unwrap();
+
+        assert_eq!(
+            name_servers,
+            [
+                IpAddr::from(Ipv4Addr::new(192, 168, 1, 1)),
+                IpAddr::from(Ipv4Addr::new(1, 1, 1, 1)),
+                IpAddr::from(Ipv6Addr::new(0x2001, 0x4860, 0x4860, 0, 0, 0, 0, 0x8888)),
+                IpAddr::from(Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 1)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_resolv_conf_without_name_server() {
+        let conf = "search wtcx.dev\n# nameserver 1.1.1.1\n";
+
+        assert!(parse_resolv_conf(conf.as_bytes()).is_err());
+    }
+}
@@ -1,40 +1,1039 @@
+use crate::blocklist::{BlockAction, Blocklist};
+use crate::cache::{Cache, Lookup, QueryOptions};
+use crate::observer::{Observer, ResolverObserver};
 use crate::record::*;
-use crate::Error;
+use crate::resolv_conf::system_resolvers;
+use crate::{ClientSubnet, Edns, Error, HeaderFlags};
 use crate::{message::Message, utils};
+use crate::transport;
+use crate::transport::pool::Pool;
+use log::{debug, trace};
 use rand::seq::SliceRandom;
 use rand::thread_rng;
-use std::net::Ipv4Addr;
-use std::net::UdpSocket;
+use std::collections::HashSet;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs};
+use std::net::{TcpStream, UdpSocket};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 
-struct Resolver;
+#[cfg(feature = "tokio")]
+mod nonblocking;
+#[cfg(feature = "tokio")]
+pub use nonblocking::query_async;
 
-const MAX_ATTEMPTS: usize = 5;
+const DEFAULT_MAX_ATTEMPTS: usize = 5;
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+const DEFAULT_RETRANSMIT_INTERVAL: Duration = Duration::from_secs(1);
+const DEFAULT_PORT: u16 = 53;
+const DEFAULT_MAX_REFERRAL_DEPTH: usize = 10;
+const DEFAULT_FAN_OUT: usize = 2;
+const DEFAULT_MAX_QUERIES: usize = 50;
+const DEFAULT_POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+/// See [RFC 8305, 3. Hostname Resolution Query Handling](https://www.rfc-editor.org/rfc/rfc8305.html).
+const DEFAULT_RESOLUTION_DELAY: Duration = Duration::from_millis(50);
+/// Avoids IP fragmentation on most networks, see [DNS Flag Day 2020](https://www.dnsflagday.net/2020/).
+const DEFAULT_EDNS_PAYLOAD_SIZE: u16 = 1232;
+
+/// An iterative resolver starting from root name servers.
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use tiny_resolver_rs::{RecordType, Resolver};
+///
+/// let resolver = Resolver::builder()
+///     .max_attempts(3)
+///     .timeout(Duration::from_secs(2))
+///     .build();
+/// let res = resolver.query("blog.wtcx.dev", &RecordType::A).unwrap();
+/// ```
+///
+/// Clones of a resolver share the same [`Cache`] and idle connections.
+#[derive(Debug, Clone)]
+pub struct Resolver {
+    max_attempts: usize,
+    timeout: Duration,
+    retransmits: usize,
+    retransmit_interval: Duration,
+    protocol: Protocol,
+    address_family: AddressFamily,
+    roots: Vec<IpAddr>,
+    port: u16,
+    recursion_desired: bool,
+    edns_payload_size: Option<u16>,
+    dnssec_ok: bool,
+    checking_disabled: bool,
+    client_subnet: Option<ClientSubnet>,
+    max_referral_depth: usize,
+    max_queries: usize,
+    resolution_timeout: Option<Duration>,
+    resolution_delay: Duration,
+    fan_out: usize,
+    require_glue: bool,
+    strict_parsing: bool,
+    keep_unknown_records: bool,
+    validate_cnames: bool,
+    randomize_case: bool,
+    bind_addr: Option<IpAddr>,
+    source_port: Option<u16>,
+    max_labels: usize,
+    #[cfg(feature = "dot")]
+    dot_server: Option<(SocketAddr, String)>,
+    #[cfg(feature = "doh")]
+    doh_endpoint: Option<String>,
+    cache: Arc<Cache>,
+    observer: Observer,
+    blocklist: Option<Blocklist>,
+    tcp_pool: Arc<Pool<TcpStream>>,
+    #[cfg(feature = "dot")]
+    dot_pool: Arc<Pool<transport::dot::TlsStream>>,
+}
+
+impl Default for Resolver {
+    fn default() -> Self {
+        ResolverBuilder::default().build()
+    }
+}
+
+/// Builder for [`Resolver`]. Unset options fall back to their defaults.
+#[derive(Debug)]
+pub struct ResolverBuilder {
+    max_attempts: usize,
+    timeout: Duration,
+    retransmits: usize,
+    retransmit_interval: Duration,
+    protocol: Protocol,
+    address_family: AddressFamily,
+    roots: Option<Vec<IpAddr>>,
+    port: u16,
+    recursion_desired: bool,
+    edns_payload_size: Option<u16>,
+    dnssec_ok: bool,
+    checking_disabled: bool,
+    client_subnet: Option<ClientSubnet>,
+    max_referral_depth: usize,
+    max_queries: usize,
+    resolution_timeout: Option<Duration>,
+    resolution_delay: Duration,
+    fan_out: usize,
+    require_glue: bool,
+    strict_parsing: bool,
+    keep_unknown_records: bool,
+    validate_cnames: bool,
+    randomize_case: bool,
+    bind_addr: Option<IpAddr>,
+    source_port: Option<u16>,
+    max_labels: usize,
+    #[cfg(feature = "dot")]
+    dot_server: Option<(SocketAddr, String)>,
+    #[cfg(feature = "doh")]
+    doh_endpoint: Option<String>,
+    cache: Option<Arc<Cache>>,
+    observer: Observer,
+    blocklist: Option<Blocklist>,
+    pool_size: usize,
+    pool_idle_timeout: Duration,
+}
+
+impl Default for ResolverBuilder {
+    fn default() -> Self {
+        Self {
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            timeout: DEFAULT_TIMEOUT,
+            retransmits: 0,
+            retransmit_interval: DEFAULT_RETRANSMIT_INTERVAL,
+            protocol: Protocol::UDP,
+            address_family: AddressFamily::V4,
+            roots: None,
+            port: DEFAULT_PORT,
+            recursion_desired: false,
+            edns_payload_size: None,
+            dnssec_ok: false,
+            checking_disabled: false,
+            client_subnet: None,
+            max_referral_depth: DEFAULT_MAX_REFERRAL_DEPTH,
+            max_queries: DEFAULT_MAX_QUERIES,
+            resolution_timeout: None,
+            resolution_delay: DEFAULT_RESOLUTION_DELAY,
+            fan_out: DEFAULT_FAN_OUT,
+            require_glue: false,
+            strict_parsing: false,
+            keep_unknown_records: false,
+            validate_cnames: false,
+            randomize_case: false,
+            bind_addr: None,
+            source_port: None,
+            max_labels: utils::MAX_LABELS,
+            #[cfg(feature = "dot")]
+            dot_server: None,
+            #[cfg(feature = "doh")]
+            doh_endpoint: None,
+            cache: None,
+            observer: Observer::default(),
+            blocklist: None,
+            pool_size: 0,
+            pool_idle_timeout: DEFAULT_POOL_IDLE_TIMEOUT,
+        }
+    }
+}
+
+impl ResolverBuilder {
+    /// How many name servers can be asked before giving up. Defaults to 5.
+    pub fn max_attempts(mut self, max_attempts: usize) -> Self {
+        self.max_attempts = max_attempts;
+        self
+    }
+
+    /// How long to wait for each name server to respond. Defaults to 5 seconds.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// How many times a UDP query is sent again to the same name servers when they don't respond,
+    /// before moving on to other ones. Disabled (0) by default.
+    ///
+    /// Retransmits happen within the [timeout](ResolverBuilder::timeout), with exponential backoff from the
+    /// [retransmit interval](ResolverBuilder::retransmit_interval), e.g., after 1, 3, and 7 seconds.
+    pub fn retransmits(mut self, retransmits: usize) -> Self {
+        self.retransmits = retransmits;
+        self
+    }
+
+    /// How long to wait before the first retransmit, doubled for every next one. Defaults to 1 second.
+    pub fn retransmit_interval(mut self, interval: Duration) -> Self {
+        self.retransmit_interval = interval;
+        self
+    }
+
+    /// Protocol used to talk to name servers. Defaults to [`Protocol::UDP`].
+    pub fn protocol(mut self, protocol: Protocol) -> Self {
+        self.protocol = protocol;
+        self
+    }
+
+    /// Which addresses name servers are reached with. Defaults to [`AddressFamily::V4`].
+    pub fn address_family(mut self, address_family: AddressFamily) -> Self {
+        self.address_family = address_family;
+        self
+    }
+
+    /// Name servers to start resolution from.
+    /// Defaults to the 13 root name servers, with the addresses of the [`AddressFamily`].
+    pub fn roots<T>(mut self, roots: &[T]) -> Self
+    where
+        T: Into<IpAddr> + Copy,
+    {
+        self.roots = Some(roots.iter().map(|ip| (*ip).into()).collect());
+        self
+    }
+
+    /// Name servers to start resolution from, given by their host names, e.g., `dns.google`.
+    ///
+    /// We can't resolve these names ourselves without knowing where to start, so they are bootstrapped
+    /// with the system resolver right away, and the addresses are used as if they were passed to [`Self::roots`].
+    pub fn roots_by_name(self, hosts: &[&str]) -> Result<Self, Error> {
+        self.roots_by_name_with(hosts, |host| {
+            let addrs = (host, 0).to_socket_addrs().map_err(Error::NetworkError)?;
+            Ok(addrs.map(|addr| addr.ip()).collect())
+        })
+    }
+
+    /// Like [`Self::roots_by_name`], with `lookup` resolving host names instead of the system resolver.
+    fn roots_by_name_with<F>(mut self, hosts: &[&str], lookup: F) -> Result<Self, Error>
+    where
+        F: Fn(&str) -> Result<Vec<IpAddr>, Error>,
+    {
+        let mut roots = vec![];
+        for host in hosts {
+            let ips = lookup(host)?;
+            if ips.is_empty() {
+                return Err(Error::ResolverError(format!(
+                    "name server {host} has no address"
+                )));
+            }
+            roots.extend(ips);
+        }
+
+        self.roots = Some(roots);
+        Ok(self)
+    }
+
+    /// Port name servers listen on. Defaults to 53.
+    pub fn port(mut self, port: u16) -> Self {
+        self.port = port;
+        self
+    }
+
+    /// Ask the name servers to resolve domains recursively, when [`Self::roots`] are recursive resolvers
+    /// rather than root name servers, e.g., the ones of [`system_resolvers`](crate::system_resolvers).
+    /// Disabled by default.
+    ///
+    /// Their responses are final, so a response without answers isn't taken as a referral.
+    pub fn recursion_desired(mut self, recursion_desired: bool) -> Self {
+        self.recursion_desired = recursion_desired;
+        self
+    }
+
+    /// Advertise a UDP payload size larger than 512 bytes with EDNS(0), e.g., 4096.
+    /// Disabled by default.
+    pub fn edns_payload_size(mut self, size: u16) -> Self {
+        self.edns_payload_size = Some(size);
+        self
+    }
+
+    /// Set the DNSSEC OK (DO) bit in queries, so name servers include RRSIG and the other DNSSEC records.
+    /// Disabled by default.
+    ///
+    /// DO is carried by EDNS(0), which is enabled with a payload size of 1232 bytes
+    /// unless one is set with [`edns_payload_size`](Self::edns_payload_size).
+    ///
+    /// See [RFC 3225](https://www.rfc-editor.org/rfc/rfc3225.html).
+    pub fn dnssec_ok(mut self, dnssec_ok: bool) -> Self {
+        self.dnssec_ok = dnssec_ok;
+        self
+    }
+
+    /// Set the Checking Disabled (CD) bit in queries, so validating name servers return data even if it fails
+    /// DNSSEC validation. Disabled by default.
+    ///
+    /// See [RFC 4035, 3.2.2. The CD Bit](https://www.rfc-editor.org/rfc/rfc4035.html).
+    pub fn checking_disabled(mut self, checking_disabled: bool) -> Self {
+        self.checking_disabled = checking_disabled;
+        self
+    }
+
+    /// Send the EDNS Client Subnet (ECS) option with queries, e.g., `192.0.2.0/24`, so name servers answer as if
+    /// the query came from that network, e.g., to debug geo-DNS. Disabled by default.
+    ///
+    /// ECS is carried by EDNS(0), which is enabled with a payload size of 1232 bytes
+    /// unless one is set with [`edns_payload_size`](Self::edns_payload_size).
+    ///
+    /// See [RFC 7871](https://www.rfc-editor.org/rfc/rfc7871.html).
+    pub fn client_subnet(mut self, client_subnet: ClientSubnet) -> Self {
+        self.client_subnet = Some(client_subnet);
+        self
+    }
+
+    /// How many levels of name server domains can be resolved in order to resolve a domain.
+    /// Defaults to 10.
+    ///
+    /// When the authority section doesn't come with glue, the name server domain has to be resolved first.
+    /// And resolving the name server domain might require resolving another name server domain, and so on.
+    pub fn max_referral_depth(mut self, depth: usize) -> Self {
+        self.max_referral_depth = depth;
+        self
+    }
+
+    /// How many queries can be sent to resolve a domain, including the ones resolving name server domains.
+    /// Defaults to 50.
+    pub fn max_queries(mut self, max_queries: usize) -> Self {
+        self.max_queries = max_queries;
+        self
+    }
+
+    /// How long resolving a domain can take in total, including the resolutions of name server domains,
+    /// no matter how many attempts are left. Unlimited by default.
+    pub fn resolution_timeout(mut self, timeout: Duration) -> Self {
+        self.resolution_timeout = Some(timeout);
+        self
+    }
+
+    /// How many name servers are asked at the same time in each attempt. Defaults to 2.
+    ///
+    /// The first response is used, so a slow name server doesn't hold back the resolution.
+    pub fn fan_out(mut self, fan_out: usize) -> Self {
+        self.fan_out = fan_out.max(1);
+        self
+    }
+
+    /// Fail instead of resolving the name server domain when a referral comes without glue,
+    /// e.g., to find out lame delegations. Disabled by default.
+    pub fn require_glue(mut self, require_glue: bool) -> Self {
+        self.require_glue = require_glue;
+        self
+    }
+
+    /// Fail responses with bytes left after the message instead of ignoring them, e.g., data smuggled behind a valid
+    /// response. Disabled by default.
+    pub fn strict_parsing(mut self, strict_parsing: bool) -> Self {
+        self.strict_parsing = strict_parsing;
+        self
+    }
+
+    /// Keep records of types [`RecordType`] doesn't have as [`RecordData::Unknown`], without `r_type`, instead of
+    /// failing the whole response. RDLENGTH tells where they end, so the records around them are still parsed.
+    /// Disabled by default.
+    pub fn keep_unknown_records(mut self, keep_unknown_records: bool) -> Self {
+        self.keep_unknown_records = keep_unknown_records;
+        self
+    }
+
+    /// Fail answers with a CNAME along with other data, or a CNAME pointing to a name without any record, with
+    /// [`Error::InvalidResponse`], e.g., to lint zones. See [`Message::check_cnames`]. A target missing from the
+    /// answer is resolved on its own, and the CNAME is only taken as dangling if the target doesn't exist or has no
+    /// records of the type. Disabled by default.
+    pub fn validate_cnames(mut self, validate_cnames: bool) -> Self {
+        self.validate_cnames = validate_cnames;
+        self
+    }
+
+    /// Flip the case of letters in queried domains randomly, and ignore responses not echoing it exactly (DNS 0x20),
+    /// to make spoofed responses harder to get accepted. See [`Message::with_randomized_case`]. Disabled by default,
+    /// as a few name servers don't keep the casing of the question.
+    pub fn randomize_case(mut self, randomize_case: bool) -> Self {
+        self.randomize_case = randomize_case;
+        self
+    }
+
+    /// Local address to send UDP queries from, e.g., to pick the interface on a multi-homed host.
+    /// Defaults to `0.0.0.0`, which lets the OS decide.
+    pub fn bind_addr(mut self, addr: IpAddr) -> Self {
+        self.bind_addr = Some(addr);
+        self
+    }
+
+    /// Local port to send UDP queries from, e.g., for a firewall that only lets a known port through.
+    /// Defaults to a random port picked by the OS.
+    ///
+    /// A fixed port makes spoofed responses much easier to get accepted, since only the query ID is left to guess.
+    ///
+    /// Each query binds its own socket to the port, so it can't be used for queries at the same time, e.g., from
+    /// clones of the resolver on other threads or concurrent async queries, which fail with
+    /// [`io::ErrorKind::AddrInUse`]. [`Resolver::resolve_addrs`] queries AAAA and A one after the other then.
+    pub fn source_port(mut self, port: u16) -> Self {
+        self.source_port = Some(port);
+        self
+    }
+
+    /// How many labels a queried domain can have, e.g., to turn down absurdly deep names
+    /// with [`Error::InvalidHostname`]. Defaults to 127, the most that fits in a domain of 255 bytes.
+    pub fn max_labels(mut self, max_labels: usize) -> Self {
+        self.max_labels = max_labels;
+        self
+    }
+
+    /// Recursive resolver to send queries to with [`Protocol::DOT`], e.g., `1.1.1.1` and `cloudflare-dns.com`.
+    /// `hostname` is used to verify the certificate of the server. Port 853 is used if `addr` has port 0.
+    #[cfg(feature = "dot")]
+    pub fn dot_server(mut self, addr: SocketAddr, hostname: &str) -> Self {
+        self.dot_server = Some((addr, hostname.to_string()));
+        self
+    }
+
+    /// Endpoint to send queries to with [`Protocol::DOH`], e.g., `https://cloudflare-dns.com/dns-query`.
+    #[cfg(feature = "doh")]
+    pub fn doh_endpoint(mut self, url: &str) -> Self {
+        self.doh_endpoint = Some(url.to_string());
+        self
+    }
+
+    /// Cache shared with other resolvers. Each resolver has its own cache by default.
+    pub fn cache(mut self, cache: Arc<Cache>) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// How long [`Resolver::resolve_addrs`] waits for AAAA records once A records are resolved. Defaults to 50ms.
+    pub fn resolution_delay(mut self, delay: Duration) -> Self {
+        self.resolution_delay = delay;
+        self
+    }
+
+    /// Receive events of resolutions, e.g., queries sent and cache hits, to feed metrics.
+    /// Clones of the resolver share the same observer.
+    pub fn observer(mut self, observer: Arc<dyn ResolverObserver>) -> Self {
+        self.observer = Observer::new(observer);
+        self
+    }
+
+    /// Answer queried names the predicate returns `true` for with the action, without going to the network.
+    /// Names are given in lowercase ASCII, without the trailing dot.
+    ///
+    /// ```
+    /// use tiny_resolver_rs::{BlockAction, Resolver};
+    ///
+    /// let resolver = Resolver::builder()
+    ///     .blocklist(
+    ///         |domain, _| domain == "ads.example.com" || domain.ends_with(".ads.example.com"),
+    ///         BlockAction::Sinkhole,
+    ///     )
+    ///     .build();
+    /// ```
+    pub fn blocklist<F>(mut self, predicate: F, action: BlockAction) -> Self
+    where
+        F: Fn(&str, &RecordType) -> bool + Send + Sync + 'static,
+    {
+        self.blocklist = Some(Blocklist::new(Arc::new(predicate), action));
+        self
+    }
+
+    /// How many idle TCP and DoT connections can be kept open for later queries to the same server.
+    /// Disabled (0) by default, so every query has its own connection.
+    pub fn pool_size(mut self, size: usize) -> Self {
+        self.pool_size = size;
+        self
+    }
+
+    /// How long an idle connection is kept open. Defaults to 10 seconds.
+    pub fn pool_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.pool_idle_timeout = timeout;
+        self
+    }
+
+    pub fn build(self) -> Resolver {
+        let roots = self
+            .roots
+            .unwrap_or_else(|| self.address_family.root_name_servers());
+
+        Resolver {
+            max_attempts: self.max_attempts,
+            timeout: self.timeout,
+            retransmits: self.retransmits,
+            retransmit_interval: self.retransmit_interval,
+            protocol: self.protocol,
+            address_family: self.address_family,
+            roots,
+            port: self.port,
+            recursion_desired: self.recursion_desired,
+            edns_payload_size: self.edns_payload_size,
+            dnssec_ok: self.dnssec_ok,
+            checking_disabled: self.checking_disabled,
+            client_subnet: self.client_subnet,
+            max_referral_depth: self.max_referral_depth,
+            max_queries: self.max_queries,
+            resolution_timeout: self.resolution_timeout,
+            resolution_delay: self.resolution_delay,
+            fan_out: self.fan_out,
+            require_glue: self.require_glue,
+            strict_parsing: self.strict_parsing,
+            keep_unknown_records: self.keep_unknown_records,
+            validate_cnames: self.validate_cnames,
+            randomize_case: self.randomize_case,
+            bind_addr: self.bind_addr,
+            source_port: self.source_port,
+            max_labels: self.max_labels,
+            #[cfg(feature = "dot")]
+            dot_server: self.dot_server,
+            #[cfg(feature = "doh")]
+            doh_endpoint: self.doh_endpoint,
+            cache: self.cache.unwrap_or_default(),
+            observer: self.observer,
+            blocklist: self.blocklist,
+            tcp_pool: Arc::new(Pool::new(self.pool_size, self.pool_idle_timeout)),
+            #[cfg(feature = "dot")]
+            dot_pool: Arc::new(Pool::new(self.pool_size, self.pool_idle_timeout)),
+        }
+    }
+}
 
 impl Resolver {
-    fn extract_domains(records: &[ResourceRecord], record_type: &RecordType) -> Vec<String> {
-        records
+    pub fn builder() -> ResolverBuilder {
+        ResolverBuilder::default()
+    }
+
+    /// A resolver sending recursive queries to the name servers of the system, as a drop-in replacement
+    /// of the system resolver.
+    ///
+    /// It's the same as the following, which other options can be set along with:
+    ///
+    /// ```no_run
+    /// use tiny_resolver_rs::{system_resolvers, Resolver};
+    ///
+    /// let resolver = Resolver::builder()
+    ///     .roots(&system_resolvers()?)
+    ///     .recursion_desired(true)
+    ///     .build();
+    /// # Ok::<(), tiny_resolver_rs::Error>(())
+    /// ```
+    pub fn system() -> Result<Self, Error> {
+        let resolver = Self::builder()
+            .roots(&system_resolvers()?)
+            .recursion_desired(true)
+            .build();
+
+        Ok(resolver)
+    }
+
+    /// Query domain with given type.
+    pub fn query(&self, domain: &str, record_type: &RecordType) -> Result<Message, Error> {
+        self.query_with_class(domain, record_type, &RecordClass::IN)
+    }
+
+    /// Query domain with given type and class, e.g., `version.bind` in the CHAOS class.
+    pub fn query_with_class(
+        &self,
+        domain: &str,
+        record_type: &RecordType,
+        record_class: &RecordClass,
+    ) -> Result<Message, Error> {
+        self.query_with_budget(domain, record_type, record_class, &self.new_budget())
+    }
+
+    /// Same as [`Resolver::query`], but it also tells how the answer was received, e.g., to diagnose MTU issues
+    /// when responses are too large for UDP.
+    pub fn query_with_stats(
+        &self,
+        domain: &str,
+        record_type: &RecordType,
+    ) -> Result<(Message, QueryStats), Error> {
+        let budget = self.new_budget();
+        let message = self.query_with_budget(domain, record_type, &RecordClass::IN, &budget)?;
+        let stats = *budget.stats.lock().unwrap();
+
+        Ok((message, stats))
+    }
+
+    /// Same as [`Resolver::query`], but it also returns the response as it's received, e.g., to inspect the hex
+    /// or pass it on to another system.
+    ///
+    /// For an answer from the cache, they are the bytes kept in the cache. Answers from the blocklist aren't received
+    /// at all, so they come without bytes.
+    pub fn query_raw(
+        &self,
+        domain: &str,
+        record_type: &RecordType,
+    ) -> Result<(Message, Option<Vec<u8>>), Error> {
+        let budget = Budget {
+            keep_raw: true,
+            ..self.new_budget()
+        };
+        let message = self.query_with_budget(domain, record_type, &RecordClass::IN, &budget)?;
+        let raw = budget.raw.lock().unwrap().take();
+
+        Ok((message, raw))
+    }
+
+    fn query_with_budget(
+        &self,
+        domain: &str,
+        record_type: &RecordType,
+        record_class: &RecordClass,
+        budget: &Budget,
+    ) -> Result<Message, Error> {
+        let domain = &utils::to_ascii(domain)?;
+        self.validate_domain(domain)?;
+        Self::check_query_type(record_type)?;
+
+        if let Some(blocked) = self.check_blocklist(domain, record_type) {
+            return blocked;
+        }
+
+        let message = self.resolve_with_protocol(domain, record_type, record_class, budget)?;
+        self.check_cnames(&message, record_type, record_class, budget)?;
+
+        Ok(message)
+    }
+
+    /// Resolve domain iteratively, or forward it over DoT or DoH, depending on the [`Protocol`].
+    fn resolve_with_protocol(
+        &self,
+        domain: &str,
+        record_type: &RecordType,
+        record_class: &RecordClass,
+        budget: &Budget,
+    ) -> Result<Message, Error> {
+        match self.protocol {
+            Protocol::UDP | Protocol::TCP => {
+                self.resolve(domain, record_type, record_class, 0, budget)
+            }
+            #[cfg(feature = "dot")]
+            Protocol::DOT => self.forward_dot(domain, record_type, record_class, budget),
+            #[cfg(feature = "doh")]
+            Protocol::DOH => self.forward_doh(domain, record_type, record_class, budget),
+            #[cfg(not(feature = "dot"))]
+            Protocol::DOT => Err(Error::ResolverError(
+                "protocol DOT requires the dot feature".into(),
+            )),
+            #[cfg(not(feature = "doh"))]
+            Protocol::DOH => Err(Error::ResolverError(
+                "protocol DOH requires the doh feature".into(),
+            )),
+        }
+    }
+
+    /// Query domain with several types, e.g., both A and AAAA.
+    ///
+    /// Name servers are only walked from the root for the first type. The rest of types are asked to the name servers
+    /// which answer the first one directly. Each type gets its own result, so one failing type doesn't fail the others.
+    /// There are no name servers to walk over DoT or DoH, so only [`Protocol::UDP`] and [`Protocol::TCP`] are
+    /// supported.
+    pub fn query_all(
+        &self,
+        domain: &str,
+        record_types: &[RecordType],
+    ) -> Result<Vec<Result<Message, Error>>, Error> {
+        let domain = &utils::to_ascii(domain)?;
+        self.validate_domain(domain)?;
+
+        if !matches!(self.protocol, Protocol::UDP | Protocol::TCP) {
+            return Err(Error::ResolverError(format!(
+                "protocol {:?} isn't supported by query_all",
+                self.protocol
+            )));
+        }
+
+        let mut name_server_ips = self.roots.clone();
+        let mut results = Vec::with_capacity(record_types.len());
+
+        for record_type in record_types {
+            if let Err(err) = Self::check_query_type(record_type) {
+                results.push(Err(err));
+                continue;
+            }
+
+            if let Some(blocked) = self.check_blocklist(domain, record_type) {
+                results.push(blocked);
+                continue;
+            }
+
+            let budget = self.new_budget();
+            let mut result = self.resolve_from(
+                &name_server_ips,
+                domain,
+                record_type,
+                &RecordClass::IN,
+                0,
+                &budget,
+            );
+
+            // The name servers found previously might not be able to answer this type, start over from the root.
+            if result.is_err() && name_server_ips != self.roots {
+                result = self.resolve_from(
+                    &self.roots,
+                    domain,
+                    record_type,
+                    &RecordClass::IN,
+                    0,
+                    &budget,
+                );
+            }
+
+            results.push(result.and_then(|(message, ips)| {
+                name_server_ips = ips;
+                self.check_cnames(&message, record_type, &RecordClass::IN, &budget)?;
+                Ok(message)
+            }));
+        }
+
+        Ok(results)
+    }
+
+    /// Send a single query to `server` and return its response without walking from the root, like `dig @server`.
+    ///
+    /// Referrals are returned as they are instead of being followed, and neither the cache nor the blocklist is
+    /// consulted. It's sent over TCP with [`Protocol::TCP`], and UDP otherwise.
+    /// Set `recursion_desired` when `server` is a recursive resolver that should resolve the domain for us.
+    pub fn query_at(
+        &self,
+        server: IpAddr,
+        domain: &str,
+        record_type: &RecordType,
+        recursion_desired: bool,
+    ) -> Result<Message, Error> {
+        let domain = &utils::to_ascii(domain)?;
+        self.validate_domain(domain)?;
+
+        let mut query = self.new_query(domain, record_type, &RecordClass::IN);
+        if recursion_desired {
+            query = query.with_recursion_desired();
+        }
+        let queries = [(SocketAddr::new(server, self.port), query)];
+        let (message, _, _) = self.exchange(&queries, record_type, self.new_budget().deadline)?;
+        message.check_rcode()?;
+
+        Ok(message)
+    }
+
+    /// Resolve the addresses to connect to a host, from both AAAA and A records, in the order to try them.
+    ///
+    /// AAAA and A are queried at the same time. Once A is resolved, AAAA is only waited for the
+    /// [resolution delay](ResolverBuilder::resolution_delay), so a slow IPv6 path doesn't hold connections back.
+    /// The addresses are interleaved, starting with IPv6. It fails only if A fails and AAAA doesn't succeed.
+    ///
+    /// See [RFC 8305, 3. Hostname Resolution Query Handling](https://www.rfc-editor.org/rfc/rfc8305.html).
+    pub fn resolve_addrs(&self, domain: &str) -> Result<Vec<IpAddr>, Error> {
+        let (sender, receiver) = mpsc::channel();
+        let resolver = self.clone();
+        let aaaa_domain = domain.to_owned();
+        let query_aaaa = move || {
+            // Nobody receives it anymore if AAAA takes longer than the resolution delay.
+            let _ = sender.send(resolver.query(&aaaa_domain, &RecordType::AAAA));
+        };
+        match self.source_port {
+            // Both queries can't be sent from the same port at once.
+            Some(_) => query_aaaa(),
+            None => {
+                thread::spawn(query_aaaa);
+            }
+        }
+
+        let a = self.query(domain, &RecordType::A);
+        let aaaa = match a {
+            Ok(_) => receiver.recv_timeout(self.resolution_delay).ok(),
+            // There might still be IPv6 addresses to connect to.
+            Err(_) => receiver.recv().ok(),
+        };
+
+        let v4 = match a {
+            Ok(message) => Self::extract_addrs(&message.answers),
+            Err(err) => match aaaa {
+                Some(Ok(_)) => {
+                    debug!("can't resolve A of {domain}: {err}");
+                    vec![]
+                }
+                _ => return Err(err),
+            },
+        };
+        let v6 = match aaaa {
+            Some(Ok(message)) => Self::extract_addrs(&message.answers),
+            Some(Err(err)) => {
+                debug!("can't resolve AAAA of {domain}: {err}");
+                vec![]
+            }
+            None => {
+                debug!("AAAA of {domain} isn't resolved within the resolution delay");
+                vec![]
+            }
+        };
+
+        Ok(Self::interleave(v6, v4))
+    }
+
+    /// The first IPv4 address of a host, from its A records.
+    pub fn resolve_ipv4(&self, domain: &str) -> Result<Ipv4Addr, Error> {
+        let message = self.query(domain, &RecordType::A)?;
+
+        message
+            .answers
+            .iter()
+            .find_map(|rr| match rr.r_data {
+                RecordData::A(ip) => Some(ip),
+                _ => None,
+            })
+            .ok_or_else(|| Error::ResolverError(format!("no A record found for {domain}")))
+    }
+
+    /// Every IPv4 address of a host, from its A records, e.g., to spread connections over them.
+    ///
+    /// They are in the order of the response unless `shuffle` is set, which shuffles them on every call for
+    /// round-robin, even if the answer comes from the cache.
+    pub fn resolve_all_ipv4(&self, domain: &str, shuffle: bool) -> Result<Vec<Ipv4Addr>, Error> {
+        let mut ips = self.query(domain, &RecordType::A)?.a_records();
+        if ips.is_empty() {
+            return Err(Error::ResolverError(format!(
+                "no A record found for {domain}"
+            )));
+        }
+
+        if shuffle {
+            ips.shuffle(&mut thread_rng());
+        }
+
+        Ok(ips)
+    }
+
+    /// The first IPv6 address of a host, from its AAAA records.
+    pub fn resolve_ipv6(&self, domain: &str) -> Result<Ipv6Addr, Error> {
+        let message = self.query(domain, &RecordType::AAAA)?;
+
+        message
+            .answers
+            .iter()
+            .find_map(|rr| match rr.r_data {
+                RecordData::AAAA(ip) => Some(ip),
+                _ => None,
+            })
+            .ok_or_else(|| Error::ResolverError(format!("no AAAA record found for {domain}")))
+    }
+
+    /// Look up host names of an IP address with PTR records.
+    pub fn reverse_lookup(&self, ip: IpAddr) -> Result<Vec<String>, Error> {
+        if ip.is_unspecified() {
+            return Err(Error::ResolverError(format!(
+                "can't look up unspecified address: {ip}"
+            )));
+        }
+
+        let message = self.query(&utils::reverse_domain(&ip), &RecordType::PTR)?;
+
+        Ok(message
+            .answers
+            .into_iter()
+            .filter_map(|rr| match rr.r_data {
+                RecordData::PTR(domain) => Some(domain),
+                _ => None,
+            })
+            .collect())
+    }
+
+    /// Look up the authoritative name servers of a zone, from the NS records at its apex.
+    ///
+    /// The delegation is walked from the root as usual, and it stops at the name servers of the zone,
+    /// which answer with their own NS records. Each name server comes with the glue addresses found for it,
+    /// which is empty if there is none. Fails if the domain has no NS records, e.g., it isn't a zone apex.
+    pub fn authoritative_servers(&self, zone: &str) -> Result<Vec<(String, Vec<IpAddr>)>, Error> {
+        let message = self.query(zone, &RecordType::NS)?;
+        let name_servers = Self::pair_glue(&message.answers, &message.additionals).name_servers;
+
+        if name_servers.is_empty() {
+            return Err(Error::ResolverError(format!(
+                "no NS record found for {zone}"
+            )));
+        }
+
+        Ok(name_servers)
+    }
+
+    /// Transfer every record of `zone` from one of its name servers with AXFR, which is always done over TCP.
+    ///
+    /// The records start with the SOA of the zone. The same SOA is sent again to close the transfer, which is left out.
+    ///
+    /// See [RFC 5936, 2.2. AXFR Response](https://www.rfc-editor.org/rfc/rfc5936.html).
+    pub fn zone_transfer(
+        &self,
+        zone: &str,
+        name_server: IpAddr,
+    ) -> Result<Vec<ResourceRecord>, Error> {
+        let zone = &utils::to_ascii(zone)?;
+        self.validate_domain(zone)?;
+
+        let addr = SocketAddr::new(name_server, self.port);
+        let query = Message::new_query_with_class(zone, &RecordType::AXFR, &RecordClass::IN);
+        let mut stream = transport::tcp::send(&addr, &query.to_query_bytes()?, self.timeout)?;
+        let mut records: Vec<ResourceRecord> = vec![];
+
+        // Each message comes with its own length prefix, until the closing SOA shows up.
+        loop {
+            let response = transport::read_message(&mut stream)?;
+            trace!("received: {} bytes from {addr}", response.len());
+
+            let message =
+                Message::with_transfer_response(&response, &query, self.keep_unknown_records)?;
+            if message.answers.is_empty() {
+                return Err(Error::ResolverError(format!(
+                    "zone transfer of {zone} ended before the closing SOA"
+                )));
+            }
+
+            for rr in message.answers {
+                let is_soa = rr.r_type == Some(RecordType::SOA);
+                if records.is_empty() && !is_soa {
+                    return Err(Error::ResolverError(format!(
+                        "zone transfer of {zone} doesn't start with SOA"
+                    )));
+                }
+                if is_soa && !records.is_empty() {
+                    debug!("transferred {} records of {zone}", records.len());
+                    return Ok(records);
+                }
+                records.push(rr);
+            }
+        }
+    }
+}
+
+impl Resolver {
+    /// Pair NS names in the authority section with their glue, see [`Resolver::pair_glue`].
+    ///
+    /// The name servers of `zone` can only refer `domain` to a zone below their own, which `domain` is in.
+    /// Otherwise they could take over names they have no authority over, so NS records of any other zone are dropped.
+    fn collect_delegation(message: &Message, domain: &str, zone: &str) -> Delegation {
+        let below_zone = |owner: &str| {
+            utils::is_subdomain(domain, owner)
+                && utils::is_subdomain(owner, zone)
+                && !utils::is_subdomain(zone, owner)
+        };
+        let records: Vec<_> = message
+            .authorities
             .iter()
-            .filter(|rr| rr.r_type == *record_type)
+            .filter(|rr| rr.r_type == Some(RecordType::NS) && below_zone(&rr.name))
+            .collect();
+
+        Self::pair_glue(records, &message.additionals)
+    }
+
+    /// Pair NS names in `records` with their glue, i.e., A and AAAA records in `additionals`.
+    ///
+    /// Glue is only taken for names within the delegated zone. Otherwise a name server could redirect later queries
+    /// anywhere with addresses of names it has no authority over, so those names are resolved instead.
+    fn pair_glue<'a>(
+        records: impl IntoIterator<Item = &'a ResourceRecord>,
+        additionals: &[ResourceRecord],
+    ) -> Delegation {
+        let mut zone = None;
+        let name_servers = records
+            .into_iter()
             .filter_map(|rr| match &rr.r_data {
-                RecordData::NS(domain) => Some(domain.to_owned()),
+                RecordData::NS(name) => Some((name, &rr.name)),
+                _ => None,
+            })
+            .map(|(name, owner)| {
+                zone.get_or_insert_with(|| owner.clone());
+                let in_bailiwick = utils::is_subdomain(name, owner);
+                let glue = additionals
+                    .iter()
+                    .filter(|rr| in_bailiwick && rr.name.eq_ignore_ascii_case(name))
+                    .filter_map(|rr| match rr.r_data {
+                        RecordData::A(ip) => Some(ip.into()),
+                        RecordData::AAAA(ip) => Some(ip.into()),
+                        _ => None,
+                    })
+                    .collect();
+                (name.to_owned(), glue)
+            })
+            .collect();
+
+        Delegation {
+            zone: zone.unwrap_or_default(),
+            name_servers,
+        }
+    }
+
+    /// Addresses of the [`AddressFamily`] in A and AAAA records.
+    fn extract_ips(&self, records: &[ResourceRecord]) -> Vec<IpAddr> {
+        records
+            .iter()
+            .filter_map(|rr| match rr.r_data {
+                RecordData::A(ip) => Some(ip.into()),
+                RecordData::AAAA(ip) => Some(ip.into()),
                 _ => None,
             })
+            .filter(|ip| self.address_family.allows(ip))
             .collect()
     }
 
-    fn extract_ipv4_ips(records: &[ResourceRecord]) -> Vec<Ipv4Addr> {
+    /// Addresses in A and AAAA records, regardless of the [`AddressFamily`].
+    fn extract_addrs(records: &[ResourceRecord]) -> Vec<IpAddr> {
         records
             .iter()
-            .filter_map(|rr| {
-                if rr.r_type == RecordType::A {
-                    rr.ipv4_ip()
-                } else {
-                    None
-                }
+            .filter_map(|rr| match rr.r_data {
+                RecordData::A(ip) => Some(ip.into()),
+                RecordData::AAAA(ip) => Some(ip.into()),
+                _ => None,
             })
             .collect()
     }
 
+    /// Alternate between IPv6 and IPv4 addresses, starting with IPv6.
+    ///
+    /// See [RFC 8305, 4. Sorting Addresses](https://www.rfc-editor.org/rfc/rfc8305.html).
+    fn interleave(v6: Vec<IpAddr>, v4: Vec<IpAddr>) -> Vec<IpAddr> {
+        let mut ips = Vec::with_capacity(v6.len() + v4.len());
+        let (mut v6, mut v4) = (v6.into_iter(), v4.into_iter());
+
+        loop {
+            match (v6.next(), v4.next()) {
+                (None, None) => break,
+                (v6_ip, v4_ip) => ips.extend(v6_ip.into_iter().chain(v4_ip)),
+            }
+        }
+
+        ips
+    }
+
     fn pick_random<T>(candicates: &[T]) -> Result<T, Error>
     where
         T: Clone,
@@ -48,79 +1047,881 @@ impl Resolver {
         Ok(res)
     }
 
-    fn resolve(domain: &str, record_type: &RecordType) -> Result<Message, Error> {
-        println!("Looking up {domain}");
+    /// Pick up to `count` name servers that haven't been tried in this resolution.
+    /// Tried ones are only picked again when there is nothing else left.
+    ///
+    /// The queries of an attempt are sent from a single socket, so the picked ones are of the same address family.
+    fn pick_untried(
+        candidates: &[IpAddr],
+        tried: &HashSet<IpAddr>,
+        count: usize,
+    ) -> Result<Vec<IpAddr>, Error> {
+        let mut untried: Vec<_> = candidates
+            .iter()
+            .filter(|ip| !tried.contains(ip))
+            .copied()
+            .collect();
 
-        let mut attempts = 0;
-        let mut name_server_ip = Self::pick_random(&ROOT_NAME_SERVERS_V4)?;
-        let mut message = Self::resolve_answer(domain, record_type, &name_server_ip)?;
+        if untried.is_empty() {
+            untried = candidates.to_vec();
+        }
 
-        while attempts < MAX_ATTEMPTS {
-            if !message.answers.is_empty() {
-                return Ok(message);
-            }
-
-            // Use name server IPs from "additional" fields in resource records
-            name_server_ip = if !message.additionals.is_empty() {
-                let name_server_ips = Self::extract_ipv4_ips(&message.additionals);
-                let ip = Self::pick_random(&name_server_ips)?;
-                println!("got {ip} from additional sections");
-                ip
-            }
-            // If there is no IP from additional resource records, we need to parse from authority domains
-            // e.g., max.ns.cloudflare.com (the authoritative server for blog.wtcx.dev)
-            else if !message.authorities.is_empty() {
-                let name_server_domains: Vec<_> =
-                    Self::extract_domains(&message.authorities, &RecordType::NS);
-                let name_server_domain = Self::pick_random(&name_server_domains)?;
-                let ns_message = Self::resolve(&name_server_domain, &RecordType::A)?;
-                let name_server_ips = Self::extract_ipv4_ips(&ns_message.answers);
-                println!("Looking up {domain} using {name_server_ip} ({name_server_domain})");
-                Self::pick_random(&name_server_ips)?
-            } else {
-                return Err(Error::ResolverError(
-                    "it's really impossible but let's just explode".into(),
-                ));
-            };
+        if untried.is_empty() {
+            return Err(Error::ResolverError("can't pick name server".into()));
+        }
 
-            println!("continue to look up {domain} with name server IP {name_server_ip}");
-            message = Self::resolve_answer(domain, record_type, &name_server_ip)?;
+        untried.shuffle(&mut thread_rng());
+        let is_ipv4 = untried[0].is_ipv4();
+        untried.retain(|ip| ip.is_ipv4() == is_ipv4);
+        untried.truncate(count);
 
-            attempts += 1;
-        }
+        Ok(untried)
+    }
 
-        Err(Error::ResolverError(format!(
-            "problem resolving address: {domain}"
-        )))
+    /// Find out where to continue from the delegation in a response without answers.
+    fn referral(&self, delegation: Delegation) -> Result<Referral, Error> {
+        // Name servers with glue can be asked right away.
+        let ips: Vec<_> = delegation
+            .name_servers
+            .iter()
+            .flat_map(|(_, glue)| glue.iter().filter(|ip| self.address_family.allows(ip)))
+            .copied()
+            .collect();
+        if !ips.is_empty() {
+            debug!("got {ips:?} from additional sections");
+            Ok(Referral::NameServers(ips))
+        }
+        // If no name server has glue, the address of one of them has to be resolved,
+        // e.g., max.ns.cloudflare.com (the authoritative server for blog.wtcx.dev)
+        else if !delegation.name_servers.is_empty() {
+            let name_server_domains: Vec<_> = delegation
+                .name_servers
+                .into_iter()
+                .map(|(name, _)| name)
+                .collect();
+            let name_server_domain = Self::pick_random(&name_server_domains)?;
+            if self.require_glue {
+                return Err(Error::ResolverError(format!(
+                    "referral to {name_server_domain} comes without glue"
+                )));
+            }
+            Ok(Referral::NameServerDomain(name_server_domain))
+        } else {
+            Err(Error::ResolverError(
+                "response has neither answers nor a referral".into(),
+            ))
+        }
     }
 
-    fn resolve_answer(
+    /// `depth` is how many name server domains are being resolved in order to resolve the original domain.
+    /// `budget` is what's left to resolve the original domain.
+    fn resolve(
+        &self,
         domain: &str,
         record_type: &RecordType,
-        name_server_ip: &Ipv4Addr,
+        record_class: &RecordClass,
+        depth: usize,
+        budget: &Budget,
     ) -> Result<Message, Error> {
-        let query = Message::new_query(domain, record_type);
-        let addr = format!("{name_server_ip}:53");
-        // port 0 = randomly picked by OS
-        let socket = UdpSocket::bind("0.0.0.0:0").map_err(Error::NetworkError)?;
-        let bytes_sent = socket
-            .send_to(&query.to_query_bytes(), addr)
-            .map_err(Error::NetworkError)?;
+        let roots = &self.roots;
+        let (message, _) =
+            self.resolve_from(roots, domain, record_type, record_class, depth, budget)?;
+        Ok(message)
+    }
 
-        // 4.2.1. UDP usage
-        // ...Messages carried by UDP are restricted to 512 bytes (not counting the IP or UDP headers).
-        let mut response = [0; 512];
-        let bytes_received = socket.recv(&mut response).map_err(Error::NetworkError)?;
+    /// Resolve starting from the given name servers.
+    /// Returns the answer and the name servers it came from.
+    fn resolve_from(
+        &self,
+        name_server_ips: &[IpAddr],
+        domain: &str,
+        record_type: &RecordType,
+        record_class: &RecordClass,
+        depth: usize,
+        budget: &Budget,
+    ) -> Result<(Message, Vec<IpAddr>), Error> {
+        debug!("Looking up {domain}");
 
-        println!("sent: {bytes_sent} bytes, received: {bytes_received} bytes");
+        // A circular chain of name servers, e.g., ns1.example.com and ns2.example.com are authorities of each other,
+        // will keep us resolving name server domains forever.
+        if depth > self.max_referral_depth {
+            return Err(Error::ResolverError("referral loop detected".into()));
+        }
 
-        Message::with_response(&response, &query)
-    }
+        // Another thread resolving the same question might be stuck, so it's only waited for until the deadline.
+        let wait_until = self.attempt_deadline(Instant::now(), budget.deadline);
+        let options = self.query_options();
+        let lookup = self
+            .cache
+            .lookup(domain, record_type, record_class, &options, wait_until);
+        let _in_flight = match lookup {
+            Lookup::Cached(response) => {
+                debug!("{domain} is answered from the cache");
+                self.observer.on_cache_hit(domain, record_type);
+                budget.keep(&response);
+                let message = self.cached_answer(response, domain, record_type, record_class)?;
+                return Ok((message, self.roots.clone()));
+            }
+            Lookup::Nonexistent(authorities) => {
+                debug!("{domain} is known not to exist");
+                self.observer.on_cache_hit(domain, record_type);
+                return Err(Error::NxDomain { authorities });
+            }
+            Lookup::Resolve(in_flight) => {
+                self.observer.on_cache_miss(domain, record_type);
+                in_flight
+            }
+        };
+
+        let mut name_server_ips = name_server_ips.to_vec();
+        // The zone the name servers are asked as authorities for, starting from the root.
+        let mut zone = String::new();
+        let mut tried = HashSet::new();
+        let mut last = None;
+
+        for _ in 0..self.max_attempts {
+            let picked = Self::pick_untried(&name_server_ips, &tried, self.fan_out)?;
+            tried.extend(&picked);
+            self.spend(budget, picked.len())?;
+
+            debug!("continue to look up {domain} with name server IPs {picked:?}");
+            let queries = self.new_queries(domain, record_type, record_class, &picked);
+            let answer = self.exchange(&queries, record_type, budget.deadline);
+            let message = match answer {
+                // The last exchange is the one answering the domain, after those of name server domains.
+                Ok((message, stats, response)) => {
+                    *budget.stats.lock().unwrap() = stats;
+                    budget.keep(&response);
+                    message
+                }
+                // The name servers might be down or unreachable, try other ones.
+                Err(Error::NetworkError(err)) => {
+                    debug!("can't reach name servers {picked:?}: {err}");
+                    self.observe_failure(&picked, record_type, &err);
+                    continue;
+                }
+                // Other name servers of the zone might still be able to answer.
+                Err(Error::ServerError(err)) if err.is_retriable() => {
+                    debug!("name servers {picked:?} failed to answer: {err}");
+                    self.observer.on_retry(&picked, record_type);
+                    continue;
+                }
+                Err(err) => return Err(err),
+            };
+
+            // Only NXDOMAIN gets here with an error RCODE.
+            if let Err(err) = message.check_rcode() {
+                self.cache_nonexistent(domain, record_class, &message.authorities);
+                return Err(err);
+            }
+
+            if !message.answers.is_empty() {
+                self.cache_answer(domain, record_type, record_class, &message);
+                return Ok((message, name_server_ips));
+            }
+
+            // A recursive resolver has followed the referrals for us, so there is no answer at all.
+            if self.recursion_desired {
+                return Ok((message, name_server_ips));
+            }
+
+            let delegation = Self::collect_delegation(&message, domain, &zone);
+            zone.clone_from(&delegation.zone);
+            let referral = self.referral(delegation)?;
+            last = Some(Box::new(message));
+            name_server_ips = match referral {
+                Referral::NameServers(ips) => ips,
+                Referral::NameServerDomain(name_server_domain) => {
+                    debug!("Looking up {domain} using {name_server_domain}");
+                    self.resolve_name_server(&name_server_domain, depth + 1, budget)?
+                }
+            };
+        }
+
+        Err(Error::ResolutionFailed {
+            domain: domain.into(),
+            last,
+        })
+    }
+
+    /// Resolve the addresses of a name server, with A and/or AAAA records depending on the [`AddressFamily`].
+    fn resolve_name_server(
+        &self,
+        domain: &str,
+        depth: usize,
+        budget: &Budget,
+    ) -> Result<Vec<IpAddr>, Error> {
+        let mut ips = vec![];
+        let mut last_err = None;
+
+        for record_type in self.address_family.record_types() {
+            match self.resolve(domain, record_type, &RecordClass::IN, depth, budget) {
+                Ok(message) => ips.extend(self.extract_ips(&message.answers)),
+                Err(err) => last_err = Some(err),
+            }
+        }
+
+        match last_err {
+            Some(err) if ips.is_empty() => Err(err),
+            _ => Ok(ips),
+        }
+    }
+
+    /// See [`ResolverBuilder::validate_cnames`].
+    fn check_cnames(
+        &self,
+        message: &Message,
+        record_type: &RecordType,
+        record_class: &RecordClass,
+        budget: &Budget,
+    ) -> Result<(), Error> {
+        if !self.validate_cnames {
+            return Ok(());
+        }
+        message.check_cnames()?;
+
+        match message.unresolved_cname() {
+            Some((name, target)) => {
+                // Resolving the target is another exchange, which isn't how the answer is received.
+                let budget = Budget {
+                    queries: AtomicUsize::new(budget.queries.load(Ordering::SeqCst)),
+                    deadline: budget.deadline,
+                    stats: Mutex::new(*budget.stats.lock().unwrap()),
+                    keep_raw: false,
+                    raw: Mutex::new(None),
+                };
+                let answer =
+                    self.resolve_with_protocol(&target, record_type, record_class, &budget);
+                Self::check_cname_target(&name, &target, answer)
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// A CNAME is dangling if its target turns out not to exist (NXDOMAIN), or has no records (NODATA).
+    fn check_cname_target(
+        name: &str,
+        target: &str,
+        answer: Result<Message, Error>,
+    ) -> Result<(), Error> {
+        match answer {
+            Ok(message) if !message.answers.is_empty() => Ok(()),
+            Ok(_) | Err(Error::NxDomain { .. }) => Err(Error::InvalidResponse(format!(
+                "CNAME of {name} points to {target}, which has no records"
+            ))),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Zone transfers have their own exchange, which isn't a query like the others.
+    fn check_query_type(record_type: &RecordType) -> Result<(), Error> {
+        match record_type {
+            RecordType::AXFR => Err(Error::ResolverError(
+                "AXFR has to be done with Resolver::zone_transfer".into(),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// See [`ResolverBuilder::max_labels`].
+    fn validate_domain(&self, domain: &str) -> Result<(), Error> {
+        utils::validate_domain_with_max_labels(domain, self.max_labels)
+    }
+
+    fn new_budget(&self) -> Budget {
+        Budget {
+            queries: AtomicUsize::new(0),
+            deadline: self
+                .resolution_timeout
+                .and_then(|timeout| Instant::now().checked_add(timeout)),
+            stats: Mutex::new(QueryStats {
+                truncated: false,
+                transport: self.protocol,
+            }),
+            keep_raw: false,
+            raw: Mutex::new(None),
+        }
+    }
+
+    /// Count `count` more queries to be sent, which fails if there are more than [`ResolverBuilder::max_queries`],
+    /// or the [resolution timeout](ResolverBuilder::resolution_timeout) has passed.
+    /// A pathological delegation can't keep us sending queries this way, even if every resolution has its own attempts.
+    fn spend(&self, budget: &Budget, count: usize) -> Result<(), Error> {
+        if budget
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            return Err(Error::ResolverError("overall deadline exceeded".into()));
+        }
+
+        if budget.queries.fetch_add(count, Ordering::SeqCst) + count > self.max_queries {
+            return Err(Error::ResolverError("query budget exceeded".into()));
+        }
+
+        Ok(())
+    }
+
+    /// When to stop waiting for the name servers of an attempt started at `start`,
+    /// which is the timeout unless the resolution has to end earlier.
+    fn attempt_deadline(&self, start: Instant, deadline: Option<Instant>) -> Instant {
+        let attempt_deadline = start + self.timeout;
+        deadline.map_or(attempt_deadline, |deadline| deadline.min(attempt_deadline))
+    }
+
+    /// How long an answer can be cached, which is the smallest TTL of the answers.
+    /// Answers with zero TTL are not cached.
+    fn answer_ttl(message: &Message) -> Option<Duration> {
+        message.min_ttl().filter(|ttl| !ttl.is_zero())
+    }
+
+    /// Parse the response to `query`, with NXDOMAIN left to be checked, see [`Message::parse_response`].
+    /// Bytes after the message fail it with [strict parsing](ResolverBuilder::strict_parsing), and records of types
+    /// we don't know are kept if [asked for](ResolverBuilder::keep_unknown_records).
+    fn parse_response(&self, buf: &[u8], query: &Message) -> Result<Message, Error> {
+        match self.strict_parsing {
+            true => Message::parse_response_strict(buf, query, self.keep_unknown_records),
+            false => Message::parse_response(buf, query, self.keep_unknown_records),
+        }
+    }
+
+    /// Same as [`Message::with_response`], but parsed like [`Resolver::parse_response`].
+    #[cfg(any(feature = "dot", feature = "doh", feature = "tokio"))]
+    fn with_response(&self, buf: &[u8], query: &Message) -> Result<Message, Error> {
+        let message = self.parse_response(buf, query)?;
+        message.check_rcode()?;
+
+        Ok(message)
+    }
+
+    fn cache_answer(
+        &self,
+        domain: &str,
+        record_type: &RecordType,
+        record_class: &RecordClass,
+        message: &Message,
+    ) {
+        let ttl = Self::answer_ttl(message);
+        if let (Some(ttl), Ok(response)) = (ttl, message.to_bytes()) {
+            let options = self.query_options();
+            self.cache
+                .insert_answer(domain, record_type, record_class, &options, response, ttl);
+        }
+    }
+
+    fn cache_nonexistent(
+        &self,
+        domain: &str,
+        record_class: &RecordClass,
+        authorities: &[ResourceRecord],
+    ) {
+        if let Some(ttl) = Self::negative_ttl(authorities) {
+            debug!("{domain} doesn't exist, remember it for {ttl:?}");
+            let options = self.query_options();
+            self.cache.insert_nonexistent(
+                domain,
+                record_class,
+                &options,
+                authorities.to_vec(),
+                ttl,
+            );
+        }
+    }
+
+    /// Parse a cached response as if it's the response to a new query.
+    fn cached_answer(
+        &self,
+        mut response: Vec<u8>,
+        domain: &str,
+        record_type: &RecordType,
+        record_class: &RecordClass,
+    ) -> Result<Message, Error> {
+        // Not `Resolver::new_query`, as the casing of a randomized question would never match the cached one.
+        let query = Message::new_query_with_class(domain, record_type, record_class);
+        response[0..2].copy_from_slice(&query.id().to_be_bytes());
+
+        let message = Message::parse_response(&response, &query, self.keep_unknown_records)?;
+        message.check_rcode()?;
+
+        Ok(message)
+    }
+
+    /// How long a negative answer can be cached, which is the smaller one of the TTL and `MINIMUM` of the SOA record.
+    /// It's not cached without SOA.
+    ///
+    /// See [RFC 2308, 5 - Caching Negative Answers](https://www.rfc-editor.org/rfc/rfc2308.html).
+    fn negative_ttl(authorities: &[ResourceRecord]) -> Option<Duration> {
+        authorities.iter().find_map(|rr| match &rr.r_data {
+            RecordData::SOA(soa) => Some(Duration::from_secs(rr.ttl.min(soa.minimum()).into())),
+            _ => None,
+        })
+    }
+
+    fn new_query(
+        &self,
+        domain: &str,
+        record_type: &RecordType,
+        record_class: &RecordClass,
+    ) -> Message {
+        let mut query = Message::new_query_with_class(domain, record_type, record_class);
+        if self.recursion_desired {
+            query = query.with_recursion_desired();
+        }
+        if let Some(size) = self.edns_payload_size() {
+            let mut edns = Edns::new(size);
+            if self.dnssec_ok {
+                edns = edns.with_dnssec_ok();
+            }
+            if let Some(client_subnet) = self.client_subnet {
+                edns = edns.with_client_subnet(client_subnet);
+            }
+            query = query.with_edns(edns);
+        }
+        if self.checking_disabled {
+            query = query.with_checking_disabled();
+        }
+        if self.randomize_case {
+            query = query.with_randomized_case();
+        }
+
+        query
+    }
+
+    /// Options of queries that change the answers, see [`QueryOptions`].
+    fn query_options(&self) -> QueryOptions {
+        QueryOptions {
+            dnssec_ok: self.dnssec_ok,
+            checking_disabled: self.checking_disabled,
+            client_subnet: self.client_subnet,
+            keep_unknown_records: self.keep_unknown_records,
+        }
+    }
+
+    /// The UDP payload size advertised with EDNS(0), if it's enabled.
+    fn edns_payload_size(&self) -> Option<u16> {
+        // The DO bit and ECS are carried by EDNS(0).
+        let needs_edns = self.dnssec_ok || self.client_subnet.is_some();
+        self.edns_payload_size
+            .or(needs_edns.then_some(DEFAULT_EDNS_PAYLOAD_SIZE))
+    }
+
+    /// Where UDP sockets sending queries to `name_server` are bound to.
+    /// The bind address is only used for name servers of the same address family.
+    fn local_addr(&self, name_server: &IpAddr) -> SocketAddr {
+        let ip = match self.bind_addr {
+            Some(ip) if ip.is_ipv4() == name_server.is_ipv4() => ip,
+            _ if name_server.is_ipv4() => Ipv4Addr::UNSPECIFIED.into(),
+            _ => Ipv6Addr::UNSPECIFIED.into(),
+        };
+        // port 0 = randomly picked by OS
+        SocketAddr::new(ip, self.source_port.unwrap_or(0))
+    }
+
+    fn udp_buffer_size(&self) -> usize {
+        // 4.2.1. UDP usage
+        // ...Messages carried by UDP are restricted to 512 bytes (not counting the IP or UDP headers).
+        //
+        // Unless we tell the name server we can handle more with EDNS(0).
+        self.edns_payload_size().unwrap_or(512).max(512) as usize
+    }
+
+    /// Send each query to its name server over UDP or TCP, depending on the [`Protocol`].
+    /// The bytes of the response are returned along with the parsed message.
+    fn exchange(
+        &self,
+        queries: &[(SocketAddr, Message)],
+        record_type: &RecordType,
+        deadline: Option<Instant>,
+    ) -> Result<(Message, QueryStats, Vec<u8>), Error> {
+        match self.protocol {
+            Protocol::TCP => {
+                let (message, response) =
+                    self.resolve_answer_tcp(queries, record_type, deadline)?;
+                let stats = QueryStats {
+                    truncated: false,
+                    transport: Protocol::TCP,
+                };
+                Ok((message, stats, response))
+            }
+            _ => self.resolve_answer(queries, record_type, deadline),
+        }
+    }
+
+    /// Send the queries to their name servers at once and use whichever responds first.
+    /// A truncated response is asked again over TCP to the name server sending it.
+    fn resolve_answer(
+        &self,
+        queries: &[(SocketAddr, Message)],
+        record_type: &RecordType,
+        deadline: Option<Instant>,
+    ) -> Result<(Message, QueryStats, Vec<u8>), Error> {
+        let socket =
+            UdpSocket::bind(self.local_addr(&queries[0].0.ip())).map_err(Error::NetworkError)?;
+
+        // Retransmits are the same bytes, so a late response to an earlier copy still matches.
+        let packets = queries
+            .iter()
+            .map(|(addr, query)| Ok((*addr, query.to_query_bytes()?)))
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        let sent_at = Instant::now();
+        self.send_packets(&socket, &packets, record_type)?;
+
+        let deadline = self.attempt_deadline(sent_at, deadline);
+        let mut retransmits = self.retransmits;
+        let mut backoff = self.retransmit_interval;
+        let mut retransmit_at = sent_at.checked_add(backoff).filter(|_| retransmits > 0);
+        let mut response = vec![0; self.udp_buffer_size()];
+
+        loop {
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(Error::NetworkError(io::ErrorKind::TimedOut.into()));
+            }
+
+            let mut wake_at = deadline;
+            if let Some(at) = retransmit_at {
+                if now >= at {
+                    debug!("no response yet, send the queries again");
+                    self.send_packets(&socket, &packets, record_type)?;
+                    retransmits -= 1;
+                    backoff = backoff.saturating_mul(2);
+                    retransmit_at = at.checked_add(backoff).filter(|_| retransmits > 0);
+                    continue;
+                }
+                wake_at = wake_at.min(at);
+            }
+
+            socket
+                .set_read_timeout(Some(wake_at - now))
+                .map_err(Error::NetworkError)?;
+            let (bytes_received, peer) = match socket.recv_from(&mut response) {
+                Ok(received) => received,
+                // Time to retransmit, or to give up.
+                Err(err) if Self::is_timeout(&err) => continue,
+                Err(err) => return Err(Error::NetworkError(err)),
+            };
+            trace!("received: {bytes_received} bytes from {peer}");
+
+            if let Some(query) = Self::match_query(queries, &response[..bytes_received], &peer) {
+                response.truncate(bytes_received);
+                self.observe_response(&peer, record_type, &response, sent_at);
+
+                // The rest of the response doesn't fit in the datagram, and what's left might be cut in the middle
+                // of a record, so it isn't parsed at all.
+                //
+                // See [RFC 2181, 9. The TC (truncated) header bit](https://www.rfc-editor.org/rfc/rfc2181.html).
+                if !Self::is_truncated(&response) {
+                    let stats = QueryStats {
+                        truncated: false,
+                        transport: Protocol::UDP,
+                    };
+                    return Ok((self.parse_response(&response, query)?, stats, response));
+                }
+
+                debug!("response from {peer} is truncated, ask it again over TCP");
+                // It's still the same attempt, so it has the time left of it.
+                let response = self.send_tcp(&peer, query, record_type, Some(deadline))?;
+                let stats = QueryStats {
+                    truncated: true,
+                    transport: Protocol::TCP,
+                };
+                return Ok((self.parse_response(&response, query)?, stats, response));
+            }
+        }
+    }
+
+    /// Send each packet to its name server. It only fails if none of them can be sent,
+    /// as a name server the network can't reach doesn't stop the others from answering.
+    fn send_packets(
+        &self,
+        socket: &UdpSocket,
+        packets: &[(SocketAddr, Vec<u8>)],
+        record_type: &RecordType,
+    ) -> Result<(), Error> {
+        let mut sent = 0;
+        let mut last_err = None;
+
+        for (addr, packet) in packets {
+            match socket.send_to(packet, addr) {
+                Ok(bytes_sent) => {
+                    trace!("sent: {bytes_sent} bytes to {addr}");
+                    self.observer.on_query_sent(addr, record_type);
+                    sent += 1;
+                }
+                Err(err) => {
+                    debug!("can't send query to {addr}: {err}");
+                    last_err = Some(err);
+                }
+            }
+        }
+
+        match last_err {
+            Some(err) if sent == 0 => Err(Error::NetworkError(err)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Ask the name servers one by one over TCP, until one of them responds.
+    fn resolve_answer_tcp(
+        &self,
+        queries: &[(SocketAddr, Message)],
+        record_type: &RecordType,
+        deadline: Option<Instant>,
+    ) -> Result<(Message, Vec<u8>), Error> {
+        let mut last_err = Error::NetworkError(io::ErrorKind::TimedOut.into());
+
+        for (addr, query) in queries {
+            match self.send_tcp(addr, query, record_type, deadline) {
+                Ok(response) => return Ok((self.parse_response(&response, query)?, response)),
+                Err(err) => {
+                    debug!("can't query {addr} over TCP: {err}");
+                    last_err = err;
+                }
+            }
+        }
+
+        Err(last_err)
+    }
+
+    /// Send the query to the name server over TCP, and return the response without parsing it.
+    fn send_tcp(
+        &self,
+        addr: &SocketAddr,
+        query: &Message,
+        record_type: &RecordType,
+        deadline: Option<Instant>,
+    ) -> Result<Vec<u8>, Error> {
+        let sent_at = Instant::now();
+        let timeout = self.attempt_deadline(sent_at, deadline) - sent_at;
+        if timeout.is_zero() {
+            return Err(Error::NetworkError(io::ErrorKind::TimedOut.into()));
+        }
+
+        self.observer.on_query_sent(addr, record_type);
+        let response = self.tcp_pool.exchange(addr, &query.to_query_bytes()?, || {
+            transport::tcp::connect(addr, timeout)
+        })?;
+        trace!("received: {} bytes from {addr}", response.len());
+        self.observe_response(addr, record_type, &response, sent_at);
+
+        Ok(response)
+    }
+
+    /// Send the query to the configured DNS over TLS server, which resolves it recursively for us.
+    #[cfg(feature = "dot")]
+    fn forward_dot(
+        &self,
+        domain: &str,
+        record_type: &RecordType,
+        record_class: &RecordClass,
+        budget: &Budget,
+    ) -> Result<Message, Error> {
+        let (mut addr, hostname) = self
+            .dot_server
+            .clone()
+            .ok_or_else(|| Error::ResolverError("DoT server is not configured".into()))?;
+        if addr.port() == 0 {
+            addr.set_port(transport::dot::DOT_PORT);
+        }
+
+        self.forward(domain, record_type, record_class, budget, |query| {
+            let response = self.dot_pool.exchange(&addr, query, || {
+                transport::dot::connect(&addr, &hostname, self.timeout)
+            })?;
+            trace!(
+                "received: {} bytes from {addr} ({hostname})",
+                response.len()
+            );
+            Ok(response)
+        })
+    }
+
+    /// Send the query to the configured DNS over HTTPS endpoint, which resolves it recursively for us.
+    #[cfg(feature = "doh")]
+    fn forward_doh(
+        &self,
+        domain: &str,
+        record_type: &RecordType,
+        record_class: &RecordClass,
+        budget: &Budget,
+    ) -> Result<Message, Error> {
+        let endpoint = self
+            .doh_endpoint
+            .as_deref()
+            .ok_or_else(|| Error::ResolverError("DoH endpoint is not configured".into()))?;
+
+        self.forward(domain, record_type, record_class, budget, |query| {
+            let response = transport::doh::exchange(endpoint, query, self.timeout)?;
+            trace!("received: {} bytes from {endpoint}", response.len());
+            Ok(response)
+        })
+    }
+
+    /// Send a query with recursion desired through `exchange` and parse the response.
+    #[cfg(any(feature = "dot", feature = "doh"))]
+    fn forward<F>(
+        &self,
+        domain: &str,
+        record_type: &RecordType,
+        record_class: &RecordClass,
+        budget: &Budget,
+        exchange: F,
+    ) -> Result<Message, Error>
+    where
+        F: FnOnce(&[u8]) -> Result<Vec<u8>, Error>,
+    {
+        let query = self
+            .new_query(domain, record_type, record_class)
+            .with_recursion_desired();
+        let response = exchange(&query.to_query_bytes()?)?;
+        budget.keep(&response);
+
+        self.with_response(&response, &query)
+    }
+
+    fn check_blocklist(
+        &self,
+        domain: &str,
+        record_type: &RecordType,
+    ) -> Option<Result<Message, Error>> {
+        self.blocklist.as_ref()?.check(domain, record_type)
+    }
+
+    /// Tell the observer how a name server responds, before the response is parsed,
+    /// so an error RCODE is observed as well.
+    fn observe_response(
+        &self,
+        server: &SocketAddr,
+        record_type: &RecordType,
+        response: &[u8],
+        sent_at: Instant,
+    ) {
+        if let Some(flags) = response.get(2..4) {
+            let flags = HeaderFlags::from_u16(u16::from_be_bytes([flags[0], flags[1]]));
+            let latency = sent_at.elapsed();
+            self.observer
+                .on_response(server, record_type, u16::from(flags.r_code), latency);
+        }
+    }
+
+    /// Tell the observer that the name servers can't be reached, which might be a timeout.
+    fn observe_failure(&self, servers: &[IpAddr], record_type: &RecordType, err: &io::Error) {
+        if Self::is_timeout(err) {
+            self.observer.on_timeout(servers, record_type);
+        }
+        self.observer.on_retry(servers, record_type);
+    }
+
+    /// Whether the TC bit is set in the header of the response.
+    fn is_truncated(response: &[u8]) -> bool {
+        response.get(2..4).is_some_and(|flags| {
+            HeaderFlags::from_u16(u16::from_be_bytes([flags[0], flags[1]])).truncated
+        })
+    }
+
+    fn is_timeout(err: &io::Error) -> bool {
+        // A read timeout is reported as `WouldBlock` on some platforms.
+        matches!(
+            err.kind(),
+            io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+        )
+    }
+
+    /// Build a query for each name server. Every query has its own random ID.
+    fn new_queries(
+        &self,
+        domain: &str,
+        record_type: &RecordType,
+        record_class: &RecordClass,
+        name_server_ips: &[IpAddr],
+    ) -> Vec<(SocketAddr, Message)> {
+        name_server_ips
+            .iter()
+            .map(|ip| {
+                let addr = SocketAddr::from((*ip, self.port));
+                (addr, self.new_query(domain, record_type, record_class))
+            })
+            .collect()
+    }
+
+    /// Find the query a response is for. The response has to come from the name server the query was sent to,
+    /// and carry the same ID and question. Otherwise it's ignored, so a spoofed response doesn't stop the real one
+    /// from being accepted.
+    fn match_query<'a>(
+        queries: &'a [(SocketAddr, Message)],
+        response: &[u8],
+        peer: &SocketAddr,
+    ) -> Option<&'a Message> {
+        let (_, query) = queries.iter().find(|(addr, _)| addr == peer)?;
+
+        if response.len() < 2 || u16::from_be_bytes([response[0], response[1]]) != query.id() {
+            trace!("ignored response with mismatched ID from {peer}");
+            return None;
+        }
+
+        if !query.matches_question(response) {
+            trace!("ignored response with mismatched question from {peer}");
+            return None;
+        }
+
+        Some(query)
+    }
+}
+
+/// Name servers a zone is delegated to, each with the glue addresses found for it.
+#[derive(Debug, PartialEq)]
+struct Delegation {
+    /// The owner of the first NS record, or the root if there is none.
+    zone: String,
+    name_servers: Vec<(String, Vec<IpAddr>)>,
+}
+
+/// What's left to resolve a domain, shared with the resolutions of name server domains along the way.
+struct Budget {
+    /// How many queries have been sent so far.
+    queries: AtomicUsize,
+    /// When to give up, see [`ResolverBuilder::resolution_timeout`].
+    deadline: Option<Instant>,
+    /// How the last response was received.
+    stats: Mutex<QueryStats>,
+    /// Whether to keep the bytes of the last response, see [`Resolver::query_raw`].
+    keep_raw: bool,
+    /// Bytes of the last response, if they are kept.
+    raw: Mutex<Option<Vec<u8>>>,
+}
+
+impl Budget {
+    /// Keep the bytes of a response, which are only copied if they are asked for.
+    fn keep(&self, response: &[u8]) {
+        if self.keep_raw {
+            *self.raw.lock().unwrap() = Some(response.to_vec());
+        }
+    }
+}
+
+/// How the answer of [`Resolver::query_with_stats`] was received. Async queries don't report it.
+///
+/// Nothing is sent for answers from the cache or the blocklist,
+/// so they are reported with the [`Protocol`] of the resolver and without truncation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QueryStats {
+    /// Whether the UDP response was truncated, so the name server was asked again over TCP.
+    pub truncated: bool,
+    /// The protocol the answer was received with in the end.
+    pub transport: Protocol,
+}
+
+/// Where to continue the resolution when a name server doesn't have the answer.
+enum Referral {
+    /// Ask one of these name servers.
+    NameServers(Vec<IpAddr>),
+    /// The address of this name server has to be resolved first.
+    NameServerDomain(String),
 }
 
 /// Currently supported DNS query protocols.
 #[non_exhaustive]
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Protocol {
     DOH,
     DOT,
@@ -128,6 +1929,44 @@ pub enum Protocol {
     UDP,
 }
 
+/// Which addresses are used to reach name servers, i.e., IPv4, IPv6, or both on a dual-stack host.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AddressFamily {
+    V4,
+    V6,
+    Dual,
+}
+
+impl AddressFamily {
+    fn root_name_servers(&self) -> Vec<IpAddr> {
+        let v4 = ROOT_NAME_SERVERS_V4.iter().map(|ip| IpAddr::from(*ip));
+        let v6 = ROOT_NAME_SERVERS_V6.iter().map(|ip| IpAddr::from(*ip));
+
+        match self {
+            AddressFamily::V4 => v4.collect(),
+            AddressFamily::V6 => v6.collect(),
+            AddressFamily::Dual => v4.chain(v6).collect(),
+        }
+    }
+
+    fn allows(&self, ip: &IpAddr) -> bool {
+        match self {
+            AddressFamily::V4 => ip.is_ipv4(),
+            AddressFamily::V6 => ip.is_ipv6(),
+            AddressFamily::Dual => true,
+        }
+    }
+
+    /// Types of the records holding addresses of this family.
+    fn record_types(&self) -> &'static [RecordType] {
+        match self {
+            AddressFamily::V4 => &[RecordType::A],
+            AddressFamily::V6 => &[RecordType::AAAA],
+            AddressFamily::Dual => &[RecordType::A, RecordType::AAAA],
+        }
+    }
+}
+
 const ROOT_NAME_SERVERS_V4: [Ipv4Addr; 13] = [
     Ipv4Addr::new(198, 41, 0, 4),     // a.root-servers.net
     Ipv4Addr::new(170, 247, 170, 2),  // b.root-servers.net
@@ -144,15 +1983,1783 @@ const ROOT_NAME_SERVERS_V4: [Ipv4Addr; 13] = [
     Ipv4Addr::new(202, 12, 27, 33),   // m.root-servers.net
 ];
 
-/// Query domain with given domain, protocol, and type.
+const ROOT_NAME_SERVERS_V6: [Ipv6Addr; 13] = [
+    Ipv6Addr::new(0x2001, 0x503, 0xba3e, 0, 0, 0, 0x2, 0x30), // a.root-servers.net
+    Ipv6Addr::new(0x2801, 0x1b8, 0x10, 0, 0, 0, 0, 0xb),      // b.root-servers.net
+    Ipv6Addr::new(0x2001, 0x500, 0x2, 0, 0, 0, 0, 0xc),       // c.root-servers.net
+    Ipv6Addr::new(0x2001, 0x500, 0x2d, 0, 0, 0, 0, 0xd),      // d.root-servers.net
+    Ipv6Addr::new(0x2001, 0x500, 0xa8, 0, 0, 0, 0, 0xe),      // e.root-servers.net
+    Ipv6Addr::new(0x2001, 0x500, 0x2f, 0, 0, 0, 0, 0xf),      // f.root-servers.net
+    Ipv6Addr::new(0x2001, 0x500, 0x12, 0, 0, 0, 0, 0xd0d),    // g.root-servers.net
+    Ipv6Addr::new(0x2001, 0x500, 0x1, 0, 0, 0, 0, 0x53),      // h.root-servers.net
+    Ipv6Addr::new(0x2001, 0x7fe, 0, 0, 0, 0, 0, 0x53),        // i.root-servers.net
+    Ipv6Addr::new(0x2001, 0x503, 0xc27, 0, 0, 0, 0x2, 0x30),  // j.root-servers.net
+    Ipv6Addr::new(0x2001, 0x7fd, 0, 0, 0, 0, 0, 0x1),         // k.root-servers.net
+    Ipv6Addr::new(0x2001, 0x500, 0x9f, 0, 0, 0, 0, 0x42),     // l.root-servers.net
+    Ipv6Addr::new(0x2001, 0xdc3, 0, 0, 0, 0, 0, 0x35),        // m.root-servers.net
+];
+
+/// Query domain with given domain and type, using a [`Resolver`] with default options.
 ///
-/// ```
+/// ```no_run
 /// use tiny_resolver_rs::{query, Protocol, RecordType};
 /// let record_type = RecordType::A;
 /// let res = query("google.com", &record_type).unwrap();
 /// ```
 pub fn query(domain: &str, record_type: &RecordType) -> Result<Message, Error> {
-    utils::validate_domain(domain)?;
+    Resolver::default().query(domain, record_type)
+}
+
+/// Look up host names of an IP address, using a [`Resolver`] with default options.
+///
+/// ```no_run
+/// use tiny_resolver_rs::reverse_lookup;
+/// let hosts = reverse_lookup("8.8.8.8".parse().unwrap()).unwrap();
+/// ```
+pub fn reverse_lookup(ip: IpAddr) -> Result<Vec<String>, Error> {
+    Resolver::default().reverse_lookup(ip)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use std::net::Ipv6Addr;
+    use std::sync::atomic::AtomicBool;
+
+    #[test]
+    fn test_max_attempts_with_non_answering_server() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let port = server.local_addr().unwrap().port();
+
+        let resolver = Resolver::builder()
+            .max_attempts(1)
+            .timeout(Duration::from_millis(100))
+            .roots(&[Ipv4Addr::LOCALHOST])
+            .port(port)
+            .build();
+
+        assert!(resolver.query("blog.wtcx.dev", &RecordType::A).is_err());
+
+        // The resolver has given up, so every query it sent is already waiting on the server socket.
+        server.set_nonblocking(true).unwrap();
+        let mut buf = [0; 512];
+        let mut received = 0;
+        while server.recv(&mut buf).is_ok() {
+            received += 1;
+        }
+
+        assert_eq!(received, 1);
+    }
+
+    #[test]
+    fn test_last_message_kept_when_attempts_run_out() {
+        let (port, mut sockets) = testing::bind_servers(1);
+        // The name server keeps referring to itself, as the name server of wtcx.dev and then blog.wtcx.dev.
+        let zones = AtomicUsize::new(0);
+        testing::serve(sockets.pop().unwrap(), move |query| {
+            let zone = ["wtcx.dev", "blog.wtcx.dev"][zones.fetch_add(1, Ordering::SeqCst) % 2];
+            let authority = testing::ns_record(zone, &format!("ns.{zone}"));
+            let glue = testing::a_record(&format!("ns.{zone}"), Ipv4Addr::LOCALHOST);
+            Some(testing::response(query, 0, &[], &[authority], &[glue]))
+        });
+
+        let resolver = Resolver::builder()
+            .max_attempts(2)
+            .timeout(Duration::from_millis(100))
+            .roots(&[Ipv4Addr::LOCALHOST])
+            .port(port)
+            .build();
+
+        match resolver.query("blog.wtcx.dev", &RecordType::A) {
+            Err(Error::ResolutionFailed { domain, last }) => {
+                assert_eq!(domain, "blog.wtcx.dev");
+                let last = last.unwrap();
+                assert_eq!(
+                    last.authorities[0].r_data,
+                    RecordData::NS("ns.blog.wtcx.dev".into())
+                );
+                assert_eq!(last.additionals[0].ipv4_ip(), Some(Ipv4Addr::LOCALHOST));
+            }
+            res => panic!("expect the resolution to fail, got {res:?}"),
+        }
+    }
+
+    #[test]
+    fn test_retransmit_after_lost_query() {
+        let (port, mut sockets) = testing::bind_servers(1);
+        let dropped = AtomicBool::new(false);
+        // The first query is lost on the way.
+        let received = testing::serve(sockets.pop().unwrap(), move |query| {
+            if !dropped.swap(true, Ordering::SeqCst) {
+                return None;
+            }
+            let answer = testing::a_record("blog.wtcx.dev", Ipv4Addr::new(1, 2, 3, 4));
+            Some(testing::response(query, 0, &[answer], &[], &[]))
+        });
 
-    Resolver::resolve(domain, record_type)
+        let resolver = Resolver::builder()
+            .max_attempts(1)
+            .timeout(Duration::from_secs(1))
+            .retransmits(2)
+            .retransmit_interval(Duration::from_millis(50))
+            .roots(&[Ipv4Addr::LOCALHOST])
+            .port(port)
+            .build();
+
+        let message = resolver.query("blog.wtcx.dev", &RecordType::A).unwrap();
+
+        assert_eq!(message.a_records(), [Ipv4Addr::new(1, 2, 3, 4)]);
+        assert_eq!(received.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_untried_name_server_is_preferred() {
+        let (port, mut sockets) = testing::bind_servers(2);
+        let answering = testing::serve(sockets.pop().unwrap(), |query| {
+            let answer = testing::a_record("blog.wtcx.dev", Ipv4Addr::new(1, 2, 3, 4));
+            Some(testing::response(query, 0, &[answer], &[], &[]))
+        });
+        let silent = testing::serve(sockets.pop().unwrap(), |_| None);
+
+        // Whichever is picked first, the other one has to be the second pick.
+        for _ in 0..5 {
+            // A new resolver each time, so the answer isn't taken from the cache.
+            let resolver = Resolver::builder()
+                .max_attempts(2)
+                .timeout(Duration::from_millis(50))
+                .roots(&[Ipv4Addr::new(127, 0, 0, 1), Ipv4Addr::new(127, 0, 0, 2)])
+                .port(port)
+                .build();
+            let message = resolver.query("blog.wtcx.dev", &RecordType::A).unwrap();
+            assert_eq!(
+                message.answers[0].ipv4_ip(),
+                Some(Ipv4Addr::new(1, 2, 3, 4))
+            );
+        }
+
+        assert_eq!(answering.load(Ordering::SeqCst), 5);
+        assert!(silent.load(Ordering::SeqCst) <= 5);
+    }
+
+    #[test]
+    fn test_unreachable_root_tries_another() {
+        let (port, mut sockets) = testing::bind_servers(1);
+        let answering = testing::serve(sockets.pop().unwrap(), |query| {
+            let answer = testing::a_record("blog.wtcx.dev", Ipv4Addr::new(1, 2, 3, 4));
+            Some(testing::response(query, 0, &[answer], &[], &[]))
+        });
+        // Sending to the broadcast address fails right away without SO_BROADCAST.
+        let roots = [Ipv4Addr::BROADCAST, Ipv4Addr::new(127, 0, 0, 1)];
+
+        // Whichever is picked first, the answer has to come from the reachable one.
+        for _ in 0..5 {
+            let resolver = Resolver::builder()
+                .max_attempts(2)
+                .fan_out(1)
+                .roots(&roots)
+                .port(port)
+                .build();
+            let message = resolver.query("blog.wtcx.dev", &RecordType::A).unwrap();
+            assert_eq!(message.a_records(), [Ipv4Addr::new(1, 2, 3, 4)]);
+        }
+
+        // Asked at the same time, the unreachable one doesn't fail the attempt.
+        let resolver = Resolver::builder()
+            .max_attempts(1)
+            .roots(&roots)
+            .port(port)
+            .build();
+        let message = resolver.query("blog.wtcx.dev", &RecordType::A).unwrap();
+        assert_eq!(message.a_records(), [Ipv4Addr::new(1, 2, 3, 4)]);
+
+        assert_eq!(answering.load(Ordering::SeqCst), 6);
+    }
+
+    #[test]
+    fn test_fan_out() {
+        let (port, mut sockets) = testing::bind_servers(2);
+        let answering = testing::serve(sockets.pop().unwrap(), |query| {
+            // Give the spoofed response a head start.
+            std::thread::sleep(Duration::from_millis(50));
+            let answer = testing::a_record("blog.wtcx.dev", Ipv4Addr::new(1, 2, 3, 4));
+            Some(testing::response(query, 0, &[answer], &[], &[]))
+        });
+        // Responds immediately, but with an ID that belongs to no query.
+        let spoofing = testing::serve(sockets.pop().unwrap(), |query| {
+            let answer = testing::a_record("blog.wtcx.dev", Ipv4Addr::new(6, 6, 6, 6));
+            let mut response = testing::response(query, 0, &[answer], &[], &[]);
+            response[0] = !response[0];
+            Some(response)
+        });
+
+        let resolver = Resolver::builder()
+            .max_attempts(1)
+            .fan_out(2)
+            .timeout(Duration::from_millis(200))
+            .roots(&[Ipv4Addr::new(127, 0, 0, 1), Ipv4Addr::new(127, 0, 0, 2)])
+            .port(port)
+            .build();
+
+        let message = resolver.query("blog.wtcx.dev", &RecordType::A).unwrap();
+
+        assert_eq!(
+            message.answers[0].ipv4_ip(),
+            Some(Ipv4Addr::new(1, 2, 3, 4))
+        );
+        assert_eq!(answering.load(Ordering::SeqCst), 1);
+        assert_eq!(spoofing.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_response_from_other_address_is_ignored() {
+        let (port, mut sockets) = testing::bind_servers(2);
+        let off_path = sockets.pop().unwrap();
+        let server = sockets.pop().unwrap();
+
+        std::thread::spawn(move || {
+            let mut buf = [0; 512];
+            let (len, peer) = server.recv_from(&mut buf).unwrap();
+            let query = &buf[..len];
+
+            // Same ID and question, but it comes from an address the query wasn't sent to.
+            let answer = testing::a_record("blog.wtcx.dev", Ipv4Addr::new(6, 6, 6, 6));
+            let spoofed = testing::response(query, 0, &[answer], &[], &[]);
+            off_path.send_to(&spoofed, peer).unwrap();
+
+            std::thread::sleep(Duration::from_millis(50));
+            let answer = testing::a_record("blog.wtcx.dev", Ipv4Addr::new(1, 2, 3, 4));
+            let response = testing::response(query, 0, &[answer], &[], &[]);
+            server.send_to(&response, peer).unwrap();
+        });
+
+        let resolver = Resolver::builder()
+            .max_attempts(1)
+            .fan_out(1)
+            .timeout(Duration::from_millis(500))
+            .roots(&[Ipv4Addr::new(127, 0, 0, 1)])
+            .port(port)
+            .build();
+
+        let message = resolver.query("blog.wtcx.dev", &RecordType::A).unwrap();
+
+        assert_eq!(
+            message.answers[0].ipv4_ip(),
+            Some(Ipv4Addr::new(1, 2, 3, 4))
+        );
+    }
+
+    #[test]
+    fn test_server_failure_tries_other_name_server() {
+        let (port, mut sockets) = testing::bind_servers(2);
+        let answering = testing::serve(sockets.pop().unwrap(), |query| {
+            let answer = testing::a_record("blog.wtcx.dev", Ipv4Addr::new(1, 2, 3, 4));
+            Some(testing::response(query, 0, &[answer], &[], &[]))
+        });
+        testing::serve(sockets.pop().unwrap(), |query| {
+            Some(testing::response(query, 2, &[], &[], &[]))
+        });
+
+        // Whichever is picked first, the answer has to come from the second one.
+        for _ in 0..5 {
+            let resolver = Resolver::builder()
+                .max_attempts(2)
+                .fan_out(1)
+                .timeout(Duration::from_millis(100))
+                .roots(&[Ipv4Addr::new(127, 0, 0, 1), Ipv4Addr::new(127, 0, 0, 2)])
+                .port(port)
+                .build();
+            let message = resolver.query("blog.wtcx.dev", &RecordType::A).unwrap();
+            assert_eq!(message.a_records(), [Ipv4Addr::new(1, 2, 3, 4)]);
+        }
+
+        assert_eq!(answering.load(Ordering::SeqCst), 5);
+    }
+
+    #[test]
+    fn test_nxdomain_is_final() {
+        let (port, mut sockets) = testing::bind_servers(2);
+        let first = testing::serve(sockets.pop().unwrap(), |query| {
+            Some(testing::response(query, 3, &[], &[], &[]))
+        });
+        let second = testing::serve(sockets.pop().unwrap(), |query| {
+            Some(testing::response(query, 3, &[], &[], &[]))
+        });
+        let resolver = Resolver::builder()
+            .max_attempts(2)
+            .fan_out(1)
+            .roots(&[Ipv4Addr::new(127, 0, 0, 1), Ipv4Addr::new(127, 0, 0, 2)])
+            .port(port)
+            .build();
+
+        assert!(matches!(
+            resolver.query("missing.wtcx.dev", &RecordType::A),
+            Err(Error::NxDomain { .. })
+        ));
+        assert_eq!(
+            first.load(Ordering::SeqCst) + second.load(Ordering::SeqCst),
+            1
+        );
+    }
+
+    #[derive(Default)]
+    struct RecordingObserver {
+        events: Mutex<Vec<String>>,
+    }
+
+    impl ResolverObserver for RecordingObserver {
+        fn on_query_sent(&self, server: &SocketAddr, record_type: &RecordType) {
+            let event = format!("query {record_type} to {}", server.ip());
+            self.events.lock().unwrap().push(event);
+        }
+
+        fn on_response(
+            &self,
+            server: &SocketAddr,
+            _record_type: &RecordType,
+            r_code: u16,
+            latency: Duration,
+        ) {
+            assert!(latency < Duration::from_secs(1));
+            let event = format!("rcode {r_code} from {}", server.ip());
+            self.events.lock().unwrap().push(event);
+        }
+
+        fn on_timeout(&self, servers: &[IpAddr], _record_type: &RecordType) {
+            let event = format!("timeout {servers:?}");
+            self.events.lock().unwrap().push(event);
+        }
+
+        fn on_retry(&self, servers: &[IpAddr], _record_type: &RecordType) {
+            let event = format!("retry {servers:?}");
+            self.events.lock().unwrap().push(event);
+        }
+
+        fn on_cache_hit(&self, domain: &str, _record_type: &RecordType) {
+            self.events.lock().unwrap().push(format!("hit {domain}"));
+        }
+
+        fn on_cache_miss(&self, domain: &str, _record_type: &RecordType) {
+            self.events.lock().unwrap().push(format!("miss {domain}"));
+        }
+    }
+
+    #[test]
+    fn test_observer() {
+        let (port, mut sockets) = testing::bind_servers(2);
+        let failed = AtomicBool::new(false);
+        // SERVFAIL for the first query, then the answer
+        testing::serve(sockets.remove(0), move |query| {
+            if !failed.swap(true, Ordering::SeqCst) {
+                return Some(testing::response(query, 2, &[], &[], &[]));
+            }
+            let answer = testing::a_record("blog.wtcx.dev", Ipv4Addr::new(1, 2, 3, 4));
+            Some(testing::response(query, 0, &[answer], &[], &[]))
+        });
+        let observer = Arc::new(RecordingObserver::default());
+        let resolver = Resolver::builder()
+            .fan_out(1)
+            .roots(&[Ipv4Addr::new(127, 0, 0, 1)])
+            .port(port)
+            .observer(observer.clone())
+            .build();
+
+        resolver.query("blog.wtcx.dev", &RecordType::A).unwrap();
+        resolver.query("blog.wtcx.dev", &RecordType::A).unwrap();
+
+        assert_eq!(
+            *observer.events.lock().unwrap(),
+            [
+                "miss blog.wtcx.dev",
+                "query A to 127.0.0.1",
+                "rcode 2 from 127.0.0.1",
+                "retry [127.0.0.1]",
+                "query A to 127.0.0.1",
+                "rcode 0 from 127.0.0.1",
+                "hit blog.wtcx.dev",
+            ]
+        );
+
+        // The other socket never responds.
+        let observer = Arc::new(RecordingObserver::default());
+        let resolver = Resolver::builder()
+            .max_attempts(1)
+            .timeout(Duration::from_millis(100))
+            .roots(&[Ipv4Addr::new(127, 0, 0, 2)])
+            .port(port)
+            .observer(observer.clone())
+            .build();
+
+        assert!(resolver.query("blog.wtcx.dev", &RecordType::A).is_err());
+        assert_eq!(
+            *observer.events.lock().unwrap(),
+            [
+                "miss blog.wtcx.dev",
+                "query A to 127.0.0.2",
+                "timeout [127.0.0.2]",
+                "retry [127.0.0.2]",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_resolve_first_address() {
+        let (port, mut sockets) = testing::bind_servers(1);
+        testing::serve(sockets.pop().unwrap(), |query| {
+            let answers = match testing::question_type(query) {
+                RecordType::AAAA => vec![
+                    testing::aaaa_record("blog.wtcx.dev", "2001:db8::1".parse().unwrap()),
+                    testing::aaaa_record("blog.wtcx.dev", "2001:db8::2".parse().unwrap()),
+                ],
+                _ => vec![
+                    testing::a_record("blog.wtcx.dev", Ipv4Addr::new(1, 2, 3, 4)),
+                    testing::a_record("blog.wtcx.dev", Ipv4Addr::new(1, 2, 3, 5)),
+                ],
+            };
+            Some(testing::response(query, 0, &answers, &[], &[]))
+        });
+        let resolver = Resolver::builder()
+            .roots(&[Ipv4Addr::LOCALHOST])
+            .port(port)
+            .build();
+
+        assert_eq!(
+            resolver.resolve_ipv4("blog.wtcx.dev").unwrap(),
+            Ipv4Addr::new(1, 2, 3, 4)
+        );
+        assert_eq!(
+            resolver.resolve_ipv6("blog.wtcx.dev").unwrap(),
+            "2001:db8::1".parse::<Ipv6Addr>().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_raw_response() {
+        let (port, mut sockets) = testing::bind_servers(1);
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let sent = received.clone();
+        testing::serve(sockets.pop().unwrap(), move |query| {
+            let answer = testing::a_record("blog.wtcx.dev", Ipv4Addr::new(1, 2, 3, 4));
+            let response = testing::response(query, 0, &[answer], &[], &[]);
+            *sent.lock().unwrap() = response.clone();
+            Some(response)
+        });
+        let resolver = Resolver::builder()
+            .roots(&[Ipv4Addr::LOCALHOST])
+            .port(port)
+            .build();
+
+        let (_, raw) = resolver.query_raw("blog.wtcx.dev", &RecordType::A).unwrap();
+
+        assert_eq!(raw.as_deref(), Some(received.lock().unwrap().as_slice()));
+    }
+
+    #[test]
+    fn test_resolve_all_ipv4() {
+        let (port, mut sockets) = testing::bind_servers(1);
+        testing::serve(sockets.pop().unwrap(), |query| {
+            let answers: Vec<_> = (1..=3)
+                .map(|i| testing::a_record("blog.wtcx.dev", Ipv4Addr::new(1, 2, 3, i)))
+                .collect();
+            Some(testing::response(query, 0, &answers, &[], &[]))
+        });
+        let resolver = Resolver::builder()
+            .roots(&[Ipv4Addr::LOCALHOST])
+            .port(port)
+            .build();
+        let expected = [
+            Ipv4Addr::new(1, 2, 3, 1),
+            Ipv4Addr::new(1, 2, 3, 2),
+            Ipv4Addr::new(1, 2, 3, 3),
+        ];
+
+        assert_eq!(
+            resolver.resolve_all_ipv4("blog.wtcx.dev", false).unwrap(),
+            expected
+        );
+
+        let mut shuffled = resolver.resolve_all_ipv4("blog.wtcx.dev", true).unwrap();
+        shuffled.sort();
+        assert_eq!(shuffled, expected);
+    }
+
+    #[test]
+    fn test_resolve_ipv4_without_a_record() {
+        let (port, mut sockets) = testing::bind_servers(1);
+        testing::serve(sockets.pop().unwrap(), |query| {
+            let target = testing::encode("wtcx.github.io");
+            let cname = testing::record("blog.wtcx.dev", RecordType::CNAME, 300, &target);
+            Some(testing::response(query, 0, &[cname], &[], &[]))
+        });
+        let resolver = Resolver::builder()
+            .roots(&[Ipv4Addr::LOCALHOST])
+            .port(port)
+            .build();
+
+        assert_eq!(
+            resolver.resolve_ipv4("blog.wtcx.dev"),
+            Err(Error::ResolverError(
+                "no A record found for blog.wtcx.dev".into()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_resolve_addrs() {
+        let (port, mut sockets) = testing::bind_servers(1);
+        testing::serve(sockets.pop().unwrap(), |query| {
+            let answers = match testing::question_type(query) {
+                RecordType::AAAA => vec![
+                    testing::aaaa_record("blog.wtcx.dev", "2001:db8::1".parse().unwrap()),
+                    testing::aaaa_record("blog.wtcx.dev", "2001:db8::2".parse().unwrap()),
+                ],
+                _ => vec![
+                    testing::a_record("blog.wtcx.dev", Ipv4Addr::new(1, 2, 3, 4)),
+                    testing::a_record("blog.wtcx.dev", Ipv4Addr::new(1, 2, 3, 5)),
+                    testing::a_record("blog.wtcx.dev", Ipv4Addr::new(1, 2, 3, 6)),
+                ],
+            };
+            Some(testing::response(query, 0, &answers, &[], &[]))
+        });
+        let resolver = Resolver::builder()
+            .roots(&[Ipv4Addr::LOCALHOST])
+            .port(port)
+            .resolution_delay(Duration::from_secs(1))
+            .build();
+
+        let ips = resolver.resolve_addrs("blog.wtcx.dev").unwrap();
+
+        let expected: Vec<IpAddr> = vec![
+            "2001:db8::1".parse().unwrap(),
+            "1.2.3.4".parse().unwrap(),
+            "2001:db8::2".parse().unwrap(),
+            "1.2.3.5".parse().unwrap(),
+            "1.2.3.6".parse().unwrap(),
+        ];
+        assert_eq!(ips, expected);
+    }
+
+    #[test]
+    fn test_resolve_addrs_with_source_port() {
+        let (port, mut sockets) = testing::bind_servers(1);
+        testing::serve(sockets.pop().unwrap(), |query| {
+            let answer = match testing::question_type(query) {
+                RecordType::AAAA => testing::aaaa_record("blog.wtcx.dev", Ipv6Addr::LOCALHOST),
+                _ => testing::a_record("blog.wtcx.dev", Ipv4Addr::new(1, 2, 3, 4)),
+            };
+            Some(testing::response(query, 0, &[answer], &[], &[]))
+        });
+        let source_port = UdpSocket::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let resolver = Resolver::builder()
+            .roots(&[Ipv4Addr::LOCALHOST])
+            .port(port)
+            .bind_addr(Ipv4Addr::LOCALHOST.into())
+            .source_port(source_port)
+            .build();
+
+        let ips = resolver.resolve_addrs("blog.wtcx.dev").unwrap();
+
+        let expected: [IpAddr; 2] = [Ipv6Addr::LOCALHOST.into(), Ipv4Addr::new(1, 2, 3, 4).into()];
+        assert_eq!(ips, expected);
+    }
+
+    #[test]
+    fn test_resolve_addrs_without_waiting_for_aaaa() {
+        let (port, mut sockets) = testing::bind_servers(1);
+        // AAAA is never answered.
+        testing::serve(sockets.pop().unwrap(), |query| {
+            if testing::question_type(query) == RecordType::AAAA {
+                return None;
+            }
+            let answer = testing::a_record("blog.wtcx.dev", Ipv4Addr::new(1, 2, 3, 4));
+            Some(testing::response(query, 0, &[answer], &[], &[]))
+        });
+        let resolver = Resolver::builder()
+            .roots(&[Ipv4Addr::LOCALHOST])
+            .port(port)
+            .build();
+
+        let started = Instant::now();
+        let ips = resolver.resolve_addrs("blog.wtcx.dev").unwrap();
+
+        assert_eq!(ips, [IpAddr::from(Ipv4Addr::new(1, 2, 3, 4))]);
+        assert!(started.elapsed() < DEFAULT_TIMEOUT);
+    }
+
+    #[test]
+    fn test_blocklist() {
+        let (port, mut sockets) = testing::bind_servers(1);
+        let received = testing::serve(sockets.pop().unwrap(), |query| {
+            let answer = testing::a_record("blog.wtcx.dev", Ipv4Addr::new(1, 2, 3, 4));
+            Some(testing::response(query, 0, &[answer], &[], &[]))
+        });
+        let builder = || {
+            Resolver::builder()
+                .timeout(Duration::from_millis(100))
+                .roots(&[Ipv4Addr::LOCALHOST])
+                .port(port)
+        };
+        let is_blocked = |domain: &str, _: &RecordType| domain.ends_with("ads.wtcx.dev");
+
+        let resolver = builder()
+            .blocklist(is_blocked, BlockAction::Sinkhole)
+            .build();
+        let message = resolver.query("ADS.wtcx.dev", &RecordType::A).unwrap();
+        assert_eq!(message.a_records(), [Ipv4Addr::UNSPECIFIED]);
+        let message = resolver.query("ads.wtcx.dev", &RecordType::AAAA).unwrap();
+        assert_eq!(message.aaaa_records(), [Ipv6Addr::UNSPECIFIED]);
+        let message = resolver.query("ads.wtcx.dev", &RecordType::TXT).unwrap();
+        assert!(message.answers.is_empty());
+
+        let resolver = builder()
+            .blocklist(is_blocked, BlockAction::NxDomain)
+            .build();
+        assert!(matches!(
+            resolver.query("ads.wtcx.dev", &RecordType::A),
+            Err(Error::NxDomain { .. })
+        ));
+        assert_eq!(received.load(Ordering::SeqCst), 0);
+
+        // Other names are still resolved.
+        let message = resolver.query("blog.wtcx.dev", &RecordType::A).unwrap();
+        assert_eq!(message.a_records(), [Ipv4Addr::new(1, 2, 3, 4)]);
+        assert_eq!(received.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_query_budget() {
+        let (port, mut sockets) = testing::bind_servers(1);
+        // Every name server is under a deeper name server without glue, so the delegation never ends.
+        let received = testing::serve(sockets.pop().unwrap(), |query| {
+            let name = testing::question_name(query);
+            let authority = testing::ns_record(&name, &format!("ns.{name}"));
+            Some(testing::response(query, 0, &[], &[authority], &[]))
+        });
+        let resolver = Resolver::builder()
+            .max_queries(20)
+            .max_referral_depth(100)
+            .roots(&[Ipv4Addr::LOCALHOST])
+            .port(port)
+            .build();
+
+        assert!(matches!(
+            resolver.query("wtcx.dev", &RecordType::A),
+            Err(Error::ResolverError(reason)) if reason == "query budget exceeded"
+        ));
+        assert_eq!(received.load(Ordering::SeqCst), 20);
+    }
+
+    #[test]
+    fn test_delegation_with_partial_glue() {
+        let query = Message::new_query("blog.wtcx.dev", &RecordType::A);
+        let authorities = [
+            testing::ns_record("wtcx.dev", "ns1.wtcx.dev"),
+            testing::ns_record("wtcx.dev", "ns2.wtcx.dev"),
+            testing::ns_record("wtcx.dev", "ns.example.com"),
+        ];
+        let additionals = [
+            testing::a_record("ns1.wtcx.dev", Ipv4Addr::new(127, 0, 0, 2)),
+            // Out of the delegated zone, so it can't be trusted.
+            testing::a_record("ns.example.com", Ipv4Addr::new(127, 0, 0, 3)),
+        ];
+        let response = testing::response(
+            &query.to_query_bytes().unwrap(),
+            0,
+            &[],
+            &authorities,
+            &additionals,
+        );
+        let message = Message::parse(&response).unwrap();
+
+        let delegation = Resolver::collect_delegation(&message, "blog.wtcx.dev", "");
+
+        let glue = IpAddr::from(Ipv4Addr::new(127, 0, 0, 2));
+        assert_eq!(
+            delegation,
+            Delegation {
+                zone: "wtcx.dev".into(),
+                name_servers: vec![
+                    ("ns1.wtcx.dev".into(), vec![glue]),
+                    ("ns2.wtcx.dev".into(), vec![]),
+                    ("ns.example.com".into(), vec![]),
+                ],
+            }
+        );
+        assert!(matches!(
+            Resolver::default().referral(delegation),
+            Ok(Referral::NameServers(ips)) if ips == [glue]
+        ));
+    }
+
+    #[test]
+    fn test_delegation_out_of_zone() {
+        let query = Message::new_query("blog.wtcx.dev", &RecordType::A);
+        let authorities = [
+            // Not a zone blog.wtcx.dev is in.
+            testing::ns_record("example.com", "ns.example.com"),
+            // Not below dev, the zone being asked.
+            testing::ns_record("dev", "ns.evil.dev"),
+            testing::ns_record("wtcx.dev", "ns1.wtcx.dev"),
+        ];
+        let response =
+            testing::response(&query.to_query_bytes().unwrap(), 0, &[], &authorities, &[]);
+        let message = Message::parse(&response).unwrap();
+
+        let delegation = Resolver::collect_delegation(&message, "blog.wtcx.dev", "dev");
+
+        assert_eq!(
+            delegation,
+            Delegation {
+                zone: "wtcx.dev".into(),
+                name_servers: vec![("ns1.wtcx.dev".into(), vec![])],
+            }
+        );
+
+        // Nothing is left of a referral from wtcx.dev to itself.
+        let delegation = Resolver::collect_delegation(&message, "blog.wtcx.dev", "wtcx.dev");
+        assert!(matches!(
+            Resolver::default().referral(delegation),
+            Err(Error::ResolverError(reason)) if reason == "response has neither answers nor a referral"
+        ));
+    }
+
+    #[test]
+    fn test_authoritative_servers() {
+        let (port, mut sockets) = testing::bind_servers(2);
+        let name_servers = || {
+            [
+                testing::ns_record("wtcx.dev", "ns1.wtcx.dev"),
+                testing::ns_record("wtcx.dev", "ns2.wtcx.dev"),
+            ]
+        };
+        // The name servers of wtcx.dev answer with their own NS records.
+        testing::serve(sockets.pop().unwrap(), move |query| {
+            let glue = testing::a_record("ns1.wtcx.dev", Ipv4Addr::new(127, 0, 0, 2));
+            Some(testing::response(query, 0, &name_servers(), &[], &[glue]))
+        });
+        // The root refers wtcx.dev to them.
+        testing::serve(sockets.pop().unwrap(), move |query| {
+            let glue = testing::a_record("ns1.wtcx.dev", Ipv4Addr::new(127, 0, 0, 2));
+            Some(testing::response(query, 0, &[], &name_servers(), &[glue]))
+        });
+        let resolver = Resolver::builder()
+            .max_attempts(2)
+            .roots(&[Ipv4Addr::LOCALHOST])
+            .port(port)
+            .build();
+
+        let name_servers = resolver.authoritative_servers("wtcx.dev").unwrap();
+        let glue = IpAddr::from(Ipv4Addr::new(127, 0, 0, 2));
+
+        assert_eq!(
+            name_servers,
+            [
+                ("ns1.wtcx.dev".to_string(), vec![glue]),
+                ("ns2.wtcx.dev".to_string(), vec![]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_query_at() {
+        let (port, mut sockets) = testing::bind_servers(2);
+        // Only a recursive query is answered, otherwise it's referred to the name servers of wtcx.dev.
+        let server = testing::serve(sockets.pop().unwrap(), |query| {
+            let message = Message::parse(query).unwrap();
+            if message.header.flags().recursion_desired {
+                let answer = testing::a_record("blog.wtcx.dev", Ipv4Addr::new(1, 2, 3, 4));
+                return Some(testing::response(query, 0, &[answer], &[], &[]));
+            }
+            let referral = testing::ns_record("wtcx.dev", "ns.wtcx.dev");
+            Some(testing::response(query, 0, &[], &[referral], &[]))
+        });
+        let root = testing::serve(sockets.pop().unwrap(), |_| None);
+        let resolver = Resolver::builder()
+            .roots(&[Ipv4Addr::LOCALHOST])
+            .port(port)
+            .build();
+        let server_ip = Ipv4Addr::new(127, 0, 0, 2).into();
+
+        let message = resolver
+            .query_at(server_ip, "blog.wtcx.dev", &RecordType::A, true)
+            .unwrap();
+        assert_eq!(message.a_records(), [Ipv4Addr::new(1, 2, 3, 4)]);
+        assert_eq!(server.load(Ordering::SeqCst), 1);
+
+        let message = resolver
+            .query_at(server_ip, "blog.wtcx.dev", &RecordType::A, false)
+            .unwrap();
+        assert!(message.answers.is_empty());
+        assert_eq!(message.authorities.len(), 1);
+        assert_eq!(server.load(Ordering::SeqCst), 2);
+        assert_eq!(root.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn test_recursion_desired() {
+        let (port, mut sockets) = testing::bind_servers(1);
+        // A recursive resolver, which finds out blog.wtcx.dev has no AAAA record.
+        testing::serve(sockets.pop().unwrap(), |query| {
+            let message = Message::parse(query).unwrap();
+            assert!(message.header.flags().recursion_desired);
+            let soa = testing::soa_record("wtcx.dev", 300);
+            Some(testing::response(query, 0, &[], &[soa], &[]))
+        });
+        let resolver = Resolver::builder()
+            .max_attempts(1)
+            .roots(&[Ipv4Addr::LOCALHOST])
+            .port(port)
+            .recursion_desired(true)
+            .build();
+
+        let message = resolver.query("blog.wtcx.dev", &RecordType::AAAA).unwrap();
+
+        assert!(message.answers.is_empty());
+        assert_eq!(message.authorities[0].r_type, Some(RecordType::SOA));
+    }
+
+    #[test]
+    fn test_out_of_bailiwick_glue_ignored() {
+        let (port, mut sockets) = testing::bind_servers(3);
+        let poisoned = testing::serve(sockets.pop().unwrap(), |query| {
+            let answer = testing::a_record("blog.wtcx.dev", Ipv4Addr::new(6, 6, 6, 6));
+            Some(testing::response(query, 0, &[answer], &[], &[]))
+        });
+        testing::serve(sockets.pop().unwrap(), |query| {
+            let answer = testing::a_record("blog.wtcx.dev", Ipv4Addr::new(1, 2, 3, 4));
+            Some(testing::response(query, 0, &[answer], &[], &[]))
+        });
+        // The glue of ns.wtcx-dns.dev points to 127.0.0.3, but only ns.wtcx-dns.dev itself tells it's 127.0.0.2.
+        testing::serve(sockets.pop().unwrap(), |query| {
+            let name = testing::question_name(query);
+            if name == "ns.wtcx-dns.dev" {
+                let answer = testing::a_record(&name, Ipv4Addr::new(127, 0, 0, 2));
+                return Some(testing::response(query, 0, &[answer], &[], &[]));
+            }
+            let referral = testing::ns_record("wtcx.dev", "ns.wtcx-dns.dev");
+            let glue = testing::a_record("ns.wtcx-dns.dev", Ipv4Addr::new(127, 0, 0, 3));
+            Some(testing::response(query, 0, &[], &[referral], &[glue]))
+        });
+
+        let resolver = Resolver::builder()
+            .timeout(Duration::from_millis(100))
+            .roots(&[Ipv4Addr::LOCALHOST])
+            .port(port)
+            .build();
+
+        let message = resolver.query("blog.wtcx.dev", &RecordType::A).unwrap();
+
+        assert_eq!(message.a_records(), [Ipv4Addr::new(1, 2, 3, 4)]);
+        assert_eq!(poisoned.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn test_resolution_timeout() {
+        let (port, mut sockets) = testing::bind_servers(1);
+        // Every name server is under a deeper name server without glue, and each response is slow.
+        let received = testing::serve(sockets.pop().unwrap(), |query| {
+            thread::sleep(Duration::from_millis(50));
+            let name = testing::question_name(query);
+            let authority = testing::ns_record(&name, &format!("ns.{name}"));
+            Some(testing::response(query, 0, &[], &[authority], &[]))
+        });
+        let resolver = Resolver::builder()
+            .resolution_timeout(Duration::from_millis(120))
+            .max_referral_depth(100)
+            .roots(&[Ipv4Addr::LOCALHOST])
+            .port(port)
+            .build();
+
+        let started = Instant::now();
+        assert!(matches!(
+            resolver.query("wtcx.dev", &RecordType::A),
+            Err(Error::ResolverError(reason)) if reason == "overall deadline exceeded"
+        ));
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert!(received.load(Ordering::SeqCst) <= 3);
+    }
+
+    #[test]
+    fn test_dnssec_ok_and_checking_disabled() {
+        let (port, mut sockets) = testing::bind_servers(1);
+        let queries = Arc::new(Mutex::new(vec![]));
+        let received = queries.clone();
+        testing::serve(sockets.pop().unwrap(), move |query| {
+            let message = Message::parse(query).unwrap();
+            received.lock().unwrap().push(message);
+            let answer = testing::a_record("blog.wtcx.dev", Ipv4Addr::new(1, 2, 3, 4));
+            Some(testing::response(query, 0, &[answer], &[], &[]))
+        });
+        let builder = || {
+            Resolver::builder()
+                .max_attempts(1)
+                .roots(&[Ipv4Addr::LOCALHOST])
+                .port(port)
+        };
+
+        let resolvers = [
+            builder().build(),
+            builder().dnssec_ok(true).checking_disabled(true).build(),
+        ];
+        for resolver in resolvers {
+            resolver.query("blog.wtcx.dev", &RecordType::A).unwrap();
+        }
+
+        let queries = queries.lock().unwrap();
+        assert_eq!(queries[0].edns, None);
+        assert!(!queries[0].header.flags().checking_disabled);
+        let edns = queries[1].edns.as_ref().unwrap();
+        assert_eq!(edns.flags & Edns::DNSSEC_OK, Edns::DNSSEC_OK);
+        assert_eq!(edns.udp_payload_size, 1232);
+        assert!(queries[1].header.flags().checking_disabled);
+    }
+
+    #[test]
+    fn test_client_subnet() {
+        let (port, mut sockets) = testing::bind_servers(1);
+        let queries = Arc::new(Mutex::new(vec![]));
+        let received = queries.clone();
+        testing::serve(sockets.pop().unwrap(), move |query| {
+            received.lock().unwrap().push(query.to_vec());
+            let answer = testing::a_record("blog.wtcx.dev", Ipv4Addr::new(1, 2, 3, 4));
+            Some(testing::response(query, 0, &[answer], &[], &[]))
+        });
+        let client_subnet = ClientSubnet::new(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 0)), 24).unwrap();
+        let resolver = Resolver::builder()
+            .max_attempts(1)
+            .roots(&[Ipv4Addr::LOCALHOST])
+            .port(port)
+            .client_subnet(client_subnet)
+            .build();
+
+        resolver.query("blog.wtcx.dev", &RecordType::A).unwrap();
+
+        let queries = queries.lock().unwrap();
+        // OPTION-CODE, OPTION-LENGTH, FAMILY, SOURCE PREFIX-LENGTH, SCOPE PREFIX-LENGTH, and ADDRESS
+        assert!(queries[0].ends_with(&[0, 8, 0, 7, 0, 1, 24, 0, 192, 0, 2]));
+        let edns = Message::parse(&queries[0]).unwrap().edns.unwrap();
+        assert_eq!(edns.client_subnet(), Some(client_subnet));
+        assert_eq!(edns.udp_payload_size, 1232);
+    }
+
+    #[test]
+    fn test_strict_parsing() {
+        let (port, mut sockets) = testing::bind_servers(1);
+        testing::serve(sockets.pop().unwrap(), |query| {
+            let answer = testing::a_record("blog.wtcx.dev", Ipv4Addr::new(1, 2, 3, 4));
+            let mut response = testing::response(query, 0, &[answer], &[], &[]);
+            response.extend_from_slice(b"junk");
+            Some(response)
+        });
+        let builder = || {
+            Resolver::builder()
+                .max_attempts(1)
+                .roots(&[Ipv4Addr::LOCALHOST])
+                .port(port)
+        };
+
+        let resolver = builder().build();
+        let message = resolver.query("blog.wtcx.dev", &RecordType::A).unwrap();
+        assert_eq!(message.a_records(), [Ipv4Addr::new(1, 2, 3, 4)]);
+
+        let resolver = builder().strict_parsing(true).build();
+        assert!(matches!(
+            resolver.query("blog.wtcx.dev", &RecordType::A),
+            Err(Error::ResolverError(reason)) if reason == "4 bytes left after the message"
+        ));
+    }
+
+    #[test]
+    fn test_keep_unknown_records() {
+        let (port, mut sockets) = testing::bind_servers(1);
+        let received = testing::serve(sockets.pop().unwrap(), |query| {
+            let mut unknown = testing::record("blog.wtcx.dev", RecordType::A, 300, &[0xde, 0xad]);
+            // Replace TYPE, which follows the name, with a made-up type code.
+            let type_pos = testing::encode("blog.wtcx.dev").len();
+            unknown[type_pos..type_pos + 2].copy_from_slice(&9999u16.to_be_bytes());
+            let answers = [
+                testing::a_record("blog.wtcx.dev", Ipv4Addr::new(1, 2, 3, 4)),
+                unknown,
+            ];
+            Some(testing::response(query, 0, &answers, &[], &[]))
+        });
+        let builder = || {
+            Resolver::builder()
+                .max_attempts(1)
+                .roots(&[Ipv4Addr::LOCALHOST])
+                .port(port)
+        };
+
+        // The second answer is from the cache.
+        let keeping = builder().keep_unknown_records(true).build();
+        for _ in 0..2 {
+            let message = keeping.query("blog.wtcx.dev", &RecordType::A).unwrap();
+            assert_eq!(message.a_records(), [Ipv4Addr::new(1, 2, 3, 4)]);
+            assert_eq!(message.answers[1].type_code(), 9999);
+        }
+        assert_eq!(received.load(Ordering::SeqCst), 1);
+
+        // The answer kept in the shared cache isn't for a resolver failing on unknown types.
+        let resolver = builder().cache(keeping.cache.clone()).build();
+        assert_eq!(
+            resolver.query("blog.wtcx.dev", &RecordType::A).unwrap_err(),
+            Error::ResolverError(
+                "can't parse record 2 of 2 in the answer section: unknown record type 9999".into()
+            )
+        );
+        assert_eq!(received.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_validate_cnames() {
+        let (port, mut sockets) = testing::bind_servers(1);
+        testing::serve(sockets.pop().unwrap(), |query| {
+            let domain = testing::question_name(query);
+            let cname = |target: &str| {
+                testing::record(&domain, RecordType::CNAME, 300, &testing::encode(target))
+            };
+            let answers = match domain.as_str() {
+                // Another record along with the CNAME
+                "www.wtcx.dev" => vec![
+                    cname("blog.wtcx.dev"),
+                    testing::a_record(&domain, Ipv4Addr::new(1, 2, 3, 4)),
+                ],
+                // The CNAME points to a name that doesn't exist
+                "cdn.wtcx.dev" => vec![cname("blog.wtcx.dev")],
+                // The CNAME points to another zone, which isn't in the answer
+                "alias.wtcx.dev" => vec![cname("blog.example.com")],
+                "blog.example.com" => vec![testing::a_record(&domain, Ipv4Addr::new(1, 2, 3, 4))],
+                _ => return Some(testing::response(query, 3, &[], &[], &[])),
+            };
+            Some(testing::response(query, 0, &answers, &[], &[]))
+        });
+        let builder = || {
+            Resolver::builder()
+                .max_attempts(1)
+                .roots(&[Ipv4Addr::LOCALHOST])
+                .port(port)
+        };
+
+        let resolver = builder().build();
+        assert!(resolver.query("www.wtcx.dev", &RecordType::A).is_ok());
+        assert!(resolver.query("cdn.wtcx.dev", &RecordType::A).is_ok());
+
+        let resolver = builder().validate_cnames(true).build();
+        assert_eq!(
+            resolver.query("www.wtcx.dev", &RecordType::A).unwrap_err(),
+            Error::InvalidResponse("www.wtcx.dev has a CNAME and other data".into())
+        );
+        assert_eq!(
+            resolver.query("cdn.wtcx.dev", &RecordType::A).unwrap_err(),
+            Error::InvalidResponse(
+                "CNAME of cdn.wtcx.dev points to blog.wtcx.dev, which has no records".into()
+            )
+        );
+        assert!(resolver.query("alias.wtcx.dev", &RecordType::A).is_ok());
+    }
+
+    #[test]
+    fn test_randomize_case() {
+        let (port, mut sockets) = testing::bind_servers(1);
+        let flip = Arc::new(AtomicBool::new(false));
+        let flip_case = flip.clone();
+        testing::serve(sockets.pop().unwrap(), move |query| {
+            let mut query = query.to_vec();
+            if flip_case.load(Ordering::SeqCst) {
+                // Flip the case of every letter in QNAME, so the casing never matches.
+                let qname_end = 12 + query[12..].iter().position(|&b| b == 0).unwrap();
+                for b in query[12..qname_end].iter_mut() {
+                    if b.is_ascii_alphabetic() {
+                        *b ^= 0x20;
+                    }
+                }
+            }
+            let answer = testing::a_record("blog.wtcx.dev", Ipv4Addr::new(1, 2, 3, 4));
+            Some(testing::response(&query, 0, &[answer], &[], &[]))
+        });
+        let resolver = Resolver::builder()
+            .max_attempts(1)
+            .timeout(Duration::from_millis(100))
+            .roots(&[Ipv4Addr::LOCALHOST])
+            .port(port)
+            .randomize_case(true)
+            .build();
+
+        let message = resolver.query("blog.wtcx.dev", &RecordType::A).unwrap();
+        assert_eq!(message.questions[0].domain(), "blog.wtcx.dev");
+        // From the cache
+        assert!(resolver.query("blog.wtcx.dev", &RecordType::A).is_ok());
+
+        flip.store(true, Ordering::SeqCst);
+        assert!(resolver.query("www.wtcx.dev", &RecordType::A).is_err());
+    }
+
+    #[test]
+    fn test_randomize_case_ignores_other_casing() {
+        let (port, mut sockets) = testing::bind_servers(1);
+        let server = sockets.pop().unwrap();
+
+        std::thread::spawn(move || {
+            let mut buf = [0; 512];
+            let (len, peer) = server.recv_from(&mut buf).unwrap();
+            let query = &buf[..len];
+
+            // Same ID, but the casing of every letter in QNAME is flipped.
+            let mut spoofed_query = query.to_vec();
+            let qname_end = 12 + spoofed_query[12..].iter().position(|&b| b == 0).unwrap();
+            for b in spoofed_query[12..qname_end].iter_mut() {
+                if b.is_ascii_alphabetic() {
+                    *b ^= 0x20;
+                }
+            }
+            let answer = testing::a_record("blog.wtcx.dev", Ipv4Addr::new(6, 6, 6, 6));
+            let spoofed = testing::response(&spoofed_query, 0, &[answer], &[], &[]);
+            server.send_to(&spoofed, peer).unwrap();
+
+            std::thread::sleep(Duration::from_millis(50));
+            let answer = testing::a_record("blog.wtcx.dev", Ipv4Addr::new(1, 2, 3, 4));
+            let response = testing::response(query, 0, &[answer], &[], &[]);
+            server.send_to(&response, peer).unwrap();
+        });
+
+        let resolver = Resolver::builder()
+            .max_attempts(1)
+            .timeout(Duration::from_millis(500))
+            .roots(&[Ipv4Addr::LOCALHOST])
+            .port(port)
+            .randomize_case(true)
+            .build();
+
+        let message = resolver.query("blog.wtcx.dev", &RecordType::A).unwrap();
+
+        assert_eq!(message.a_records(), [Ipv4Addr::new(1, 2, 3, 4)]);
+        assert_eq!(message.questions[0].domain(), "blog.wtcx.dev");
+    }
+
+    #[test]
+    fn test_record_past_received_length() {
+        let (port, mut sockets) = testing::bind_servers(1);
+        testing::serve(sockets.pop().unwrap(), |query| {
+            let answer = testing::a_record("blog.wtcx.dev", Ipv4Addr::new(1, 2, 3, 4));
+            let mut response = testing::response(query, 0, &[answer], &[], &[]);
+            // RDLENGTH still claims 4 bytes of the address, but only 2 of them are sent.
+            response.truncate(response.len() - 2);
+            Some(response)
+        });
+        let resolver = Resolver::builder()
+            .max_attempts(1)
+            .roots(&[Ipv4Addr::LOCALHOST])
+            .port(port)
+            .build();
+
+        // The rest of the receive buffer is zeros, which must not be taken as the address.
+        assert!(matches!(
+            resolver.query("blog.wtcx.dev", &RecordType::A),
+            Err(Error::ResolverError(reason))
+                if reason.starts_with("can't parse record 1 of 1 in the answer section")
+        ));
+    }
+
+    #[test]
+    fn test_require_glue() {
+        let (port, mut sockets) = testing::bind_servers(1);
+        testing::serve(sockets.pop().unwrap(), |query| {
+            let authority = testing::ns_record("wtcx.dev", "ns.example.com");
+            Some(testing::response(query, 0, &[], &[authority], &[]))
+        });
+        let resolver = Resolver::builder()
+            .require_glue(true)
+            .roots(&[Ipv4Addr::LOCALHOST])
+            .port(port)
+            .build();
+
+        assert!(matches!(
+            resolver.query("blog.wtcx.dev", &RecordType::A),
+            Err(Error::ResolverError(reason)) if reason == "referral to ns.example.com comes without glue"
+        ));
+    }
+
+    #[test]
+    fn test_tcp() {
+        let (port, received) = testing::serve_tcp(|query| {
+            let answer = testing::a_record("blog.wtcx.dev", Ipv4Addr::new(1, 2, 3, 4));
+            testing::response(query, 0, &[answer], &[], &[])
+        });
+        let resolver = Resolver::builder()
+            .protocol(Protocol::TCP)
+            .roots(&[Ipv4Addr::new(127, 0, 0, 1)])
+            .port(port)
+            .build();
+
+        let message = resolver.query("blog.wtcx.dev", &RecordType::A).unwrap();
+
+        assert_eq!(message.a_records(), [Ipv4Addr::new(1, 2, 3, 4)]);
+        assert_eq!(received.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_truncated_response_over_tcp() {
+        let (port, received) = testing::serve_tcp(|query| {
+            let answer = testing::a_record("blog.wtcx.dev", Ipv4Addr::new(1, 2, 3, 4));
+            testing::response(query, 0, &[answer], &[], &[])
+        });
+        // The same name server over UDP, whose answer doesn't fit in the datagram.
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, port)).unwrap();
+        testing::serve(socket, |query| {
+            let mut response = testing::response(query, 0, &[], &[], &[]);
+            // TC
+            response[2] |= 0x02;
+            Some(response)
+        });
+        let resolver = Resolver::builder()
+            .max_attempts(1)
+            .roots(&[Ipv4Addr::LOCALHOST])
+            .port(port)
+            .build();
+
+        let (message, stats) = resolver
+            .query_with_stats("blog.wtcx.dev", &RecordType::A)
+            .unwrap();
+
+        assert_eq!(message.a_records(), [Ipv4Addr::new(1, 2, 3, 4)]);
+        assert_eq!(
+            stats,
+            QueryStats {
+                truncated: true,
+                transport: Protocol::TCP,
+            }
+        );
+        assert_eq!(received.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_tcp_connection_reused() {
+        let (port, connections) = testing::serve_tcp_connections(|query| {
+            let name = testing::question_name(query);
+            let answer = testing::a_record(&name, Ipv4Addr::new(1, 2, 3, 4));
+            testing::response(query, 0, &[answer], &[], &[])
+        });
+        let resolver = Resolver::builder()
+            .protocol(Protocol::TCP)
+            .pool_size(1)
+            .roots(&[Ipv4Addr::new(127, 0, 0, 1)])
+            .port(port)
+            .build();
+
+        // Different domains, so the second one isn't answered from the cache.
+        let first = resolver.query("blog.wtcx.dev", &RecordType::A).unwrap();
+        let second = resolver.query("www.wtcx.dev", &RecordType::A).unwrap();
+
+        assert_eq!(first.a_records(), [Ipv4Addr::new(1, 2, 3, 4)]);
+        assert_eq!(second.a_records(), [Ipv4Addr::new(1, 2, 3, 4)]);
+        assert_eq!(connections.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_zone_transfer() {
+        let port = testing::serve_tcp_stream(|query| {
+            assert_eq!(testing::question_type(query), RecordType::AXFR);
+            let soa = testing::soa_record("wtcx.dev", 300);
+            let first = testing::response(
+                query,
+                0,
+                &[soa.clone(), testing::ns_record("wtcx.dev", "ns.wtcx.dev")],
+                &[],
+                &[],
+            );
+            // The messages following the first one leave the question out.
+            let next = |answers: &[Vec<u8>]| {
+                let mut message = query[0..2].to_vec();
+                message.extend_from_slice(&[0x80, 0, 0, 0]);
+                message.extend_from_slice(&(answers.len() as u16).to_be_bytes());
+                message.extend_from_slice(&[0, 0, 0, 0]);
+                message.extend(answers.concat());
+                message
+            };
+            vec![
+                first,
+                next(&[testing::a_record("ns.wtcx.dev", Ipv4Addr::new(1, 2, 3, 4))]),
+                next(&[
+                    testing::a_record("blog.wtcx.dev", Ipv4Addr::new(5, 6, 7, 8)),
+                    soa,
+                ]),
+            ]
+        });
+        let resolver = Resolver::builder().port(port).build();
+
+        let records = resolver
+            .zone_transfer("wtcx.dev", Ipv4Addr::LOCALHOST.into())
+            .unwrap();
+        let types: Vec<_> = records.iter().map(|rr| rr.r_type.unwrap()).collect();
+
+        assert_eq!(
+            types,
+            [
+                RecordType::SOA,
+                RecordType::NS,
+                RecordType::A,
+                RecordType::A
+            ]
+        );
+        assert_eq!(
+            records[3].to_string(),
+            "blog.wtcx.dev.  300  IN  A  5.6.7.8"
+        );
+    }
+
+    #[test]
+    fn test_zone_transfer_without_closing_soa() {
+        let port = testing::serve_tcp_stream(|query| {
+            let soa = testing::soa_record("wtcx.dev", 300);
+            vec![testing::response(query, 0, &[soa], &[], &[])]
+        });
+        let resolver = Resolver::builder()
+            .timeout(Duration::from_millis(100))
+            .port(port)
+            .build();
+
+        assert!(resolver
+            .zone_transfer("wtcx.dev", Ipv4Addr::LOCALHOST.into())
+            .is_err());
+    }
+
+    #[test]
+    fn test_negative_cache() {
+        let (port, mut sockets) = testing::bind_servers(1);
+        let received = testing::serve(sockets.pop().unwrap(), |query| {
+            let soa = testing::soa_record("wtcx.dev", 60);
+            Some(testing::response(query, 3, &[], &[soa], &[]))
+        });
+        let resolver = Resolver::builder()
+            .roots(&[Ipv4Addr::new(127, 0, 0, 1)])
+            .port(port)
+            .build();
+
+        // The SOA is kept for the answer from the cache as well.
+        for _ in 0..2 {
+            assert!(matches!(
+                resolver.query("missing.wtcx.dev", &RecordType::A),
+                Err(Error::NxDomain { authorities })
+                    if matches!(authorities[..], [ResourceRecord { r_type: Some(RecordType::SOA), .. }])
+            ));
+        }
+        assert_eq!(received.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_nxdomain_keeps_soa() {
+        let (port, mut sockets) = testing::bind_servers(1);
+        testing::serve(sockets.pop().unwrap(), |query| {
+            let soa = testing::soa_record("wtcx.dev", 60);
+            Some(testing::response(query, 3, &[], &[soa], &[]))
+        });
+        let resolver = Resolver::builder()
+            .roots(&[Ipv4Addr::new(127, 0, 0, 1)])
+            .port(port)
+            .build();
+
+        let result = resolver.query("missing.wtcx.dev", &RecordType::A);
+        let Err(Error::NxDomain { authorities }) = result else {
+            panic!("expect NXDOMAIN");
+        };
+        assert_eq!(authorities.len(), 1);
+        assert_eq!(authorities[0].name, "wtcx.dev");
+        let RecordData::SOA(soa) = &authorities[0].r_data else {
+            panic!("expect SOA, got {}", authorities[0]);
+        };
+        assert_eq!(soa.minimum(), 60);
+    }
+
+    #[test]
+    fn test_query_root() {
+        let (port, mut sockets) = testing::bind_servers(1);
+        testing::serve(sockets.pop().unwrap(), |query| {
+            // QNAME is the null label alone, followed by QTYPE.
+            assert_eq!(query[12..15], [0, 0, 2]);
+            let answer = testing::ns_record("", "a.root-servers.net");
+            Some(testing::response(query, 0, &[answer], &[], &[]))
+        });
+        let resolver = Resolver::builder()
+            .roots(&[Ipv4Addr::new(127, 0, 0, 1)])
+            .port(port)
+            .build();
+
+        let message = resolver.query(".", &RecordType::NS).unwrap();
+
+        assert_eq!(
+            message.answers[0].to_string(),
+            ".  300  IN  NS  a.root-servers.net."
+        );
+    }
+
+    #[test]
+    fn test_max_labels() {
+        let resolver = Resolver::builder()
+            .roots(&[Ipv4Addr::LOCALHOST])
+            .max_labels(3)
+            .build();
+
+        assert_eq!(
+            resolver.query("www.blog.wtcx.dev", &RecordType::A).err(),
+            Some(Error::InvalidHostname)
+        );
+    }
+
+    #[cfg(feature = "idna")]
+    #[test]
+    fn test_query_unicode_domain() {
+        let (port, mut sockets) = testing::bind_servers(1);
+        testing::serve(sockets.pop().unwrap(), |query| {
+            let name = testing::question_name(query);
+            assert_eq!(name, "xn--mnchen-3ya.de");
+            let answer = testing::a_record(&name, Ipv4Addr::new(1, 2, 3, 4));
+            Some(testing::response(query, 0, &[answer], &[], &[]))
+        });
+        let resolver = Resolver::builder()
+            .roots(&[Ipv4Addr::new(127, 0, 0, 1)])
+            .port(port)
+            .build();
+
+        let message = resolver.query("münchen.de", &RecordType::A).unwrap();
+
+        assert_eq!(message.a_records(), [Ipv4Addr::new(1, 2, 3, 4)]);
+    }
+
+    #[test]
+    fn test_shared_cache() {
+        let (port, mut sockets) = testing::bind_servers(1);
+        let received = testing::serve(sockets.pop().unwrap(), |query| {
+            // Keep the others waiting for a while.
+            std::thread::sleep(Duration::from_millis(100));
+            let answer = testing::a_record("blog.wtcx.dev", Ipv4Addr::new(1, 2, 3, 4));
+            Some(testing::response(query, 0, &[answer], &[], &[]))
+        });
+        let resolver = Resolver::builder()
+            .roots(&[Ipv4Addr::new(127, 0, 0, 1)])
+            .port(port)
+            .build();
+
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let resolver = resolver.clone();
+                std::thread::spawn(move || resolver.query("blog.wtcx.dev", &RecordType::A))
+            })
+            .collect();
+
+        for handle in handles {
+            let message = handle.join().unwrap().unwrap();
+            assert_eq!(message.a_records(), [Ipv4Addr::new(1, 2, 3, 4)]);
+        }
+        assert_eq!(received.load(Ordering::SeqCst), 1);
+
+        // Another resolver with the same cache doesn't ask again either.
+        let resolver = Resolver::builder()
+            .roots(&[Ipv4Addr::new(127, 0, 0, 1)])
+            .port(port)
+            .cache(resolver.cache.clone())
+            .build();
+        assert!(resolver.query("blog.wtcx.dev", &RecordType::A).is_ok());
+        assert_eq!(received.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_bind_addr() {
+        let v4 = IpAddr::from(ROOT_NAME_SERVERS_V4[0]);
+        let v6 = IpAddr::from(ROOT_NAME_SERVERS_V6[0]);
+
+        let resolver = Resolver::default();
+        assert_eq!(resolver.local_addr(&v4), "0.0.0.0:0".parse().unwrap());
+        assert_eq!(resolver.local_addr(&v6), "[::]:0".parse().unwrap());
+
+        let resolver = Resolver::builder()
+            .bind_addr(Ipv4Addr::LOCALHOST.into())
+            .build();
+        let socket = UdpSocket::bind(resolver.local_addr(&v4)).unwrap();
+        assert_eq!(
+            socket.local_addr().unwrap().ip(),
+            IpAddr::from(Ipv4Addr::LOCALHOST)
+        );
+        assert_eq!(resolver.local_addr(&v6), "[::]:0".parse().unwrap());
+    }
+
+    #[test]
+    fn test_source_port() {
+        let port = UdpSocket::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let resolver = Resolver::builder()
+            .bind_addr(Ipv4Addr::LOCALHOST.into())
+            .source_port(port)
+            .build();
+
+        let socket = UdpSocket::bind(resolver.local_addr(&Ipv4Addr::LOCALHOST.into())).unwrap();
+        assert_eq!(socket.local_addr().unwrap().port(), port);
+    }
+
+    #[test]
+    fn test_roots_by_name() {
+        let (port, mut sockets) = testing::bind_servers(1);
+        let received = testing::serve(sockets.pop().unwrap(), |query| {
+            let answer = testing::a_record("blog.wtcx.dev", Ipv4Addr::new(1, 2, 3, 4));
+            Some(testing::response(query, 0, &[answer], &[], &[]))
+        });
+        let lookup = |host: &str| match host {
+            "ns.wtcx.test" => Ok(vec![IpAddr::from(Ipv4Addr::LOCALHOST)]),
+            _ => Ok(vec![]),
+        };
+
+        let resolver = Resolver::builder()
+            .port(port)
+            .roots_by_name_with(&["ns.wtcx.test"], lookup)
+            .unwrap()
+            .build();
+        let message = resolver.query("blog.wtcx.dev", &RecordType::A).unwrap();
+
+        assert_eq!(resolver.roots, [IpAddr::from(Ipv4Addr::LOCALHOST)]);
+        assert_eq!(message.a_records(), [Ipv4Addr::new(1, 2, 3, 4)]);
+        assert_eq!(received.load(Ordering::SeqCst), 1);
+        assert!(Resolver::builder()
+            .roots_by_name_with(&["unknown.test"], lookup)
+            .is_err());
+    }
+
+    #[test]
+    fn test_root_name_servers() {
+        let v6: HashSet<_> = ROOT_NAME_SERVERS_V6.iter().collect();
+        assert_eq!(v6.len(), 13);
+        assert_eq!(
+            ROOT_NAME_SERVERS_V6[0],
+            "2001:503:ba3e::2:30".parse::<Ipv6Addr>().unwrap()
+        );
+        assert_eq!(
+            ROOT_NAME_SERVERS_V6[12],
+            "2001:dc3::35".parse::<Ipv6Addr>().unwrap()
+        );
+
+        let resolver = Resolver::builder()
+            .address_family(AddressFamily::V6)
+            .build();
+        assert!(resolver.roots.iter().all(IpAddr::is_ipv6));
+
+        let resolver = Resolver::builder()
+            .address_family(AddressFamily::Dual)
+            .build();
+        assert_eq!(resolver.roots.len(), 26);
+    }
+
+    #[test]
+    fn test_ipv6_name_servers() {
+        let server = UdpSocket::bind((Ipv6Addr::LOCALHOST, 0)).unwrap();
+        let port = server.local_addr().unwrap().port();
+        let referred = AtomicBool::new(false);
+        testing::serve(server, move |query| {
+            let name = testing::question_name(query);
+            let response = match (name.as_str(), testing::question_type(query)) {
+                ("ns.wtcx.dev", RecordType::AAAA) => {
+                    let answer = testing::aaaa_record(&name, Ipv6Addr::LOCALHOST);
+                    testing::response(query, 0, &[answer], &[], &[])
+                }
+                // The glue only has an IPv4 address, which can't be used.
+                ("blog.wtcx.dev", _) if !referred.swap(true, Ordering::SeqCst) => {
+                    let referral = testing::ns_record("wtcx.dev", "ns.wtcx.dev");
+                    let glue = testing::a_record("ns.wtcx.dev", Ipv4Addr::new(127, 0, 0, 9));
+                    testing::response(query, 0, &[], &[referral], &[glue])
+                }
+                _ => {
+                    let answer = testing::a_record(&name, Ipv4Addr::new(1, 2, 3, 4));
+                    testing::response(query, 0, &[answer], &[], &[])
+                }
+            };
+            Some(response)
+        });
+
+        let resolver = Resolver::builder()
+            .address_family(AddressFamily::V6)
+            .timeout(Duration::from_millis(100))
+            .roots(&[Ipv6Addr::LOCALHOST])
+            .port(port)
+            .build();
+
+        let message = resolver.query("blog.wtcx.dev", &RecordType::A).unwrap();
+
+        assert_eq!(message.a_records(), [Ipv4Addr::new(1, 2, 3, 4)]);
+    }
+
+    #[test]
+    fn test_query_all_reuses_name_servers() {
+        let (port, mut sockets) = testing::bind_servers(2);
+        let authority = testing::serve(sockets.pop().unwrap(), |query| {
+            let answer = match testing::question_type(query) {
+                RecordType::AAAA => testing::aaaa_record("blog.wtcx.dev", Ipv6Addr::LOCALHOST),
+                _ => testing::a_record("blog.wtcx.dev", Ipv4Addr::new(1, 2, 3, 4)),
+            };
+            Some(testing::response(query, 0, &[answer], &[], &[]))
+        });
+        let root = testing::serve(sockets.pop().unwrap(), |query| {
+            let referral = testing::ns_record("wtcx.dev", "ns.wtcx.dev");
+            let glue = testing::a_record("ns.wtcx.dev", Ipv4Addr::new(127, 0, 0, 2));
+            Some(testing::response(query, 0, &[], &[referral], &[glue]))
+        });
+
+        let resolver = Resolver::builder()
+            .timeout(Duration::from_millis(100))
+            .roots(&[Ipv4Addr::LOCALHOST])
+            .port(port)
+            .build();
+
+        let results = resolver
+            .query_all("blog.wtcx.dev", &[RecordType::A, RecordType::AAAA])
+            .unwrap();
+
+        assert_eq!(results.len(), 2);
+        assert_eq!(
+            results[0].as_ref().unwrap().answers[0].r_type,
+            Some(RecordType::A)
+        );
+        assert_eq!(
+            results[1].as_ref().unwrap().answers[0].r_type,
+            Some(RecordType::AAAA)
+        );
+        assert_eq!(root.load(Ordering::SeqCst), 1);
+        assert_eq!(authority.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_query_all_checks_like_query() {
+        let (port, mut sockets) = testing::bind_servers(1);
+        let received = testing::serve(sockets.pop().unwrap(), |query| {
+            let answer = testing::a_record("blog.wtcx.dev", Ipv4Addr::new(1, 2, 3, 4));
+            Some(testing::response(query, 0, &[answer], &[], &[]))
+        });
+        let resolver = Resolver::builder()
+            .roots(&[Ipv4Addr::LOCALHOST])
+            .port(port)
+            .build();
+
+        let results = resolver
+            .query_all("blog.wtcx.dev", &[RecordType::AXFR, RecordType::A])
+            .unwrap();
+
+        assert_eq!(
+            results[0].as_ref().unwrap_err(),
+            &Error::ResolverError("AXFR has to be done with Resolver::zone_transfer".into())
+        );
+        assert_eq!(
+            results[1].as_ref().unwrap().a_records(),
+            [Ipv4Addr::new(1, 2, 3, 4)]
+        );
+        assert_eq!(received.load(Ordering::SeqCst), 1);
+
+        let resolver = Resolver::builder().protocol(Protocol::DOT).build();
+        assert_eq!(
+            resolver
+                .query_all("blog.wtcx.dev", &[RecordType::A])
+                .unwrap_err(),
+            Error::ResolverError("protocol DOT isn't supported by query_all".into())
+        );
+    }
+
+    #[test]
+    fn test_referral_loop() {
+        let (port, mut sockets) = testing::bind_servers(1);
+        // ns1.loop.test and ns2.loop.test are the authorities of each other, without any glue.
+        testing::serve(sockets.pop().unwrap(), |query| {
+            let name_server = match testing::question_name(query).as_str() {
+                "ns1.loop.test" => "ns2.loop.test",
+                _ => "ns1.loop.test",
+            };
+            let authority = testing::ns_record("loop.test", name_server);
+            Some(testing::response(query, 0, &[], &[authority], &[]))
+        });
+
+        let resolver = Resolver::builder()
+            .max_referral_depth(3)
+            .timeout(Duration::from_millis(100))
+            .roots(&[Ipv4Addr::LOCALHOST])
+            .port(port)
+            .build();
+
+        assert!(matches!(
+            resolver.query("blog.loop.test", &RecordType::A),
+            Err(Error::ResolverError(reason)) if reason == "referral loop detected"
+        ));
+    }
+
+    #[cfg(feature = "dot")]
+    #[test]
+    #[ignore = "requires network access to a public DoT server"]
+    fn test_dot() {
+        let resolver = Resolver::builder()
+            .protocol(Protocol::DOT)
+            .dot_server("1.1.1.1:853".parse().unwrap(), "cloudflare-dns.com")
+            .build();
+
+        let message = resolver.query("one.one.one.one", &RecordType::A).unwrap();
+
+        assert!(message
+            .answers
+            .iter()
+            .any(|rr| rr.ipv4_ip() == Some(Ipv4Addr::new(1, 1, 1, 1))));
+    }
+
+    #[cfg(feature = "dot")]
+    #[test]
+    fn test_dot_without_server() {
+        let resolver = Resolver::builder().protocol(Protocol::DOT).build();
+
+        assert!(resolver.query("one.one.one.one", &RecordType::A).is_err());
+    }
+
+    #[cfg(feature = "doh")]
+    #[test]
+    fn test_doh() {
+        let endpoint = testing::serve_http(|query| {
+            assert_eq!(testing::question_name(query), "blog.wtcx.dev");
+            let answer = testing::a_record("blog.wtcx.dev", Ipv4Addr::new(192, 0, 2, 1));
+            (200, testing::response(query, 0, &[answer], &[], &[]))
+        });
+        let resolver = Resolver::builder()
+            .protocol(Protocol::DOH)
+            .doh_endpoint(&endpoint)
+            .build();
+
+        let message = resolver.query("blog.wtcx.dev", &RecordType::A).unwrap();
+
+        assert_eq!(message.answers.len(), 1);
+        assert_eq!(
+            message.answers[0].ipv4_ip(),
+            Some(Ipv4Addr::new(192, 0, 2, 1))
+        );
+    }
+
+    #[cfg(feature = "doh")]
+    #[test]
+    fn test_doh_with_http_error() {
+        let endpoint = testing::serve_http(|_| (503, vec![]));
+        let resolver = Resolver::builder()
+            .protocol(Protocol::DOH)
+            .doh_endpoint(&endpoint)
+            .build();
+
+        assert!(matches!(
+            resolver.query("blog.wtcx.dev", &RecordType::A),
+            Err(Error::NetworkError(_))
+        ));
+    }
+
+    #[test]
+    fn test_reverse_lookup_unspecified_address() {
+        let resolver = Resolver::default();
+
+        assert!(resolver
+            .reverse_lookup(Ipv4Addr::UNSPECIFIED.into())
+            .is_err());
+        assert!(resolver.reverse_lookup("::".parse().unwrap()).is_err());
+    }
 }
@@ -0,0 +1,513 @@
+use std::collections::HashSet;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+
+use log::{debug, trace};
+use tokio::net::UdpSocket;
+use tokio::time::{self, Instant};
+
+use super::{Budget, Protocol, Referral, Resolver};
+use crate::cache::Lookup;
+use crate::message::Message;
+use crate::{transport, utils, Error, RecordClass, RecordType};
+
+impl Resolver {
+    /// Query domain with given type without blocking the thread.
+    ///
+    /// The resolution is the same as [`Resolver::query`], but the network I/O is done with tokio.
+    /// It's safe to cancel (i.e., drop) the returned future, since each query uses its own socket.
+    ///
+    /// Answers are cached the same way, but concurrent queries of the same question don't wait for each other,
+    /// see [`Cache`](crate::Cache). Only [`Protocol::UDP`] and [`Protocol::TCP`] are supported, others fail with
+    /// [`Error::ResolverError`].
+    pub async fn query_async(
+        &self,
+        domain: &str,
+        record_type: &RecordType,
+    ) -> Result<Message, Error> {
+        let domain = &utils::to_ascii(domain)?;
+        self.validate_domain(domain)?;
+        Self::check_query_type(record_type)?;
+
+        if let Some(blocked) = self.check_blocklist(domain, record_type) {
+            return blocked;
+        }
+
+        if !matches!(self.protocol, Protocol::UDP | Protocol::TCP) {
+            return Err(Error::ResolverError(format!(
+                "protocol {:?} isn't supported by async queries",
+                self.protocol
+            )));
+        }
+
+        let budget = self.new_budget();
+        let message = self
+            .resolve_async(domain, record_type, &RecordClass::IN, 0, &budget)
+            .await?;
+        self.check_cnames_async(&message, record_type, &budget)
+            .await?;
+
+        Ok(message)
+    }
+
+    /// Same as [`Resolver::check_cnames`], but without blocking the thread.
+    async fn check_cnames_async(
+        &self,
+        message: &Message,
+        record_type: &RecordType,
+        budget: &Budget,
+    ) -> Result<(), Error> {
+        if !self.validate_cnames {
+            return Ok(());
+        }
+        message.check_cnames()?;
+
+        match message.unresolved_cname() {
+            Some((name, target)) => {
+                let answer = self
+                    .resolve_async(&target, record_type, &RecordClass::IN, 0, budget)
+                    .await;
+                Self::check_cname_target(&name, &target, answer)
+            }
+            None => Ok(()),
+        }
+    }
+
+    async fn resolve_async(
+        &self,
+        domain: &str,
+        record_type: &RecordType,
+        record_class: &RecordClass,
+        depth: usize,
+        budget: &Budget,
+    ) -> Result<Message, Error> {
+        debug!("Looking up {domain}");
+
+        if depth > self.max_referral_depth {
+            return Err(Error::ResolverError("referral loop detected".into()));
+        }
+
+        match self
+            .cache
+            .peek(domain, record_type, record_class, &self.query_options())
+        {
+            Some(Lookup::Cached(response)) => {
+                debug!("{domain} is answered from the cache");
+                self.observer.on_cache_hit(domain, record_type);
+                return self.cached_answer(response, domain, record_type, record_class);
+            }
+            Some(Lookup::Nonexistent(authorities)) => {
+                debug!("{domain} is known not to exist");
+                self.observer.on_cache_hit(domain, record_type);
+                return Err(Error::NxDomain { authorities });
+            }
+            _ => self.observer.on_cache_miss(domain, record_type),
+        }
+
+        let mut name_server_ips = self.roots.clone();
+        // The zone the name servers are asked as authorities for, starting from the root.
+        let mut zone = String::new();
+        let mut tried = HashSet::new();
+        let mut last = None;
+
+        for _ in 0..self.max_attempts {
+            let picked = Self::pick_untried(&name_server_ips, &tried, self.fan_out)?;
+            tried.extend(&picked);
+            self.spend(budget, picked.len())?;
+
+            debug!("continue to look up {domain} with name server IPs {picked:?}");
+            let message = match self
+                .exchange_async(domain, record_type, record_class, &picked, budget.deadline)
+                .await
+            {
+                Ok(message) => message,
+                Err(Error::NetworkError(err)) => {
+                    debug!("can't reach name servers {picked:?}: {err}");
+                    self.observe_failure(&picked, record_type, &err);
+                    continue;
+                }
+                Err(Error::ServerError(err)) if err.is_retriable() => {
+                    debug!("name servers {picked:?} failed to answer: {err}");
+                    self.observer.on_retry(&picked, record_type);
+                    continue;
+                }
+                Err(Error::NxDomain { authorities }) => {
+                    self.cache_nonexistent(domain, record_class, &authorities);
+                    return Err(Error::NxDomain { authorities });
+                }
+                Err(err) => return Err(err),
+            };
+
+            if !message.answers.is_empty() {
+                self.cache_answer(domain, record_type, record_class, &message);
+                return Ok(message);
+            }
+            if self.recursion_desired {
+                return Ok(message);
+            }
+
+            let delegation = Self::collect_delegation(&message, domain, &zone);
+            zone.clone_from(&delegation.zone);
+            let referral = self.referral(delegation)?;
+            last = Some(Box::new(message));
+            name_server_ips = match referral {
+                Referral::NameServers(ips) => ips,
+                Referral::NameServerDomain(name_server_domain) => {
+                    debug!("Looking up {domain} using {name_server_domain}");
+                    // Recursive async calls need to be boxed, otherwise the future would have an infinite size.
+                    Box::pin(self.resolve_name_server_async(&name_server_domain, depth + 1, budget))
+                        .await?
+                }
+            };
+        }
+
+        Err(Error::ResolutionFailed {
+            domain: domain.into(),
+            last,
+        })
+    }
+
+    /// Same as [`Resolver::resolve_name_server`], but without blocking the thread.
+    async fn resolve_name_server_async(
+        &self,
+        domain: &str,
+        depth: usize,
+        budget: &Budget,
+    ) -> Result<Vec<IpAddr>, Error> {
+        let mut ips = vec![];
+        let mut last_err = None;
+
+        for record_type in self.address_family.record_types() {
+            match self
+                .resolve_async(domain, record_type, &RecordClass::IN, depth, budget)
+                .await
+            {
+                Ok(message) => ips.extend(self.extract_ips(&message.answers)),
+                Err(err) => last_err = Some(err),
+            }
+        }
+
+        match last_err {
+            Some(err) if ips.is_empty() => Err(err),
+            _ => Ok(ips),
+        }
+    }
+
+    /// Same as [`Resolver::exchange`], but without blocking the thread.
+    async fn exchange_async(
+        &self,
+        domain: &str,
+        record_type: &RecordType,
+        record_class: &RecordClass,
+        name_server_ips: &[IpAddr],
+        deadline: Option<std::time::Instant>,
+    ) -> Result<Message, Error> {
+        let queries = self.new_queries(domain, record_type, record_class, name_server_ips);
+        match self.protocol {
+            Protocol::TCP => {
+                self.resolve_answer_tcp_async(&queries, record_type, deadline)
+                    .await
+            }
+            _ => {
+                self.resolve_answer_async(&queries, record_type, deadline)
+                    .await
+            }
+        }
+    }
+
+    /// Same as [`Resolver::resolve_answer`], but without blocking the thread.
+    async fn resolve_answer_async(
+        &self,
+        queries: &[(SocketAddr, Message)],
+        record_type: &RecordType,
+        deadline: Option<std::time::Instant>,
+    ) -> Result<Message, Error> {
+        let socket = UdpSocket::bind(self.local_addr(&queries[0].0.ip()))
+            .await
+            .map_err(Error::NetworkError)?;
+
+        let packets = queries
+            .iter()
+            .map(|(addr, query)| Ok((*addr, query.to_query_bytes()?)))
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        let sent_at = Instant::now();
+        self.send_packets_async(&socket, &packets, record_type)
+            .await?;
+
+        let deadline = Instant::from_std(self.attempt_deadline(sent_at.into_std(), deadline));
+        let mut retransmits = self.retransmits;
+        let mut backoff = self.retransmit_interval;
+        let mut retransmit_at = sent_at.checked_add(backoff).filter(|_| retransmits > 0);
+        let mut response = vec![0; self.udp_buffer_size()];
+
+        loop {
+            let wake_at = retransmit_at.map_or(deadline, |at| at.min(deadline));
+            // `recv_from` is cancellation safe, nothing is lost if the timeout fires first.
+            let received = time::timeout_at(wake_at, socket.recv_from(&mut response)).await;
+            let (bytes_received, peer) = match received {
+                Ok(received) => received.map_err(Error::NetworkError)?,
+                Err(elapsed) if wake_at == deadline => {
+                    return Err(Error::NetworkError(elapsed.into()))
+                }
+                Err(_) => {
+                    debug!("no response yet, send the queries again");
+                    self.send_packets_async(&socket, &packets, record_type)
+                        .await?;
+                    retransmits -= 1;
+                    backoff = backoff.saturating_mul(2);
+                    retransmit_at = wake_at.checked_add(backoff).filter(|_| retransmits > 0);
+                    continue;
+                }
+            };
+            trace!("received: {bytes_received} bytes from {peer}");
+
+            if let Some(query) = Self::match_query(queries, &response[..bytes_received], &peer) {
+                let response = &response[..bytes_received];
+                self.observe_response(&peer, record_type, response, sent_at.into_std());
+
+                // See `Resolver::resolve_answer` for why a truncated response isn't parsed at all.
+                if !Self::is_truncated(response) {
+                    return self.with_response(response, query);
+                }
+
+                debug!("response from {peer} is truncated, ask it again over TCP");
+                // It's still the same attempt, so it has the time left of it.
+                let response = self
+                    .send_tcp_async(&peer, query, record_type, deadline)
+                    .await?;
+                return self.with_response(&response, query);
+            }
+        }
+    }
+
+    /// Same as [`Resolver::resolve_answer_tcp`], but without blocking the thread.
+    async fn resolve_answer_tcp_async(
+        &self,
+        queries: &[(SocketAddr, Message)],
+        record_type: &RecordType,
+        deadline: Option<std::time::Instant>,
+    ) -> Result<Message, Error> {
+        let mut last_err = Error::NetworkError(io::ErrorKind::TimedOut.into());
+
+        for (addr, query) in queries {
+            let deadline = self.attempt_deadline(std::time::Instant::now(), deadline);
+            let response = self
+                .send_tcp_async(addr, query, record_type, Instant::from_std(deadline))
+                .await;
+            match response {
+                Ok(response) => return self.with_response(&response, query),
+                Err(err) => {
+                    debug!("can't query {addr} over TCP: {err}");
+                    last_err = err;
+                }
+            }
+        }
+
+        Err(last_err)
+    }
+
+    /// Same as [`Resolver::send_tcp`], but without blocking the thread. Each query has its own connection.
+    async fn send_tcp_async(
+        &self,
+        addr: &SocketAddr,
+        query: &Message,
+        record_type: &RecordType,
+        deadline: Instant,
+    ) -> Result<Vec<u8>, Error> {
+        let sent_at = std::time::Instant::now();
+        let packet = query.to_query_bytes()?;
+
+        self.observer.on_query_sent(addr, record_type);
+        let response = time::timeout_at(deadline, transport::tcp::exchange_async(addr, &packet))
+            .await
+            .map_err(|elapsed| Error::NetworkError(elapsed.into()))??;
+        trace!("received: {} bytes from {addr}", response.len());
+        self.observe_response(addr, record_type, &response, sent_at);
+
+        Ok(response)
+    }
+
+    /// Same as [`Resolver::send_packets`], but without blocking the thread.
+    async fn send_packets_async(
+        &self,
+        socket: &UdpSocket,
+        packets: &[(SocketAddr, Vec<u8>)],
+        record_type: &RecordType,
+    ) -> Result<(), Error> {
+        let mut sent = 0;
+        let mut last_err = None;
+
+        for (addr, packet) in packets {
+            match socket.send_to(packet, addr).await {
+                Ok(bytes_sent) => {
+                    trace!("sent: {bytes_sent} bytes to {addr}");
+                    self.observer.on_query_sent(addr, record_type);
+                    sent += 1;
+                }
+                Err(err) => {
+                    debug!("can't send query to {addr}: {err}");
+                    last_err = Some(err);
+                }
+            }
+        }
+
+        match last_err {
+            Some(err) if sent == 0 => Err(Error::NetworkError(err)),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Query domain with given type without blocking the thread, using a [`Resolver`] with default options.
+///
+/// ```no_run
+/// # async fn run() {
+/// use tiny_resolver_rs::{query_async, RecordType};
+/// let res = query_async("google.com", &RecordType::A).await.unwrap();
+/// # }
+/// ```
+pub async fn query_async(domain: &str, record_type: &RecordType) -> Result<Message, Error> {
+    Resolver::default().query_async(domain, record_type).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use std::net::{Ipv4Addr, UdpSocket};
+    use std::sync::atomic::Ordering;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_query_async() {
+        let (port, mut sockets) = testing::bind_servers(1);
+        testing::serve(sockets.pop().unwrap(), |query| {
+            let answer = testing::a_record("blog.wtcx.dev", Ipv4Addr::new(1, 2, 3, 4));
+            Some(testing::response(query, 0, &[answer], &[], &[]))
+        });
+
+        let resolver = Resolver::builder()
+            .timeout(Duration::from_millis(100))
+            .roots(&[Ipv4Addr::LOCALHOST])
+            .port(port)
+            .build();
+
+        let message = resolver
+            .query_async("blog.wtcx.dev", &RecordType::A)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            message.answers[0].ipv4_ip(),
+            Some(Ipv4Addr::new(1, 2, 3, 4))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_query_async_cache() {
+        let (port, mut sockets) = testing::bind_servers(1);
+        let received = testing::serve(sockets.pop().unwrap(), |query| {
+            if testing::question_name(query) == "blog.wtcx.dev" {
+                let answer = testing::a_record("blog.wtcx.dev", Ipv4Addr::new(1, 2, 3, 4));
+                return Some(testing::response(query, 0, &[answer], &[], &[]));
+            }
+            let soa = testing::soa_record("wtcx.dev", 60);
+            Some(testing::response(query, 3, &[], &[soa], &[]))
+        });
+        let resolver = Resolver::builder()
+            .timeout(Duration::from_millis(100))
+            .roots(&[Ipv4Addr::LOCALHOST])
+            .port(port)
+            .build();
+
+        for _ in 0..2 {
+            let message = resolver.query_async("blog.wtcx.dev", &RecordType::A).await;
+            assert_eq!(message.unwrap().a_records(), [Ipv4Addr::new(1, 2, 3, 4)]);
+            assert!(matches!(
+                resolver.query_async("missing.wtcx.dev", &RecordType::A).await,
+                Err(Error::NxDomain { authorities }) if authorities.len() == 1
+            ));
+        }
+        assert_eq!(received.load(Ordering::SeqCst), 2);
+
+        // The blocking queries share the same cache.
+        assert!(resolver.query("blog.wtcx.dev", &RecordType::A).is_ok());
+        assert_eq!(received.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_truncated_response_over_tcp_async() {
+        let (port, received) = testing::serve_tcp(|query| {
+            let answer = testing::a_record("blog.wtcx.dev", Ipv4Addr::new(1, 2, 3, 4));
+            testing::response(query, 0, &[answer], &[], &[])
+        });
+        // The same name server over UDP, whose answer doesn't fit in the datagram.
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, port)).unwrap();
+        testing::serve(socket, |query| {
+            let mut response = testing::response(query, 0, &[], &[], &[]);
+            // TC
+            response[2] |= 0x02;
+            Some(response)
+        });
+        let resolver = Resolver::builder()
+            .max_attempts(1)
+            .roots(&[Ipv4Addr::LOCALHOST])
+            .port(port)
+            .build();
+
+        let message = resolver
+            .query_async("blog.wtcx.dev", &RecordType::A)
+            .await
+            .unwrap();
+
+        assert_eq!(message.a_records(), [Ipv4Addr::new(1, 2, 3, 4)]);
+        assert_eq!(received.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_query_async_over_tcp() {
+        let (port, received) = testing::serve_tcp(|query| {
+            let answer = testing::a_record("blog.wtcx.dev", Ipv4Addr::new(1, 2, 3, 4));
+            testing::response(query, 0, &[answer], &[], &[])
+        });
+        let resolver = Resolver::builder()
+            .protocol(Protocol::TCP)
+            .roots(&[Ipv4Addr::LOCALHOST])
+            .port(port)
+            .build();
+
+        let message = resolver
+            .query_async("blog.wtcx.dev", &RecordType::A)
+            .await
+            .unwrap();
+
+        assert_eq!(message.a_records(), [Ipv4Addr::new(1, 2, 3, 4)]);
+        assert_eq!(received.load(Ordering::SeqCst), 1);
+
+        let resolver = Resolver::builder().protocol(Protocol::DOT).build();
+        assert!(matches!(
+            resolver.query_async("blog.wtcx.dev", &RecordType::A).await,
+            Err(Error::ResolverError(reason)) if reason == "protocol DOT isn't supported by async queries"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_query_async_rejects_axfr() {
+        let (port, mut sockets) = testing::bind_servers(1);
+        let received = testing::serve(sockets.pop().unwrap(), |_| None);
+        let resolver = Resolver::builder()
+            .roots(&[Ipv4Addr::LOCALHOST])
+            .port(port)
+            .build();
+
+        assert_eq!(
+            resolver
+                .query_async("wtcx.dev", &RecordType::AXFR)
+                .await
+                .unwrap_err(),
+            Error::ResolverError("AXFR has to be done with Resolver::zone_transfer".into())
+        );
+        assert_eq!(received.load(Ordering::SeqCst), 0);
+    }
+}
@@ -0,0 +1,289 @@
+//! Fake name servers and response builders for tests that shouldn't touch the real network.
+//!
+//! The fake name servers are only for the resolver, so they need the `std` feature as well.
+#![cfg_attr(not(feature = "std"), allow(dead_code))]
+
+#[cfg(feature = "doh")]
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{Ipv4Addr, Ipv6Addr};
+#[cfg(feature = "std")]
+use std::net::{TcpListener, UdpSocket};
+#[cfg(feature = "std")]
+use std::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(feature = "std")]
+use std::sync::Arc;
+#[cfg(feature = "std")]
+use std::thread;
+
+use crate::RecordType;
+
+/// How many ports [`bind_servers`] tries before giving up, so a busy host fails the test instead of hanging it.
+#[cfg(feature = "std")]
+const BIND_ATTEMPTS: usize = 100;
+
+/// Bind one UDP socket for each of `127.0.0.1`, `127.0.0.2`, ... sharing the same port,
+/// because the resolver talks to every name server with the same port.
+#[cfg(feature = "std")]
+pub(crate) fn bind_servers(count: u8) -> (u16, Vec<UdpSocket>) {
+    for _ in 0..BIND_ATTEMPTS {
+        let first = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let port = first.local_addr().unwrap().port();
+        let mut sockets = vec![first];
+
+        for i in 2..=count {
+            match UdpSocket::bind((Ipv4Addr::new(127, 0, 0, i), port)) {
+                Ok(socket) => sockets.push(socket),
+                Err(_) => break,
+            }
+        }
+
+        // Retry with another port if it's taken on any of the addresses.
+        if sockets.len() == count as usize {
+            return (port, sockets);
+        }
+    }
+
+    panic!("can't find a port free on {count} loopback addresses after {BIND_ATTEMPTS} attempts");
+}
+
+/// Answer every query the socket receives with `handler` in the background.
+/// Returns the number of queries received so far.
+#[cfg(feature = "std")]
+pub(crate) fn serve<F>(socket: UdpSocket, handler: F) -> Arc<AtomicUsize>
+where
+    F: Fn(&[u8]) -> Option<Vec<u8>> + Send + 'static,
+{
+    let received = Arc::new(AtomicUsize::new(0));
+    let counter = received.clone();
+
+    thread::spawn(move || {
+        let mut buf = [0; 4096];
+        while let Ok((len, peer)) = socket.recv_from(&mut buf) {
+            counter.fetch_add(1, Ordering::SeqCst);
+            if let Some(response) = handler(&buf[..len]) {
+                socket.send_to(&response, peer).unwrap();
+            }
+        }
+    });
+
+    received
+}
+
+/// Answer every length prefixed query over TCP with `handler` in the background.
+/// Returns the port and the number of queries received so far.
+#[cfg(feature = "std")]
+pub(crate) fn serve_tcp<F>(handler: F) -> (u16, Arc<AtomicUsize>)
+where
+    F: Fn(&[u8]) -> Vec<u8> + Send + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let received = Arc::new(AtomicUsize::new(0));
+    let counter = received.clone();
+
+    thread::spawn(move || {
+        for mut stream in listener.incoming().map_while(Result::ok) {
+            let query = crate::transport::read_message(&mut stream).unwrap();
+            counter.fetch_add(1, Ordering::SeqCst);
+            crate::transport::write_message(&mut stream, &handler(&query)).unwrap();
+        }
+    });
+
+    (port, received)
+}
+
+/// Answer every length prefixed query over TCP with `handler` in the background,
+/// keeping each connection open for more queries until the client closes it.
+/// Returns the port and the number of connections accepted so far.
+#[cfg(feature = "std")]
+pub(crate) fn serve_tcp_connections<F>(handler: F) -> (u16, Arc<AtomicUsize>)
+where
+    F: Fn(&[u8]) -> Vec<u8> + Send + Sync + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let accepted = Arc::new(AtomicUsize::new(0));
+    let counter = accepted.clone();
+    let handler = Arc::new(handler);
+
+    thread::spawn(move || {
+        for mut stream in listener.incoming().map_while(Result::ok) {
+            counter.fetch_add(1, Ordering::SeqCst);
+            let handler = handler.clone();
+            thread::spawn(move || {
+                while let Ok(query) = crate::transport::read_message(&mut stream) {
+                    crate::transport::write_message(&mut stream, &handler(&query)).unwrap();
+                }
+            });
+        }
+    });
+
+    (port, accepted)
+}
+
+/// Answer every length prefixed query over TCP with the messages `handler` returns, one after another,
+/// in the background. Returns the port.
+#[cfg(feature = "std")]
+pub(crate) fn serve_tcp_stream<F>(handler: F) -> u16
+where
+    F: Fn(&[u8]) -> Vec<Vec<u8>> + Send + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    thread::spawn(move || {
+        for mut stream in listener.incoming().map_while(Result::ok) {
+            let query = crate::transport::read_message(&mut stream).unwrap();
+            for message in handler(&query) {
+                crate::transport::write_message(&mut stream, &message).unwrap();
+            }
+        }
+    });
+
+    port
+}
+
+/// Answer every DNS over HTTPS POST request with `handler` in the background.
+/// `handler` returns the HTTP status and the body. Returns the URL of the endpoint.
+#[cfg(feature = "doh")]
+pub(crate) fn serve_http<F>(handler: F) -> String
+where
+    F: Fn(&[u8]) -> (u16, Vec<u8>) + Send + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/dns-query", listener.local_addr().unwrap());
+
+    thread::spawn(move || {
+        for mut stream in listener.incoming().map_while(Result::ok) {
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut content_length = 0;
+            let mut content_type = String::new();
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                let line = line.trim_end();
+                if line.is_empty() {
+                    break;
+                }
+                if let Some((name, value)) = line.split_once(':') {
+                    match name.to_ascii_lowercase().as_str() {
+                        "content-length" => content_length = value.trim().parse().unwrap(),
+                        "content-type" => content_type = value.trim().to_string(),
+                        _ => {}
+                    }
+                }
+            }
+            assert_eq!(content_type, "application/dns-message");
+
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).unwrap();
+
+            let (status, response) = handler(&body);
+            let head = format!(
+                "HTTP/1.1 {status} Status\r\ncontent-type: application/dns-message\r\ncontent-length: {}\r\nconnection: close\r\n\r\n",
+                response.len()
+            );
+            stream.write_all(head.as_bytes()).unwrap();
+            stream.write_all(&response).unwrap();
+        }
+    });
+
+    url
+}
+
+/// Encode a domain without compression, e.g., "blog.wtcx.dev" to "4blog4wtcx3dev0".
+pub(crate) fn encode(domain: &str) -> Vec<u8> {
+    let mut name = vec![];
+    for label in domain.split('.').filter(|label| !label.is_empty()) {
+        name.push(label.len() as u8);
+        name.extend_from_slice(label.as_bytes());
+    }
+    name.push(0);
+    name
+}
+
+/// Build a resource record in the IN class.
+pub(crate) fn record(name: &str, r_type: RecordType, ttl: u32, r_data: &[u8]) -> Vec<u8> {
+    let mut rr = encode(name);
+    rr.extend_from_slice(&r_type.to_u16().to_be_bytes());
+    rr.extend_from_slice(&1u16.to_be_bytes());
+    rr.extend_from_slice(&ttl.to_be_bytes());
+    rr.extend_from_slice(&(r_data.len() as u16).to_be_bytes());
+    rr.extend_from_slice(r_data);
+    rr
+}
+
+pub(crate) fn a_record(name: &str, ip: Ipv4Addr) -> Vec<u8> {
+    record(name, RecordType::A, 300, &ip.octets())
+}
+
+pub(crate) fn aaaa_record(name: &str, ip: Ipv6Addr) -> Vec<u8> {
+    record(name, RecordType::AAAA, 300, &ip.octets())
+}
+
+pub(crate) fn ns_record(zone: &str, name_server: &str) -> Vec<u8> {
+    record(zone, RecordType::NS, 300, &encode(name_server))
+}
+
+/// Build an SOA record of `zone` with the given `MINIMUM`.
+pub(crate) fn soa_record(zone: &str, minimum: u32) -> Vec<u8> {
+    let mut r_data = encode(&format!("ns.{zone}"));
+    r_data.extend(encode(&format!("hostmaster.{zone}")));
+    for field in [1, 7200, 3600, 1209600, minimum] {
+        r_data.extend_from_slice(&field.to_be_bytes());
+    }
+    record(zone, RecordType::SOA, 3600, &r_data)
+}
+
+/// Read the domain in the question of a query.
+pub(crate) fn question_name(query: &[u8]) -> String {
+    let mut labels = vec![];
+    let mut pos = 12;
+    while query[pos] != 0 {
+        let len = query[pos] as usize;
+        labels.push(String::from_utf8_lossy(&query[pos + 1..pos + 1 + len]).into_owned());
+        pos += len + 1;
+    }
+    labels.join(".")
+}
+
+/// Read the type in the question of a query.
+pub(crate) fn question_type(query: &[u8]) -> RecordType {
+    let mut pos = 12;
+    while query[pos] != 0 {
+        pos += query[pos] as usize + 1;
+    }
+    RecordType::try_from(u16::from_be_bytes([query[pos + 1], query[pos + 2]])).unwrap()
+}
+
+/// Build a response to `query` with the given RCODE and sections.
+/// The question is copied from the query.
+pub(crate) fn response(
+    query: &[u8],
+    rcode: u16,
+    answers: &[Vec<u8>],
+    authorities: &[Vec<u8>],
+    additionals: &[Vec<u8>],
+) -> Vec<u8> {
+    // The question ends with QTYPE and QCLASS after the QNAME.
+    let mut question_end = 12;
+    while query[question_end] != 0 {
+        question_end += query[question_end] as usize + 1;
+    }
+    question_end += 5;
+
+    let mut buf = query[0..2].to_vec();
+    // QR = 1, RD copied from the query
+    let flags = 0x8000 | (u16::from_be_bytes([query[2], query[3]]) & 0x0100) | rcode;
+    buf.extend_from_slice(&flags.to_be_bytes());
+    buf.extend_from_slice(&1u16.to_be_bytes());
+    for section in [answers, authorities, additionals] {
+        buf.extend_from_slice(&(section.len() as u16).to_be_bytes());
+    }
+    buf.extend_from_slice(&query[12..question_end]);
+    for rr in answers.iter().chain(authorities).chain(additionals) {
+        buf.extend_from_slice(rr);
+    }
+
+    buf
+}
@@ -0,0 +1,76 @@
+//! Transports other than plain UDP, which carry DNS messages over streams or HTTPS.
+
+use std::io::{Read, Write};
+
+use crate::Error;
+
+#[cfg(feature = "doh")]
+pub(crate) mod doh;
+#[cfg(feature = "dot")]
+pub(crate) mod dot;
+pub(crate) mod pool;
+pub(crate) mod tcp;
+
+// 4.2.2. TCP usage
+//
+// The message is prefixed with a two byte length field which gives the message length, excluding the two byte length field.
+//
+/// Write a message to a stream with its length prefixed.
+///
+/// See [RFC 1035, 4.2.2. TCP usage](https://www.rfc-editor.org/rfc/inline-errata/rfc1035.html).
+pub(crate) fn write_message<W: Write>(stream: &mut W, message: &[u8]) -> Result<(), Error> {
+    stream
+        .write_all(&frame(message)?)
+        .map_err(Error::NetworkError)?;
+    stream.flush().map_err(Error::NetworkError)
+}
+
+/// Prefix a message with its length.
+fn frame(message: &[u8]) -> Result<Vec<u8>, Error> {
+    let len = u16::try_from(message.len())
+        .map_err(|_| Error::ResolverError("message is too long to send".into()))?;
+
+    let mut framed = Vec::with_capacity(message.len() + 2);
+    framed.extend_from_slice(&len.to_be_bytes());
+    framed.extend_from_slice(message);
+
+    Ok(framed)
+}
+
+/// Read a length prefixed message from a stream.
+pub(crate) fn read_message<R: Read>(stream: &mut R) -> Result<Vec<u8>, Error> {
+    let mut len = [0; 2];
+    stream.read_exact(&mut len).map_err(Error::NetworkError)?;
+
+    let mut message = vec![0; u16::from_be_bytes(len) as usize];
+    stream
+        .read_exact(&mut message)
+        .map_err(Error::NetworkError)?;
+
+    Ok(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_length_prefixed_message() {
+        let mut stream = vec![];
+        write_message(&mut stream, b"\x12\x34hello").unwrap();
+
+        assert_eq!(stream, b"\x00\x07\x12\x34hello");
+        assert_eq!(
+            read_message(&mut Cursor::new(stream)).unwrap(),
+            b"\x12\x34hello"
+        );
+    }
+
+    #[test]
+    fn test_truncated_message() {
+        let stream = b"\x00\x07\x12\x34".to_vec();
+
+        assert!(read_message(&mut Cursor::new(stream)).is_err());
+    }
+}
@@ -0,0 +1,42 @@
+use std::io;
+use std::time::Duration;
+
+use ureq::Agent;
+
+use crate::Error;
+
+/// Media type of DNS messages in wire format, see [RFC 8484, 6. Definition of the "application/dns-message" Media Type](https://www.rfc-editor.org/rfc/rfc8484.html).
+const DNS_MESSAGE: &str = "application/dns-message";
+
+/// Send a query to a DNS over HTTPS endpoint, e.g., `https://cloudflare-dns.com/dns-query`, and return the raw response.
+///
+/// The query is sent in the body of a POST request.
+///
+/// See [RFC 8484, 4.1. The HTTP Request](https://www.rfc-editor.org/rfc/rfc8484.html).
+pub(crate) fn exchange(endpoint: &str, query: &[u8], timeout: Duration) -> Result<Vec<u8>, Error> {
+    let agent: Agent = Agent::config_builder()
+        .timeout_global(Some(timeout))
+        .http_status_as_error(false)
+        .build()
+        .into();
+
+    let mut response = agent
+        .post(endpoint)
+        .header("content-type", DNS_MESSAGE)
+        .header("accept", DNS_MESSAGE)
+        .send(query)
+        .map_err(|err| Error::NetworkError(io::Error::other(err)))?;
+
+    // A DNS error such as NXDOMAIN still comes with 200, anything else means the message didn't make it.
+    let status = response.status();
+    if status != 200 {
+        return Err(Error::NetworkError(io::Error::other(format!(
+            "DoH server responded with HTTP {status}"
+        ))));
+    }
+
+    response
+        .body_mut()
+        .read_to_vec()
+        .map_err(|err| Error::NetworkError(io::Error::other(err)))
+}
@@ -0,0 +1,58 @@
+use std::net::{SocketAddr, TcpStream};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+use rustls::pki_types::ServerName;
+use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+
+use crate::Error;
+
+/// Port for DNS over TLS, see [RFC 7858, 3.1. Session Initiation](https://www.rfc-editor.org/rfc/rfc7858.html).
+pub(crate) const DOT_PORT: u16 = 853;
+
+/// The TLS config is the same for every connection, so build it once.
+fn tls_config() -> Result<Arc<ClientConfig>, Error> {
+    static CONFIG: OnceLock<Arc<ClientConfig>> = OnceLock::new();
+
+    if let Some(config) = CONFIG.get() {
+        return Ok(config.clone());
+    }
+
+    let roots = RootCertStore {
+        roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+    };
+    let config =
+        ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .map_err(|err| Error::ResolverError(format!("can't set up TLS: {err}")))?
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+
+    Ok(CONFIG.get_or_init(|| Arc::new(config)).clone())
+}
+
+/// A TLS session over TCP.
+pub(crate) type TlsStream = StreamOwned<ClientConnection, TcpStream>;
+
+/// Connect to a DNS over TLS server. `hostname` is used to verify the certificate of the server.
+///
+/// See [RFC 7858](https://www.rfc-editor.org/rfc/rfc7858.html).
+pub(crate) fn connect(
+    server: &SocketAddr,
+    hostname: &str,
+    timeout: Duration,
+) -> Result<TlsStream, Error> {
+    let server_name = ServerName::try_from(hostname.to_string())
+        .map_err(|_| Error::ResolverError(format!("invalid DoT server name: {hostname}")))?;
+    let connection = ClientConnection::new(tls_config()?, server_name)
+        .map_err(|err| Error::ResolverError(format!("can't set up TLS: {err}")))?;
+
+    let tcp = TcpStream::connect_timeout(server, timeout).map_err(Error::NetworkError)?;
+    tcp.set_read_timeout(Some(timeout))
+        .map_err(Error::NetworkError)?;
+    tcp.set_write_timeout(Some(timeout))
+        .map_err(Error::NetworkError)?;
+
+    // The handshake is done when the stream is first written to.
+    Ok(StreamOwned::new(connection, tcp))
+}
@@ -0,0 +1,188 @@
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use log::{debug, trace};
+
+use crate::Error;
+
+/// Idle stream connections kept open for later queries to the same server, which saves the handshakes.
+///
+/// See [RFC 7766, 6.2.1. Connection Reuse](https://www.rfc-editor.org/rfc/rfc7766.html).
+#[derive(Debug)]
+pub(crate) struct Pool<S> {
+    /// Idle connections of each server and since when they are idle.
+    idle: Mutex<HashMap<SocketAddr, Vec<(S, Instant)>>>,
+    /// How many idle connections can be kept across all servers. 0 disables the pool.
+    max_idle: usize,
+    /// How long a connection can stay idle before it's closed.
+    idle_timeout: Duration,
+}
+
+impl<S: Read + Write> Pool<S> {
+    pub(crate) fn new(max_idle: usize, idle_timeout: Duration) -> Self {
+        Self {
+            idle: Mutex::new(HashMap::new()),
+            max_idle,
+            idle_timeout,
+        }
+    }
+
+    /// Send a query over an idle connection to the server, or a new one from `connect` if there is none,
+    /// and return the raw response. The connection is kept for later queries if it's still usable.
+    pub(crate) fn exchange<F>(
+        &self,
+        server: &SocketAddr,
+        query: &[u8],
+        connect: F,
+    ) -> Result<Vec<u8>, Error>
+    where
+        F: FnOnce() -> Result<S, Error>,
+    {
+        // The server might have closed an idle connection in the meantime, which is fine to retry.
+        if let Some(mut stream) = self.take(server) {
+            match Self::round_trip(&mut stream, query) {
+                Ok(response) => {
+                    trace!("reused idle connection to {server}");
+                    self.put(*server, stream);
+                    return Ok(response);
+                }
+                Err(err) => debug!("idle connection to {server} is no longer usable: {err}"),
+            }
+        }
+
+        let mut stream = connect()?;
+        let response = Self::round_trip(&mut stream, query)?;
+        self.put(*server, stream);
+
+        Ok(response)
+    }
+
+    /// Take the most recently used idle connection to the server, closing the ones idle for too long.
+    fn take(&self, server: &SocketAddr) -> Option<S> {
+        let mut idle = self.idle.lock().unwrap();
+        let now = Instant::now();
+        for streams in idle.values_mut() {
+            streams.retain(|(_, since)| now.duration_since(*since) < self.idle_timeout);
+        }
+        idle.retain(|_, streams| !streams.is_empty());
+
+        idle.get_mut(server)?.pop().map(|(stream, _)| stream)
+    }
+
+    /// Keep a connection for later queries, unless the pool is full.
+    fn put(&self, server: SocketAddr, stream: S) {
+        let mut idle = self.idle.lock().unwrap();
+
+        if idle.values().map(Vec::len).sum::<usize>() < self.max_idle {
+            idle.entry(server)
+                .or_default()
+                .push((stream, Instant::now()));
+        }
+    }
+
+    /// Send the query and read responses until the one with its ID, skipping ones nobody asked for.
+    fn round_trip(stream: &mut S, query: &[u8]) -> Result<Vec<u8>, Error> {
+        let id = Self::id(query)
+            .ok_or_else(|| Error::ResolverError("query is too short to have an ID".into()))?;
+        super::write_message(stream, query)?;
+
+        loop {
+            let response = super::read_message(stream)?;
+            match Self::id(&response) {
+                Some(response_id) if response_id == id => return Ok(response),
+                _ => trace!("ignored response with mismatched ID"),
+            }
+        }
+    }
+
+    fn id(message: &[u8]) -> Option<u16> {
+        Some(u16::from_be_bytes([*message.first()?, *message.get(1)?]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// A stream which plays back `input` and records what is written to it.
+    struct FakeStream {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Read for FakeStream {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for FakeStream {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn fake_stream(responses: &[&[u8]]) -> FakeStream {
+        let mut input = vec![];
+        for response in responses {
+            super::super::write_message(&mut input, response).unwrap();
+        }
+
+        FakeStream {
+            input: Cursor::new(input),
+            output: vec![],
+        }
+    }
+
+    #[test]
+    fn test_stray_response_ignored() {
+        let pool = Pool::new(0, Duration::from_secs(10));
+        let server = SocketAddr::from(([127, 0, 0, 1], 53));
+        // A response nobody asked for comes first.
+        let stream = fake_stream(&[b"\x00\x03stray", b"\x00\x01first"]);
+
+        let response = pool.exchange(&server, b"\x00\x01", || Ok(stream)).unwrap();
+
+        assert_eq!(response, b"\x00\x01first");
+    }
+
+    #[test]
+    fn test_idle_connection_reused() {
+        let pool = Pool::new(1, Duration::from_secs(10));
+        let server = SocketAddr::from(([127, 0, 0, 1], 53));
+        let stream = fake_stream(&[b"\x00\x01first", b"\x00\x02second"]);
+
+        pool.exchange(&server, b"\x00\x01", || Ok(stream)).unwrap();
+        let response = pool
+            .exchange(&server, b"\x00\x02", || {
+                panic!("expect the idle connection")
+            })
+            .unwrap();
+
+        assert_eq!(response, b"\x00\x02second");
+    }
+
+    #[test]
+    fn test_idle_connection_expired() {
+        let pool = Pool::new(1, Duration::ZERO);
+        let server = SocketAddr::from(([127, 0, 0, 1], 53));
+
+        pool.exchange(&server, b"\x00\x01", || {
+            Ok(fake_stream(&[b"\x00\x01first"]))
+        })
+        .unwrap();
+        let response = pool
+            .exchange(&server, b"\x00\x02", || Ok(fake_stream(&[b"\x00\x02new"])))
+            .unwrap();
+
+        assert_eq!(response, b"\x00\x02new");
+    }
+}
@@ -0,0 +1,61 @@
+use std::net::{SocketAddr, TcpStream};
+use std::time::Duration;
+
+use crate::Error;
+
+/// Send a query to a name server over TCP and leave the stream open,
+/// e.g., for a zone transfer where the response spans several messages.
+pub(crate) fn send(
+    server: &SocketAddr,
+    query: &[u8],
+    timeout: Duration,
+) -> Result<TcpStream, Error> {
+    let mut stream = connect(server, timeout)?;
+
+    super::write_message(&mut stream, query)?;
+
+    Ok(stream)
+}
+
+/// Connect to a name server over TCP, with `timeout` applied to every read and write.
+///
+/// See [RFC 7766, 5. Transport Protocol Selection](https://www.rfc-editor.org/rfc/rfc7766.html).
+pub(crate) fn connect(server: &SocketAddr, timeout: Duration) -> Result<TcpStream, Error> {
+    let stream = TcpStream::connect_timeout(server, timeout).map_err(Error::NetworkError)?;
+    stream
+        .set_read_timeout(Some(timeout))
+        .map_err(Error::NetworkError)?;
+    stream
+        .set_write_timeout(Some(timeout))
+        .map_err(Error::NetworkError)?;
+
+    Ok(stream)
+}
+
+/// Send a query to a name server over a new TCP connection and read the response, without blocking the thread.
+#[cfg(feature = "tokio")]
+pub(crate) async fn exchange_async(server: &SocketAddr, query: &[u8]) -> Result<Vec<u8>, Error> {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let mut stream = tokio::net::TcpStream::connect(server)
+        .await
+        .map_err(Error::NetworkError)?;
+    stream
+        .write_all(&super::frame(query)?)
+        .await
+        .map_err(Error::NetworkError)?;
+
+    let mut len = [0; 2];
+    stream
+        .read_exact(&mut len)
+        .await
+        .map_err(Error::NetworkError)?;
+
+    let mut response = vec![0; u16::from_be_bytes(len) as usize];
+    stream
+        .read_exact(&mut response)
+        .await
+        .map_err(Error::NetworkError)?;
+
+    Ok(response)
+}
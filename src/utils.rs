@@ -1,7 +1,18 @@
-use std::collections::HashSet;
+use alloc::borrow::Cow;
+use alloc::collections::{BTreeMap, BTreeSet};
+#[cfg(feature = "std")]
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use core::net::IpAddr;
 
 use crate::error::Error;
 
+/// How many compression pointers a domain can follow, so a long chain of pointers can't keep the parser busy.
+const MAX_COMPRESSION_POINTERS: usize = 128;
+
 /// Parse domain name with various length of byte array. Returns the domain and where the domain ends.
 ///
 /// Thanks to ChatGPT
@@ -9,8 +20,10 @@ pub(crate) fn parse_domain(buf: &[u8], start_pos: usize) -> Result<(String, usiz
     let mut domain = String::new();
     let mut stack = vec![start_pos];
     let mut set_end = false;
-    let mut end = 0;
-    let mut visited = HashSet::new();
+    // The root name is a single zero byte.
+    let mut end = start_pos + 1;
+    let mut visited = BTreeSet::new();
+    let mut pointers = 0;
 
     while let Some(mut curr_pos) = stack.pop() {
         if visited.contains(&curr_pos) {
@@ -19,7 +32,11 @@ pub(crate) fn parse_domain(buf: &[u8], start_pos: usize) -> Result<(String, usiz
         visited.insert(curr_pos);
 
         // 0 byte indicates the end of domain.
-        while buf[curr_pos] != 0 {
+        while *buf
+            .get(curr_pos)
+            .ok_or_else(|| Error::ResolverError("QNAME out of bounds".into()))?
+            != 0
+        {
             // There are two kinds of domain representation.
             // One is uncompressed and contains every label. there will be a byte indicating the lenth and characters followed by the byte.
             // The QNAME format will look like: "4blog4wtcx3dev0"
@@ -54,6 +71,11 @@ pub(crate) fn parse_domain(buf: &[u8], start_pos: usize) -> Result<(String, usiz
                     return Err(Error::ResolverError("offset is out of bounds".into()));
                 }
 
+                pointers += 1;
+                if pointers > MAX_COMPRESSION_POINTERS {
+                    return Err(Error::ResolverError("too many compression pointers".into()));
+                }
+
                 stack.push(offset);
 
                 if !set_end {
@@ -68,7 +90,7 @@ pub(crate) fn parse_domain(buf: &[u8], start_pos: usize) -> Result<(String, usiz
                     return Err(Error::ResolverError("domain is out of bound".into()));
                 }
 
-                let label = std::str::from_utf8(&buf[curr_pos..curr_pos + len])
+                let label = core::str::from_utf8(&buf[curr_pos..curr_pos + len])
                     .map_err(|_| Error::ResolverError("domain contains invalid characters".into()))?;
                 domain.push_str(label);
 
@@ -88,8 +110,239 @@ pub(crate) fn parse_domain(buf: &[u8], start_pos: usize) -> Result<(String, usiz
     Ok((domain, end))
 }
 
+/// Parse a domain which must not be compressed, e.g., the signer name of RRSIG. Returns the domain and where it ends.
+///
+/// See [RFC 4034, 3.1.7. The Signer's Name Field](https://www.rfc-editor.org/rfc/rfc4034.html).
+pub(crate) fn parse_uncompressed_domain(
+    buf: &[u8],
+    start_pos: usize,
+) -> Result<(String, usize), Error> {
+    let mut labels = vec![];
+    let mut pos = start_pos;
+
+    loop {
+        let len = *buf
+            .get(pos)
+            .ok_or_else(|| Error::ResolverError("domain is out of bound".into()))?
+            as usize;
+        if len == 0 {
+            return Ok((labels.join("."), pos + 1));
+        }
+        // Labels are at most 63 characters long, so the first 2 bits are only set by pointers.
+        if len & 0xC0 != 0 {
+            return Err(Error::ResolverError("domain must not be compressed".into()));
+        }
+
+        let label = buf
+            .get(pos + 1..pos + 1 + len)
+            .ok_or_else(|| Error::ResolverError("domain is out of bound".into()))?;
+        let label = core::str::from_utf8(label)
+            .map_err(|_| Error::ResolverError("domain contains invalid characters".into()))?;
+        labels.push(label);
+        pos += 1 + len;
+    }
+}
+
+/// Encode a domain without compression, e.g., "blog.wtcx.dev" to "4blog4wtcx3dev0".
+///
+/// See [RFC 1035, 3.1. Name space definitions](https://www.rfc-editor.org/rfc/inline-errata/rfc1035.html).
+pub(crate) fn encode_domain(domain: &str) -> Vec<u8> {
+    let mut name = vec![];
+
+    // The root has no label but the null one.
+    if !domain.is_empty() {
+        for label in domain.split('.') {
+            name.push(label.len() as u8);
+            name.extend_from_slice(label.as_bytes());
+        }
+    }
+
+    // Every domain ends with a null label.
+    // > ...Since every domain name ends with the null label of the root, a domain name is terminated by a length byte of zero.
+    name.push(0);
+
+    name
+}
+
+/// Where names are written in a message, so the same names, or the same suffixes of names, written later can point
+/// to them instead of being written again.
+///
+/// See [RFC 1035, 4.1.4. Message compression](https://www.rfc-editor.org/rfc/inline-errata/rfc1035.html).
+#[derive(Default)]
+pub(crate) struct NameCompressor {
+    offsets: BTreeMap<String, u16>,
+}
+
+impl NameCompressor {
+    /// Append the domain to `buf`, which is the message written so far, ending with a pointer to the longest suffix
+    /// written before. Suffixes are matched exactly, so the casing of each name is kept.
+    pub(crate) fn write(&mut self, buf: &mut Vec<u8>, domain: &str) {
+        let mut rest = domain;
+
+        while !rest.is_empty() {
+            if let Some(offset) = self.offsets.get(rest) {
+                buf.extend_from_slice(&(0xC000 | offset).to_be_bytes());
+                return;
+            }
+
+            // A pointer only has 14 bits for the offset, so names further than that can't be pointed to.
+            if let Ok(offset @ 0..=0x3FFF) = u16::try_from(buf.len()) {
+                self.offsets.insert(rest.into(), offset);
+            }
+
+            let (label, next) = rest.split_once('.').unwrap_or((rest, ""));
+            buf.push(label.len() as u8);
+            buf.extend_from_slice(label.as_bytes());
+            rest = next;
+        }
+
+        buf.push(0);
+    }
+}
+
+/// Encode a domain to the wire format without compression, e.g., to build RDATA containing names.
+/// The trailing dot of an FQDN is optional, and the root is either empty or a single dot.
+///
+/// It fails with [`Error::InvalidHostname`] if the domain can't be represented,
+/// i.e., it has an empty label in the middle, a label longer than 63 bytes, or more than 255 bytes in total.
+///
+/// ```
+/// use tiny_resolver_rs::encode_name;
+///
+/// assert_eq!(encode_name("example.com").unwrap(), b"\x07example\x03com\x00");
+/// assert_eq!(encode_name(".").unwrap(), [0]);
+/// ```
+pub fn encode_name(domain: &str) -> Result<Vec<u8>, Error> {
+    let domain = match domain {
+        "." => "",
+        _ => domain.strip_suffix('.').unwrap_or(domain),
+    };
+
+    let is_invalid = |label: &str| label.is_empty() || label.len() > 63;
+    if !domain.is_empty() && domain.split('.').any(is_invalid) {
+        return Err(Error::InvalidHostname);
+    }
+
+    let name = encode_domain(domain);
+    if name.len() > 255 {
+        return Err(Error::InvalidHostname);
+    }
+
+    Ok(name)
+}
+
+/// Parse a `<character-string>`, which is a length byte followed by that many characters.
+/// Returns the string and where it ends.
+///
+/// See [RFC 1035, 3.3. Standard RRs](https://www.rfc-editor.org/rfc/inline-errata/rfc1035.html).
+pub(crate) fn parse_character_string(
+    buf: &[u8],
+    start_pos: usize,
+) -> Result<(String, usize), Error> {
+    if start_pos >= buf.len() {
+        return Err(Error::ResolverError(
+            "character-string is out of bound".into(),
+        ));
+    }
+
+    let len = buf[start_pos] as usize;
+    let end = start_pos + 1 + len;
+
+    if end > buf.len() {
+        return Err(Error::ResolverError(
+            "character-string is out of bound".into(),
+        ));
+    }
+
+    let text = String::from_utf8_lossy(&buf[start_pos + 1..end]).into_owned();
+
+    Ok((text, end))
+}
+
+/// Build the domain used for reverse lookup of an IP address.
+///
+/// IPv4 addresses are reversed by octets under `in-addr.arpa`, e.g., `8.8.4.4` becomes `4.4.8.8.in-addr.arpa`.
+/// IPv6 addresses are reversed by nibbles under `ip6.arpa`.
+///
+/// See [RFC 1035, 3.5. IN-ADDR.ARPA domain](https://www.rfc-editor.org/rfc/inline-errata/rfc1035.html)
+/// and [RFC 3596, 2.5 IP6.ARPA Domain](https://www.rfc-editor.org/rfc/rfc3596.html).
+#[cfg(feature = "std")]
+pub(crate) fn reverse_domain(ip: &IpAddr) -> String {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, c, d] = ip.octets();
+            format!("{d}.{c}.{b}.{a}.in-addr.arpa")
+        }
+        IpAddr::V6(ip) => {
+            let mut domain = String::with_capacity(72);
+            for octet in ip.octets().iter().rev() {
+                domain.push_str(&format!("{:x}.{:x}.", octet & 0x0F, octet >> 4));
+            }
+            domain.push_str("ip6.arpa");
+            domain
+        }
+    }
+}
+
+/// Convert Unicode labels to A-labels (punycode), e.g., "münchen.de" to "xn--mnchen-3ya.de".
+/// ASCII labels, including A-labels already encoded, are kept as they are.
+///
+/// See [RFC 5891, 4. Registration Protocol](https://www.rfc-editor.org/rfc/rfc5891.html).
+#[cfg(feature = "idna")]
+pub(crate) fn to_ascii(domain: &str) -> Result<Cow<'_, str>, Error> {
+    if domain.is_ascii() {
+        return Ok(Cow::Borrowed(domain));
+    }
+
+    let labels = domain
+        .split('.')
+        .map(|label| match label.is_ascii() {
+            true => Ok(label.to_string()),
+            false => idna::domain_to_ascii(label).map_err(|_| Error::InvalidHostname),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(Cow::Owned(labels.join(".")))
+}
+
+/// Unicode labels are only supported with the `idna` feature, and they are rejected by [`validate_domain`] without it.
+#[cfg(not(feature = "idna"))]
+pub(crate) fn to_ascii(domain: &str) -> Result<Cow<'_, str>, Error> {
+    Ok(Cow::Borrowed(domain))
+}
+
+/// Whether the name is the zone itself or under it, ignoring case. Every name is under the root zone `""`.
+#[cfg(feature = "std")]
+pub(crate) fn is_subdomain(name: &str, zone: &str) -> bool {
+    let (name, zone) = (name.trim_end_matches('.'), zone.trim_end_matches('.'));
+    if zone.is_empty() || name.eq_ignore_ascii_case(zone) {
+        return true;
+    }
+
+    name.len() > zone.len()
+        && name.as_bytes()[name.len() - zone.len() - 1] == b'.'
+        && name[name.len() - zone.len()..].eq_ignore_ascii_case(zone)
+}
+
+/// How many labels a domain can have at most. Each label takes at least 2 bytes,
+/// so no more than 127 of them fit in 255 bytes along with the ending zero byte.
+pub(crate) const MAX_LABELS: usize = 127;
+
 /// Validates whether a domain is eligible for query.
 pub(crate) fn validate_domain(domain: &str) -> Result<(), Error> {
+    validate_domain_with_max_labels(domain, MAX_LABELS)
+}
+
+/// Same as [`validate_domain`], but the domain can't have more than `max_labels` labels.
+pub(crate) fn validate_domain_with_max_labels(
+    domain: &str,
+    max_labels: usize,
+) -> Result<(), Error> {
+    // The root, e.g., to look up the root name servers.
+    if domain == "." {
+        return Ok(());
+    }
+
     // Handle trailing dot of FQDN
     let domain = domain.trim_end_matches('.');
 
@@ -100,7 +353,7 @@ pub(crate) fn validate_domain(domain: &str) -> Result<(), Error> {
     // We will just init length with the ending zero byte here.
     let mut total_len = 1;
 
-    if domain.is_empty() {
+    if domain.is_empty() || domain.split('.').count() > max_labels {
         return Err(Error::InvalidHostname);
     }
 
@@ -108,10 +361,17 @@ pub(crate) fn validate_domain(domain: &str) -> Result<(), Error> {
         if label.is_empty() || label.len() > 63 {
             return Err(Error::InvalidHostname);
         }
-        if label.starts_with('-') || label.ends_with('-') {
+
+        // Names of services like "_sip._tcp.example.com" and "_dmarc.example.com" have labels starting with an underscore,
+        // see [RFC 8552](https://www.rfc-editor.org/rfc/rfc8552.html). Underscores are still not allowed anywhere else.
+        let name = label.strip_prefix('_').unwrap_or(label);
+        if name.is_empty() {
+            return Err(Error::InvalidHostname);
+        }
+        if name.starts_with('-') || name.ends_with('-') {
             return Err(Error::InvalidHostname);
         }
-        if !label.chars().all(|c| c.is_alphanumeric() || c == '-') {
+        if !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
             return Err(Error::InvalidHostname);
         }
 
@@ -131,11 +391,199 @@ pub(crate) fn validate_domain(domain: &str) -> Result<(), Error> {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_parse_domain_with_label_out_of_bound() {
+        // The label claims 10 characters but there are only 3 left.
+        let buf = [10, b'c', b'o', b'm'];
+        assert!(parse_domain(&buf, 0).is_err());
+    }
+
+    #[test]
+    fn test_parse_domain_starts_out_of_bound() {
+        let buf = [3, b'c', b'o', b'm', 0];
+        assert!(matches!(
+            parse_domain(&buf, buf.len()),
+            Err(Error::ResolverError(reason)) if reason == "QNAME out of bounds"
+        ));
+    }
+
+    /// "com" followed by `count` pointers, each pointing to the previous one.
+    fn pointer_chain(count: usize) -> Vec<u8> {
+        let mut buf = vec![3, b'c', b'o', b'm', 0];
+        let mut target = 0;
+        for _ in 0..count {
+            let pos = buf.len();
+            buf.extend_from_slice(&(0xC000 | target as u16).to_be_bytes());
+            target = pos;
+        }
+        buf
+    }
+
+    #[test]
+    fn test_parse_domain_with_pointer_chain() {
+        let buf = pointer_chain(MAX_COMPRESSION_POINTERS);
+        assert_eq!(
+            parse_domain(&buf, buf.len() - 2).unwrap(),
+            ("com".into(), buf.len())
+        );
+
+        let buf = pointer_chain(MAX_COMPRESSION_POINTERS + 1);
+        assert!(matches!(
+            parse_domain(&buf, buf.len() - 2),
+            Err(Error::ResolverError(reason)) if reason == "too many compression pointers"
+        ));
+    }
+
+    #[test]
+    fn test_encode_name() {
+        let name = b"\x07example\x03com\x00";
+
+        assert_eq!(encode_name("example.com").unwrap(), name);
+        assert_eq!(encode_name("example.com.").unwrap(), name);
+        assert_eq!(encode_name("").unwrap(), [0]);
+        assert_eq!(encode_name(".").unwrap(), [0]);
+    }
+
+    #[test]
+    fn test_name_compressor() {
+        let mut names = NameCompressor::default();
+        let mut buf = vec![0; 12];
+
+        names.write(&mut buf, "blog.wtcx.dev");
+        names.write(&mut buf, "ns.wtcx.dev");
+        names.write(&mut buf, "blog.wtcx.dev");
+
+        let mut expected = vec![0; 12];
+        expected.extend(encode_domain("blog.wtcx.dev"));
+        // "wtcx.dev" starts after "4blog"
+        expected.extend_from_slice(&[2, b'n', b's', 0xC0, 17]);
+        expected.extend_from_slice(&[0xC0, 12]);
+        assert_eq!(buf, expected);
+        assert_eq!(parse_domain(&buf, 27).unwrap(), ("ns.wtcx.dev".into(), 32));
+        assert_eq!(
+            parse_domain(&buf, 32).unwrap(),
+            ("blog.wtcx.dev".into(), 34)
+        );
+    }
+
+    #[test]
+    fn test_name_compressor_beyond_pointer_offset() {
+        let mut names = NameCompressor::default();
+        let mut buf = vec![0; 12];
+        names.write(&mut buf, "wtcx.dev");
+        buf.resize(0x4000, 0);
+
+        // Written past the 14 bit offset, it can point back, but can't be pointed to.
+        names.write(&mut buf, "blog.wtcx.dev");
+        let start = buf.len();
+        names.write(&mut buf, "blog.wtcx.dev");
+
+        assert_eq!(buf[start..], [4, b'b', b'l', b'o', b'g', 0xC0, 12]);
+        assert_eq!(
+            parse_domain(&buf, start).unwrap(),
+            ("blog.wtcx.dev".into(), buf.len())
+        );
+    }
+
+    #[test]
+    fn test_encode_invalid_name() {
+        assert_eq!(encode_name("foo..bar"), Err(Error::InvalidHostname));
+        assert_eq!(encode_name(".com"), Err(Error::InvalidHostname));
+        assert_eq!(encode_name(&"a".repeat(64)), Err(Error::InvalidHostname));
+        // 128 labels take 257 bytes.
+        let domain = format!("{}a", "a.".repeat(127));
+        assert_eq!(encode_name(&domain), Err(Error::InvalidHostname));
+    }
+
+    #[test]
+    fn test_parse_root_domain() {
+        let buf = [0, 0, 1];
+        assert_eq!(parse_domain(&buf, 0).unwrap(), (String::new(), 1));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_is_subdomain() {
+        assert!(is_subdomain("blog.wtcx.dev", "wtcx.dev"));
+        assert!(is_subdomain("blog.WTCX.dev.", "wtcx.dev"));
+        assert!(is_subdomain("wtcx.dev", "wtcx.dev"));
+        assert!(is_subdomain("wtcx.dev", ""));
+        assert!(!is_subdomain("blogwtcx.dev", "wtcx.dev"));
+        assert!(!is_subdomain("dev", "wtcx.dev"));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_reverse_domain_ipv4() {
+        let ip = "8.8.8.8".parse().unwrap();
+        assert_eq!(reverse_domain(&ip), "8.8.8.8.in-addr.arpa");
+
+        let ip = "1.2.3.4".parse().unwrap();
+        assert_eq!(reverse_domain(&ip), "4.3.2.1.in-addr.arpa");
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_reverse_domain_ipv6() {
+        let ip = "2001:db8::567:89ab".parse().unwrap();
+        assert_eq!(
+            reverse_domain(&ip),
+            "b.a.9.8.7.6.5.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.ip6.arpa"
+        );
+    }
+
     #[test]
     fn test_empty_domain() {
         assert_eq!(validate_domain(""), Err(Error::InvalidHostname));
     }
 
+    #[test]
+    fn test_root_domain() {
+        assert!(validate_domain(".").is_ok());
+        assert_eq!(validate_domain(".."), Err(Error::InvalidHostname));
+    }
+
+    #[test]
+    fn test_unicode_domain() {
+        assert_eq!(validate_domain("münchen.de"), Err(Error::InvalidHostname));
+    }
+
+    #[cfg(feature = "idna")]
+    #[test]
+    fn test_unicode_domain_to_ascii() {
+        assert_eq!(to_ascii("münchen.de").unwrap(), "xn--mnchen-3ya.de");
+        assert_eq!(
+            to_ascii("WWW.münchen.xn--p1ai").unwrap(),
+            "WWW.xn--mnchen-3ya.xn--p1ai"
+        );
+        assert!(matches!(
+            to_ascii("blog.wtcx.dev").unwrap(),
+            Cow::Borrowed(_)
+        ));
+    }
+
+    #[test]
+    fn test_domain_with_leading_underscore() {
+        assert!(validate_domain("_sip._tcp.example.com").is_ok());
+        assert!(validate_domain("_dmarc.example.com").is_ok());
+    }
+
+    #[test]
+    fn test_domain_with_underscore_in_label() {
+        assert_eq!(
+            validate_domain("a_b.example.com"),
+            Err(Error::InvalidHostname)
+        );
+        assert_eq!(
+            validate_domain("_.example.com"),
+            Err(Error::InvalidHostname)
+        );
+        assert_eq!(
+            validate_domain("__sip.example.com"),
+            Err(Error::InvalidHostname)
+        );
+    }
+
     #[test]
     fn test_hyphen_domain() {
         assert_eq!(validate_domain("-"), Err(Error::InvalidHostname));
@@ -174,6 +622,28 @@ mod tests {
         assert!(validate_domain("aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa.aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa.aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa.aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa").is_ok());
     }
 
+    #[test]
+    fn test_domain_with_short_text_but_long_wire_format() {
+        // 254 characters, but 256 bytes with a length byte for each of the 127 labels and the ending zero.
+        let domain = format!("{}bb", "a.".repeat(126));
+
+        assert_eq!(domain.len(), 254);
+        assert_eq!(validate_domain(&domain), Err(Error::InvalidHostname));
+        assert!(validate_domain(&domain[2..]).is_ok());
+    }
+
+    #[test]
+    fn test_domain_with_too_many_labels() {
+        let domain = format!("{}dev", "a.".repeat(9));
+
+        assert!(validate_domain_with_max_labels(&domain, 10).is_ok());
+        assert!(validate_domain_with_max_labels(&format!("{domain}."), 10).is_ok());
+        assert_eq!(
+            validate_domain_with_max_labels(&format!("a.{domain}"), 10),
+            Err(Error::InvalidHostname)
+        );
+    }
+
     #[test]
     fn test_domain_with_256_bytes_domain() {
         assert!(validate_domain("aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa.aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa.aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa.aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa").is_err());